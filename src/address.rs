@@ -0,0 +1,623 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::NodeId;
+use crate::property::Property;
+
+// The type of a bus, which decides how the addresses of its children are
+// matched with its "ranges", like `struct of_bus` of Linux.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bus {
+    Default,
+    // A bus with 3 address cells, where the first cell holds flags that must
+    // equal the flags of the range
+    DefaultFlags,
+    // A PCI bus, where the first address cell holds the space code
+    Pci,
+}
+
+impl Bus {
+    // Get the type of the bus formed by a node.
+    fn of(tree: &DeviceTree, id: NodeId) -> Bus {
+        let node = tree.node(id);
+        let pci = matches!(
+            node.read_string("device_type"),
+            Ok("pci") | Ok("pciex") | Ok("vci") | Ok("ht")
+        ) || node.base_name() == "pcie";
+        if pci {
+            Bus::Pci
+        } else if Address::cells(tree, id, "#address-cells", 2) == 3 {
+            Bus::DefaultFlags
+        } else {
+            Bus::Default
+        }
+    }
+
+    // Get the numbers of address and size cells of the children of the bus.
+    fn count_cells(&self, tree: &DeviceTree, id: NodeId) -> (usize, usize) {
+        match self {
+            Bus::Pci => (3, 2),
+            _ => (
+                Address::cells(tree, id, "#address-cells", 2),
+                Address::cells(tree, id, "#size-cells", 1),
+            ),
+        }
+    }
+
+    // The number of leading cells of an address that are not the number.
+    fn flag_cells(&self) -> usize {
+        match self {
+            Bus::Default => 0,
+            _ => 1,
+        }
+    }
+
+    // Get the offset of an address in a range, or `None` if the address is
+    // not in the range.
+    fn map(&self, addr: &[u32], range: &[u32], na: usize, ns: usize, pna: usize) -> Option<u64> {
+        let skip = self.flag_cells().min(na);
+        match self {
+            Bus::Default => {}
+            Bus::DefaultFlags => {
+                if addr[0] != range[0] {
+                    return None;
+                }
+            }
+            Bus::Pci => {
+                // The space codes of I/O (1) and memory (2 or 3) must match
+                let class = |cell: u32| match (cell >> 24) & 0x3 {
+                    0 => 0,
+                    1 => 1,
+                    _ => 2,
+                };
+                if class(addr[0]) != class(range[0]) {
+                    return None;
+                }
+            }
+        }
+        let child = Address::read_number(&range[skip..na]);
+        let size = Address::read_number(&range[(na + pna)..(na + pna + ns)]);
+        let addr = Address::read_number(&addr[skip..na]);
+        if addr >= child && addr - child < size {
+            Some(addr - child)
+        } else {
+            None
+        }
+    }
+
+    // Add an offset to the number part of an address.
+    fn translate(&self, addr: &mut [u32], offset: u64) {
+        let skip = self.flag_cells().min(addr.len());
+        let addr = &mut addr[skip..];
+        let number = Address::read_number(addr).wrapping_add(offset);
+        addr.fill(0);
+        let n = addr.len();
+        if n > 1 {
+            addr[n - 2] = (number >> 32) as u32;
+        }
+        if n > 0 {
+            addr[n - 1] = number as u32;
+        }
+    }
+}
+
+/// A range of DMA addresses of the devices on a bus and the CPU physical
+/// addresses they map to, read from "dma-ranges".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DmaRange {
+    /// The CPU physical address of the start of the range
+    pub cpu_start: u64,
+    /// The DMA address of the start of the range
+    pub dma_start: u64,
+    pub size: u64,
+}
+
+pub(crate) struct Address {}
+
+impl Address {
+    // Read a `#*-cells` property of a bus node, or of the closest ancestor
+    // having it, like `of_n_addr_cells` of Linux.
+    pub(crate) fn cells(tree: &DeviceTree, id: NodeId, name: &str, default: usize) -> usize {
+        let mut node = Some(id);
+        while let Some(id) = node {
+            if let Ok(cells) = tree.node(id).read_u32(name) {
+                return cells as usize;
+            }
+            node = tree.parent(id);
+        }
+        default
+    }
+
+    // Read a number from big-endian cells, keeping the lowest 64 bits.
+    pub(crate) fn read_number(cells: &[u32]) -> u64 {
+        cells
+            .iter()
+            .fold(0u64, |number, cell| (number << 32) | *cell as u64)
+    }
+
+    fn untranslatable(tree: &DeviceTree, id: NodeId) -> DeviceTreeError {
+        DeviceTreeError::UntranslatableAddress(tree.path_of(id))
+    }
+
+    // Translate an address on the bus formed by a node to the bus of its
+    // parent through "ranges" or "dma-ranges", like `of_translate_one` of
+    // Linux.
+    fn translate_one(
+        tree: &DeviceTree,
+        id: NodeId,
+        (bus, pbus): (Bus, Bus),
+        addr: &[u32],
+        (na, ns, pna): (usize, usize, usize),
+        rprop: &str,
+    ) -> Result<Vec<u32>, DeviceTreeError> {
+        // A missing "dma-ranges" maps the DMA addresses 1:1
+        let ranges = tree.node(id).find_property(rprop);
+        if ranges.is_none() && rprop != "dma-ranges" {
+            return Err(Address::untranslatable(tree, id));
+        }
+
+        let (mut parent_addr, offset) = if ranges.is_none_or(|r| r.value.is_empty()) {
+            // An empty "ranges" maps the addresses 1:1
+            (vec![0; pna], Address::read_number(addr))
+        } else {
+            let len = ranges.map_or(0, |r| r.value.len());
+            let entry = na + pna + ns;
+            if entry == 0 || !len.is_multiple_of(entry * 4) {
+                return Err(DeviceTreeError::CellMismatch(format!(
+                    "{}:{}",
+                    tree.path_of(id),
+                    rprop
+                )));
+            }
+            let cells = tree.node(id).read_u32_array(rprop, len / 4)?;
+            cells
+                .chunks(entry)
+                .find_map(|range| {
+                    let offset = bus.map(addr, range, na, ns, pna)?;
+                    Some((range[na..(na + pna)].to_vec(), offset))
+                })
+                .ok_or_else(|| Address::untranslatable(tree, id))?
+        };
+        pbus.translate(&mut parent_addr, offset);
+        Ok(parent_addr)
+    }
+
+    // Translate an address of a node on the bus of its parent to a CPU
+    // physical address through "ranges" or "dma-ranges", like
+    // `of_translate_address` and `of_translate_dma_address` of Linux.
+    pub(crate) fn translate(
+        tree: &DeviceTree,
+        id: NodeId,
+        addr: &[u32],
+        rprop: &str,
+    ) -> Result<u64, DeviceTreeError> {
+        let Some(mut parent) = tree.parent(id) else {
+            return Ok(Address::read_number(addr));
+        };
+        let mut bus = Bus::of(tree, parent);
+        let (mut na, mut ns) = bus.count_cells(tree, parent);
+        if addr.len() != na {
+            return Err(DeviceTreeError::CellMismatch(format!(
+                "{}:#address-cells",
+                tree.path_of(parent)
+            )));
+        }
+
+        let mut addr = addr.to_vec();
+        loop {
+            let bus_node = parent;
+            match tree.parent(bus_node) {
+                Some(grandparent) => parent = grandparent,
+                // The addresses of the root bus are CPU physical addresses
+                None => return Ok(Address::read_number(&addr)),
+            }
+            let pbus = Bus::of(tree, parent);
+            let (pna, pns) = pbus.count_cells(tree, parent);
+            addr =
+                Address::translate_one(tree, bus_node, (bus, pbus), &addr, (na, ns, pna), rprop)?;
+            (bus, na, ns) = (pbus, pna, pns);
+        }
+    }
+
+    // Get the translated addresses and the sizes of the "reg" of a node, or
+    // of another property in the format of "reg".
+    pub(crate) fn reg(
+        tree: &DeviceTree,
+        id: NodeId,
+        name: &str,
+    ) -> Result<Vec<(u64, u64)>, DeviceTreeError> {
+        let (na, ns) = match tree.parent(id) {
+            Some(parent) => Bus::of(tree, parent).count_cells(tree, parent),
+            None => (
+                Address::cells(tree, id, "#address-cells", 2),
+                Address::cells(tree, id, "#size-cells", 1),
+            ),
+        };
+        let node = tree.node(id);
+        let count = node.count_elems_of_size(name, 4)?;
+        if na + ns == 0 || count % (na + ns) != 0 {
+            return Err(DeviceTreeError::CellMismatch(format!(
+                "{}:{}",
+                tree.path_of(id),
+                name
+            )));
+        }
+        let cells = node.read_u32_array(name, count)?;
+        cells
+            .chunks(na + ns)
+            .map(|entry| {
+                let addr = Address::translate(tree, id, &entry[..na], "ranges")?;
+                Ok((addr, Address::read_number(&entry[na..])))
+            })
+            .collect()
+    }
+
+    // Get the "dma-ranges" of the bus of a node, like `of_dma_get_range` of
+    // Linux. The node itself may not have "dma-ranges", and neither may its
+    // parent unless the node has an empty one. Empty "dma-ranges" on the way
+    // are skipped, and no ranges are found if it ends at a node without it.
+    pub(crate) fn dma_ranges(
+        tree: &DeviceTree,
+        id: NodeId,
+    ) -> Result<Vec<DmaRange>, DeviceTreeError> {
+        let mut node = Some(id);
+        let mut bus = None;
+        let mut found = false;
+        while let Some(id) = node {
+            match tree.node(id).find_property("dma-ranges") {
+                Some(ranges) if !ranges.value.is_empty() => {
+                    bus = Some(id);
+                    break;
+                }
+                None if found => return Ok(vec![]),
+                _ => {}
+            }
+            found = true;
+            node = tree.parent(id);
+        }
+        let Some(id) = bus else {
+            return Ok(vec![]);
+        };
+
+        let kind = Bus::of(tree, id);
+        let (na, ns) = kind.count_cells(tree, id);
+        let pna = match tree.parent(id) {
+            Some(parent) => Bus::of(tree, parent).count_cells(tree, parent).0,
+            None => Address::cells(tree, id, "#address-cells", 2),
+        };
+        let entry = na + pna + ns;
+        let count = tree.node(id).count_elems_of_size("dma-ranges", 4)?;
+        if entry == 0 || count % entry != 0 {
+            return Err(DeviceTreeError::CellMismatch(format!(
+                "{}:dma-ranges",
+                tree.path_of(id)
+            )));
+        }
+        let cells = tree.node(id).read_u32_array("dma-ranges", count)?;
+        let skip = kind.flag_cells().min(na);
+        cells
+            .chunks(entry)
+            .map(|range| {
+                Ok(DmaRange {
+                    cpu_start: Address::translate(tree, id, &range[na..(na + pna)], "dma-ranges")?,
+                    dma_start: Address::read_number(&range[skip..na]),
+                    size: Address::read_number(&range[(na + pna)..]),
+                })
+            })
+            .collect()
+    }
+
+    // Read the entries of a property, where every entry is numbers of the
+    // given numbers of cells.
+    pub(crate) fn read_entries(
+        tree: &DeviceTree,
+        id: NodeId,
+        name: &str,
+        cells: &[usize],
+    ) -> Result<Vec<Vec<u64>>, DeviceTreeError> {
+        let entry: usize = cells.iter().sum();
+        let count = tree.node(id).count_elems_of_size(name, 4)?;
+        if entry == 0 || count == 0 || count % entry != 0 {
+            return Err(DeviceTreeError::CellMismatch(format!(
+                "{}:{}",
+                tree.path_of(id),
+                name
+            )));
+        }
+        let values = tree.node(id).read_u32_array(name, count)?;
+        Ok(values
+            .chunks(entry)
+            .map(|entry| {
+                let mut rest = entry;
+                cells
+                    .iter()
+                    .map(|n| {
+                        let (number, tail) = rest.split_at(*n);
+                        rest = tail;
+                        Address::read_number(number)
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    // Write numbers into big-endian cells of the given numbers, or `None` if
+    // a number doesn't fit.
+    pub(crate) fn encode(numbers: &[u64], cells: &[usize]) -> Option<Vec<u32>> {
+        let mut encoded = vec![];
+        for (number, cells) in numbers.iter().zip(cells) {
+            if number.checked_shr(32 * *cells as u32).unwrap_or(0) != 0 {
+                return None;
+            }
+            encoded.extend(
+                (0..*cells)
+                    .rev()
+                    .map(|i| number.checked_shr(32 * i as u32).unwrap_or(0) as u32),
+            );
+        }
+        Some(encoded)
+    }
+
+    // Write the entries of a property, failing if a number doesn't fit its
+    // cells.
+    pub(crate) fn write_entries(
+        tree: &mut DeviceTree,
+        id: NodeId,
+        name: &str,
+        cells: &[usize],
+        entries: &[Vec<u64>],
+    ) -> Result<(), DeviceTreeError> {
+        let mut value = vec![];
+        for entry in entries {
+            let encoded = Address::encode(entry, cells).ok_or_else(|| {
+                DeviceTreeError::CellMismatch(format!("{}:{}", tree.path_of(id), name))
+            })?;
+            value.extend(encoded);
+        }
+        tree.node_mut(id)
+            .set_property(Property::new_u32s(name, value));
+        Ok(())
+    }
+
+    // Get the numbers of cells of the child addresses, the parent addresses
+    // and the sizes in the "ranges" or "dma-ranges" of a node.
+    pub(crate) fn ranges_cells(tree: &DeviceTree, id: NodeId) -> [usize; 3] {
+        [
+            Address::cells(tree, id, "#address-cells", 2),
+            tree.address_cells(id),
+            Address::cells(tree, id, "#size-cells", 1),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> DeviceTree {
+        DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	#address-cells = <2>;
+	#size-cells = <2>;
+
+	soc {
+		compatible = "simple-bus";
+		#address-cells = <1>;
+		#size-cells = <1>;
+		ranges = <0x0 0x0 0x10000000 0x1000000>, <0x2000000 0x1 0x0 0x100000>;
+		dma-ranges = <0x0 0x0 0x80000000 0x40000000>;
+
+		uart@1000 {
+			reg = <0x1000 0x100>, <0x2000010 0x10>;
+		};
+
+		bridge {
+			#address-cells = <1>;
+			#size-cells = <1>;
+			ranges;
+			dma-ranges;
+
+			timer@3000 {
+				reg = <0x3000 0x100>;
+			};
+		};
+
+		isolated {
+			#address-cells = <1>;
+			#size-cells = <1>;
+
+			device@0 {
+				reg = <0x0 0x10>;
+			};
+		};
+	};
+
+	pcie@30000000 {
+		device_type = "pci";
+		#address-cells = <3>;
+		#size-cells = <2>;
+		ranges = <0x1000000 0x0 0x0 0x0 0x3eff0000 0x0 0x10000>,
+			<0x2000000 0x0 0x10000000 0x0 0x10000000 0x0 0x2eff0000>,
+			<0x3000000 0x80 0x0 0x80 0x0 0x80 0x0>;
+		dma-ranges = <0x2000000 0x0 0x0 0x0 0x40000000 0x0 0x80000000>;
+	};
+};
+"#,
+        )
+    }
+
+    #[test]
+    fn test_address_translate() {
+        let mut tree = tree();
+        let uart = tree.find_node_by_path("/soc/uart@1000").unwrap();
+        let timer = tree.find_node_by_path("/soc/bridge/timer@3000").unwrap();
+        let device = tree.find_node_by_path("/soc/isolated/device@0").unwrap();
+        let soc = tree.find_node_by_path("/soc").unwrap();
+        let pcie = tree.find_node_by_path("/pcie@30000000").unwrap();
+
+        assert_eq!(tree.translate_address(uart, &[0x1000]), Ok(0x10001000));
+        assert_eq!(
+            tree.translate_address(uart, &[0x2000010]),
+            Ok(0x1_0000_0010)
+        );
+        assert_eq!(
+            tree.translate_address(uart, &[0x1000000]),
+            Err(DeviceTreeError::UntranslatableAddress(String::from("/soc")))
+        );
+        assert_eq!(
+            tree.translate_address(uart, &[0, 0x1000]),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/soc:#address-cells"
+            )))
+        );
+        assert_eq!(tree.translate_address(timer, &[0x3000]), Ok(0x10003000));
+        assert_eq!(
+            tree.translate_address(device, &[0x0]),
+            Err(DeviceTreeError::UntranslatableAddress(String::from(
+                "/soc/isolated"
+            )))
+        );
+        assert_eq!(tree.translate_address(soc, &[0, 0x1234]), Ok(0x1234));
+
+        // The PCI space codes must match: I/O, 32-bit and 64-bit memory
+        let child = tree.add_sub_node(pcie, crate::node::Node::new("device@0"));
+        assert_eq!(
+            tree.translate_address(child, &[0x81000000, 0, 0x20]),
+            Ok(0x3eff0020)
+        );
+        assert_eq!(
+            tree.translate_address(child, &[0x82000000, 0, 0x10001000]),
+            Ok(0x10001000)
+        );
+        assert_eq!(
+            tree.translate_address(child, &[0x43000000, 0x80, 0x1000]),
+            Ok(0x80_0000_1000)
+        );
+        assert!(tree
+            .translate_address(child, &[0x1000000, 0, 0x10001000])
+            .is_err());
+        assert!(tree.translate_address(child, &[0x0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_address_reg() {
+        let tree = tree();
+        let uart = tree.find_node_by_path("/soc/uart@1000").unwrap();
+        let timer = tree.find_node_by_path("/soc/bridge/timer@3000").unwrap();
+        let device = tree.find_node_by_path("/soc/isolated/device@0").unwrap();
+
+        assert_eq!(
+            tree.reg(uart),
+            Ok(vec![(0x10001000, 0x100), (0x1_0000_0010, 0x10)])
+        );
+        assert_eq!(tree.reg(timer), Ok(vec![(0x10003000, 0x100)]));
+        assert!(tree.reg(device).is_err());
+        assert_eq!(
+            tree.reg(tree.root()),
+            Err(DeviceTreeError::PropertyNotFound(String::from("reg")))
+        );
+
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let tree = DeviceTree::from_dts_bytes(&dts);
+        let intc = tree.find_node_by_path("/intc").unwrap();
+        let msic = tree.find_node_by_path("/intc/msic").unwrap();
+        assert_eq!(
+            tree.reg(intc),
+            Ok(vec![(0x8ff0000, 0x10000), (0x8cf0000, 0x300000)])
+        );
+        assert_eq!(tree.reg(msic), Ok(vec![(0x8cd0000, 0x20000)]));
+    }
+
+    #[test]
+    fn test_address_dma_ranges() {
+        let tree = tree();
+        let soc = tree.find_node_by_path("/soc").unwrap();
+        let uart = tree.find_node_by_path("/soc/uart@1000").unwrap();
+        let timer = tree.find_node_by_path("/soc/bridge/timer@3000").unwrap();
+        let device = tree.find_node_by_path("/soc/isolated/device@0").unwrap();
+        let pcie = tree.find_node_by_path("/pcie@30000000").unwrap();
+
+        let soc_ranges = vec![DmaRange {
+            cpu_start: 0x80000000,
+            dma_start: 0,
+            size: 0x40000000,
+        }];
+        assert_eq!(tree.dma_ranges(uart), Ok(soc_ranges.clone()));
+        assert_eq!(tree.dma_ranges(soc), Ok(soc_ranges.clone()));
+        // The empty "dma-ranges" of the bridge is skipped
+        assert_eq!(tree.dma_ranges(timer), Ok(soc_ranges));
+        // The parent of the device has no "dma-ranges"
+        assert_eq!(tree.dma_ranges(device), Ok(vec![]));
+        assert_eq!(tree.dma_ranges(tree.root()), Ok(vec![]));
+        assert_eq!(
+            tree.dma_ranges(pcie),
+            Ok(vec![DmaRange {
+                cpu_start: 0x40000000,
+                dma_start: 0,
+                size: 0x80000000,
+            }])
+        );
+
+        assert_eq!(tree.translate_dma_address(uart, &[0x1000]), Ok(0x80001000));
+        assert_eq!(tree.translate_dma_address(timer, &[0x10]), Ok(0x80000010));
+        assert_eq!(
+            tree.translate_dma_address(uart, &[0x40000000]),
+            Err(DeviceTreeError::UntranslatableAddress(String::from("/soc")))
+        );
+    }
+
+    #[test]
+    fn test_address_reg_and_ranges_builders() {
+        let mut tree = tree();
+        let soc = tree.find_node_by_path("/soc").unwrap();
+        let uart = tree.find_node_by_path("/soc/uart@1000").unwrap();
+        let bridge = tree.find_node_by_path("/soc/bridge").unwrap();
+
+        assert_eq!(
+            tree.read_reg(uart),
+            Ok(vec![(0x1000, 0x100), (0x2000010, 0x10)])
+        );
+        assert_eq!(
+            tree.read_ranges(soc),
+            Ok(vec![
+                (0x0, 0x10000000, 0x1000000),
+                (0x2000000, 0x1_0000_0000, 0x100000)
+            ])
+        );
+        assert_eq!(
+            tree.read_dma_ranges(soc),
+            Ok(vec![(0x0, 0x80000000, 0x40000000)])
+        );
+        assert_eq!(tree.read_ranges(bridge), Ok(vec![]));
+        assert_eq!(
+            tree.read_ranges(uart),
+            Err(DeviceTreeError::PropertyNotFound(String::from("ranges")))
+        );
+
+        tree.set_reg(uart, &[(0x4000, 0x200)]).unwrap();
+        assert_eq!(tree.reg(uart), Ok(vec![(0x10004000, 0x200)]));
+        // The address doesn't fit the single cell of the soc
+        assert_eq!(
+            tree.set_reg(uart, &[(0x1_0000_0000, 0x200)]),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/soc/uart@1000:reg"
+            )))
+        );
+        assert_eq!(tree.read_reg(uart), Ok(vec![(0x4000, 0x200)]));
+
+        tree.set_ranges(soc, &[(0x0, 0x2_0000_0000, 0x1000000)])
+            .unwrap();
+        assert_eq!(tree.reg(uart), Ok(vec![(0x2_0000_4000, 0x200)]));
+        assert_eq!(
+            tree.set_dma_ranges(soc, &[(0x0, 0x0, 0x1_0000_0000)]),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/soc:dma-ranges"
+            )))
+        );
+        tree.set_dma_ranges(bridge, &[]).unwrap();
+        assert_eq!(tree.read_dma_ranges(bridge), Ok(vec![]));
+    }
+}
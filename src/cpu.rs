@@ -0,0 +1,927 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::address::Address;
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::{Node, NodeId};
+use crate::property::Property;
+use std::collections::BTreeMap;
+
+/// The geometry of a cache, from the `*cache-size`, `*cache-sets`,
+/// `*cache-block-size` and `*cache-line-size` properties.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheGeometry {
+    pub size: Option<u32>,
+    pub sets: Option<u32>,
+    pub block_size: Option<u32>,
+    pub line_size: Option<u32>,
+}
+
+impl CacheGeometry {
+    /// Create a cache geometry with the size, the number of sets and the line
+    /// size.
+    pub fn new(size: u32, sets: u32, line_size: u32) -> Self {
+        CacheGeometry {
+            size: Some(size),
+            sets: Some(sets),
+            block_size: None,
+            line_size: Some(line_size),
+        }
+    }
+
+    fn names(prefix: &str) -> [String; 4] {
+        ["size", "sets", "block-size", "line-size"].map(|name| format!("{prefix}cache-{name}"))
+    }
+
+    // Read the geometry with the prefix of the property names, which is
+    // "", "i-" or "d-".
+    fn read(node: &Node, prefix: &str) -> Option<CacheGeometry> {
+        let [size, sets, block_size, line_size] =
+            CacheGeometry::names(prefix).map(|name| node.read_u32(&name).ok());
+        if size.is_none() && sets.is_none() && block_size.is_none() && line_size.is_none() {
+            return None;
+        }
+        Some(CacheGeometry {
+            size,
+            sets,
+            block_size,
+            line_size,
+        })
+    }
+
+    fn write(&self, node: &mut Node, prefix: &str) {
+        let values = [self.size, self.sets, self.block_size, self.line_size];
+        for (name, value) in CacheGeometry::names(prefix).iter().zip(values) {
+            if let Some(value) = value {
+                node.add_property(Property::new_u32(name, value));
+            }
+        }
+    }
+}
+
+/// A level of cache, described by the properties of a CPU node for level 1,
+/// or by a cache node for the other levels.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cache {
+    /// The level of the cache, from "cache-level" of a cache node
+    pub level: u32,
+    /// Whether the instructions and the data share the cache
+    pub unified: bool,
+    /// The geometry from the "cache-*" properties
+    pub geometry: Option<CacheGeometry>,
+    /// The geometry from the "i-cache-*" properties
+    pub instruction: Option<CacheGeometry>,
+    /// The geometry from the "d-cache-*" properties
+    pub data: Option<CacheGeometry>,
+    /// The index of the cache in `CpuTopology::caches` that
+    /// "next-level-cache" refers to
+    pub next_level: Option<usize>,
+    /// The node the cache is read from
+    pub node: Option<NodeId>,
+}
+
+impl Cache {
+    /// Create a unified cache of a level.
+    pub fn new_unified(level: u32, geometry: CacheGeometry) -> Self {
+        Cache {
+            level,
+            unified: true,
+            geometry: Some(geometry),
+            ..Default::default()
+        }
+    }
+
+    /// Create a level 1 cache with separate instruction and data caches.
+    pub fn new_split(instruction: CacheGeometry, data: CacheGeometry) -> Self {
+        Cache {
+            level: 1,
+            instruction: Some(instruction),
+            data: Some(data),
+            ..Default::default()
+        }
+    }
+
+    fn read(node: &Node, level: u32) -> Self {
+        Cache {
+            level,
+            unified: node.find_property("cache-unified").is_some(),
+            geometry: CacheGeometry::read(node, ""),
+            instruction: CacheGeometry::read(node, "i-"),
+            data: CacheGeometry::read(node, "d-"),
+            next_level: None,
+            node: None,
+        }
+    }
+
+    fn write(&self, node: &mut Node) {
+        if self.unified {
+            node.add_property(Property::new_empty("cache-unified"));
+        }
+        for (geometry, prefix) in [
+            (&self.geometry, ""),
+            (&self.instruction, "i-"),
+            (&self.data, "d-"),
+        ] {
+            if let Some(geometry) = geometry {
+                geometry.write(node, prefix);
+            }
+        }
+    }
+}
+
+/// Where a CPU is in the "cpu-map" of "/cpus".
+#[derive(Clone, Debug, PartialEq)]
+pub struct CpuLocation {
+    /// The number of the "socketN" node
+    pub socket: Option<u32>,
+    /// The numbers of the "clusterN" nodes, from the outermost
+    pub clusters: Vec<u32>,
+    /// The number of the "coreN" node
+    pub core: u32,
+    /// The number of the "threadN" node
+    pub thread: Option<u32>,
+}
+
+impl CpuLocation {
+    /// Create a location of a core in a cluster.
+    pub fn new(cluster: u32, core: u32) -> Self {
+        CpuLocation {
+            socket: None,
+            clusters: vec![cluster],
+            core,
+            thread: None,
+        }
+    }
+
+    // Get the names of the "cpu-map" nodes down to the CPU.
+    fn names(&self) -> Vec<String> {
+        let mut names = vec![];
+        names.extend(self.socket.map(|socket| format!("socket{socket}")));
+        names.extend(
+            self.clusters
+                .iter()
+                .map(|cluster| format!("cluster{cluster}")),
+        );
+        names.push(format!("core{}", self.core));
+        names.extend(self.thread.map(|thread| format!("thread{thread}")));
+        names
+    }
+}
+
+/// A CPU of a `CpuTopology`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cpu {
+    /// The hardware ID of the CPU, from "reg", like the MPIDR of ARM
+    pub hwid: u64,
+    pub compatible: Option<String>,
+    /// The "enable-method", like "psci" or "spin-table"
+    pub enable_method: Option<String>,
+    /// The "capacity-dmips-mhz" for the scheduler
+    pub capacity_dmips_mhz: Option<u32>,
+    /// The level 1 cache, described by the properties of the CPU node
+    pub cache: Cache,
+    /// The place in "cpu-map"
+    pub location: Option<CpuLocation>,
+    /// The node the CPU is read from
+    pub node: Option<NodeId>,
+}
+
+impl Cpu {
+    /// Create a CPU with the hardware ID.
+    pub fn new(hwid: u64) -> Self {
+        Cpu {
+            hwid,
+            cache: Cache {
+                level: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// The CPU topology described by "/cpus": the CPUs, their places in
+/// "cpu-map" and the caches chained by "next-level-cache".
+///
+/// It is read with `DeviceTree::cpu_topology`, and written with
+/// `DeviceTree::set_cpu_topology`.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{Cache, CacheGeometry, Cpu, CpuLocation, CpuTopology, DeviceTree, Node};
+///
+/// let mut topology = CpuTopology::new(1);
+/// let l2 = topology.add_cache(Cache::new_unified(2, CacheGeometry::new(0x100000, 1024, 64)));
+/// for core in 0..2 {
+///     let mut cpu = Cpu::new(core as u64);
+///     cpu.compatible = Some(String::from("arm,cortex-a72"));
+///     cpu.enable_method = Some(String::from("psci"));
+///     cpu.cache.next_level = Some(l2);
+///     cpu.location = Some(CpuLocation::new(0, core));
+///     topology.add_cpu(cpu);
+/// }
+///
+/// let mut tree = DeviceTree::new(vec![], Node::new(""));
+/// tree.set_cpu_topology(&topology).unwrap();
+/// assert!(tree.find_node_by_path("/cpus/cpu-map/cluster0/core1").is_some());
+/// assert!(tree.find_node_by_path("/cpus/l2-cache0").is_some());
+///
+/// let parsed = tree.cpu_topology().unwrap();
+/// assert_eq!(parsed.cpus[1].location, Some(CpuLocation::new(0, 1)));
+/// assert_eq!(parsed.caches[0].level, 2);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CpuTopology {
+    /// The "#address-cells" of "/cpus", which is the size of the hardware IDs
+    pub address_cells: u32,
+    pub cpus: Vec<Cpu>,
+    /// The caches beyond level 1, which have their own nodes
+    pub caches: Vec<Cache>,
+}
+
+impl CpuTopology {
+    /// Create an empty topology with the number of cells of hardware IDs.
+    pub fn new(address_cells: u32) -> Self {
+        CpuTopology {
+            address_cells,
+            cpus: vec![],
+            caches: vec![],
+        }
+    }
+
+    /// Add a CPU, and get its index.
+    pub fn add_cpu(&mut self, cpu: Cpu) -> usize {
+        self.cpus.push(cpu);
+        self.cpus.len() - 1
+    }
+
+    /// Add a cache, and get its index to be used in `Cache::next_level`.
+    pub fn add_cache(&mut self, cache: Cache) -> usize {
+        self.caches.push(cache);
+        self.caches.len() - 1
+    }
+
+    /// Get the caches of a CPU, from level 1 following "next-level-cache".
+    pub fn cache_chain(&self, cpu: usize) -> Vec<&Cache> {
+        let mut chain = vec![&self.cpus[cpu].cache];
+        let mut next = self.cpus[cpu].cache.next_level;
+        while let Some(index) = next {
+            match self.caches.get(index) {
+                // A loop is cut where it returns
+                Some(cache) if !chain.iter().any(|c| std::ptr::eq(*c, cache)) => {
+                    chain.push(cache);
+                    next = cache.next_level;
+                }
+                _ => break,
+            }
+        }
+        chain
+    }
+
+    fn invalid(message: String) -> DeviceTreeError {
+        DeviceTreeError::InvalidTopology(message)
+    }
+
+    // Check that the topology can be written as "/cpus".
+    fn validate(&self) -> Result<(), DeviceTreeError> {
+        if !(1..=2).contains(&self.address_cells) {
+            return Err(CpuTopology::invalid(format!(
+                "#address-cells {}",
+                self.address_cells
+            )));
+        }
+        let mut hwids = vec![];
+        for cpu in &self.cpus {
+            if self.address_cells == 1 && cpu.hwid > u32::MAX as u64 {
+                return Err(CpuTopology::invalid(format!(
+                    "hwid {:#x} too large",
+                    cpu.hwid
+                )));
+            }
+            if hwids.contains(&cpu.hwid) {
+                return Err(CpuTopology::invalid(format!(
+                    "duplicate hwid {:#x}",
+                    cpu.hwid
+                )));
+            }
+            hwids.push(cpu.hwid);
+        }
+
+        // The levels go up along "next-level-cache", which also rules out
+        // loops
+        let chains = self
+            .cpus
+            .iter()
+            .map(|cpu| &cpu.cache)
+            .chain(self.caches.iter());
+        for cache in chains {
+            if let Some(next) = cache.next_level {
+                let Some(next) = self.caches.get(next) else {
+                    return Err(CpuTopology::invalid(format!("no cache {next}")));
+                };
+                if next.level <= cache.level {
+                    return Err(CpuTopology::invalid(format!(
+                        "level {} cache after level {}",
+                        next.level, cache.level
+                    )));
+                }
+            }
+        }
+
+        // The cores are in clusters, and either all or none of the CPUs of a
+        // core are threads
+        let locations: Vec<&CpuLocation> = self
+            .cpus
+            .iter()
+            .filter_map(|cpu| cpu.location.as_ref())
+            .collect();
+        for (i, location) in locations.iter().enumerate() {
+            let path = location.names().join("/");
+            if location.clusters.is_empty() {
+                return Err(CpuTopology::invalid(format!("{path} not in a cluster")));
+            }
+            for other in &locations[..i] {
+                let same_core = other.socket == location.socket
+                    && other.clusters == location.clusters
+                    && other.core == location.core;
+                if same_core && (other.thread.is_none() || location.thread.is_none()) {
+                    return Err(CpuTopology::invalid(format!("{path} used twice")));
+                }
+                if same_core && other.thread == location.thread {
+                    return Err(CpuTopology::invalid(format!("{path} used twice")));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Read the topology from "/cpus".
+    pub(crate) fn read(tree: &DeviceTree) -> Result<CpuTopology, DeviceTreeError> {
+        let cpus = tree
+            .find_node_by_path("/cpus")
+            .ok_or_else(|| DeviceTreeError::NodeNotFound(String::from("/cpus")))?;
+        let na = Address::cells(tree, cpus, "#address-cells", 2);
+        let mut topology = CpuTopology::new(na as u32);
+
+        let mut ids = BTreeMap::new();
+        for id in tree.children(cpus).iter() {
+            let node = tree.node(*id);
+            if !CpuTopology::is_cpu(node) {
+                continue;
+            }
+            let reg = node.read_u32_array("reg", na)?;
+            let mut cpu = Cpu::new(Address::read_number(&reg));
+            cpu.compatible = node.read_string("compatible").ok().map(String::from);
+            cpu.enable_method = node.read_string("enable-method").ok().map(String::from);
+            cpu.capacity_dmips_mhz = node.read_u32("capacity-dmips-mhz").ok();
+            cpu.cache = Cache::read(node, 1);
+            cpu.cache.next_level = topology.read_next_level(tree, *id, 1)?;
+            cpu.node = Some(*id);
+            ids.insert(*id, topology.add_cpu(cpu));
+        }
+
+        if let Some(map) = tree.find_child_by_name(cpus, "cpu-map") {
+            let mut names = vec![];
+            topology.read_cpu_map(tree, map, &ids, &mut names)?;
+        }
+        Ok(topology)
+    }
+
+    // Read the cache that "next-level-cache" of a node refers to, and get
+    // its index. The caches read before are shared.
+    fn read_next_level(
+        &mut self,
+        tree: &DeviceTree,
+        id: NodeId,
+        level: u32,
+    ) -> Result<Option<usize>, DeviceTreeError> {
+        let Ok(phandle) = tree.node(id).read_u32("next-level-cache") else {
+            return Ok(None);
+        };
+        let next = tree
+            .find_node_by_phandle(phandle)
+            .ok_or(DeviceTreeError::DanglingPhandle(phandle))?;
+        if let Some(index) = self.caches.iter().position(|c| c.node == Some(next)) {
+            return Ok(Some(index));
+        }
+        let node = tree.node(next);
+        let mut cache = Cache::read(node, node.read_u32("cache-level").unwrap_or(level + 1));
+        cache.node = Some(next);
+        let level = cache.level;
+        let index = self.add_cache(cache);
+        self.caches[index].next_level = self.read_next_level(tree, next, level)?;
+        Ok(Some(index))
+    }
+
+    // Read the places of the CPUs in a node of "cpu-map" and its children.
+    fn read_cpu_map(
+        &mut self,
+        tree: &DeviceTree,
+        id: NodeId,
+        ids: &BTreeMap<NodeId, usize>,
+        names: &mut Vec<(String, u32)>,
+    ) -> Result<(), DeviceTreeError> {
+        if let Ok(phandle) = tree.node(id).read_u32("cpu") {
+            let cpu = tree
+                .find_node_by_phandle(phandle)
+                .ok_or(DeviceTreeError::DanglingPhandle(phandle))?;
+            let find = |kind: &str| {
+                names
+                    .iter()
+                    .filter(|(k, _)| k == kind)
+                    .map(|(_, n)| *n)
+                    .collect::<Vec<u32>>()
+            };
+            if let (Some(index), Some(core)) = (ids.get(&cpu), find("core").first()) {
+                self.cpus[*index].location = Some(CpuLocation {
+                    socket: find("socket").first().copied(),
+                    clusters: find("cluster"),
+                    core: *core,
+                    thread: find("thread").first().copied(),
+                });
+            }
+        }
+        for child in tree.children(id).iter() {
+            let name = &tree.node(*child).name;
+            let level = ["socket", "cluster", "core", "thread"]
+                .iter()
+                .find_map(|kind| {
+                    let number = name.strip_prefix(kind)?.parse().ok()?;
+                    Some((String::from(*kind), number))
+                });
+            if let Some(level) = level {
+                names.push(level);
+                self.read_cpu_map(tree, *child, ids, names)?;
+                names.pop();
+            }
+        }
+        Ok(())
+    }
+
+    // Check if a node of "/cpus" is a CPU.
+    fn is_cpu(node: &Node) -> bool {
+        match node.find_property("device_type") {
+            Some(_) => node.read_string("device_type") == Ok("cpu"),
+            None => node.base_name() == "cpu",
+        }
+    }
+
+    // Check if a node is a cache of the CPUs.
+    fn is_cache(node: &Node) -> bool {
+        node.is_compatible("cache") || node.find_property("cache-level").is_some()
+    }
+
+    // Check if a property of a CPU node is written from the topology.
+    fn is_modeled(name: &str) -> bool {
+        let modeled = [
+            "device_type",
+            "compatible",
+            "reg",
+            "enable-method",
+            "capacity-dmips-mhz",
+            "next-level-cache",
+            "cache-unified",
+        ];
+        modeled.contains(&name)
+            || ["", "i-", "d-"]
+                .iter()
+                .any(|prefix| CacheGeometry::names(prefix).iter().any(|n| n == name))
+    }
+
+    // Get the properties of a CPU node written from the topology, except
+    // "next-level-cache".
+    fn cpu_properties(&self, cpu: &Cpu) -> Vec<Property> {
+        let mut node = Node::new("");
+        node.add_property(Property::new_str("device_type", "cpu"));
+        if let Some(compatible) = &cpu.compatible {
+            node.add_property(Property::new_str("compatible", compatible));
+        }
+        let reg = match self.address_cells {
+            1 => vec![cpu.hwid as u32],
+            _ => vec![(cpu.hwid >> 32) as u32, cpu.hwid as u32],
+        };
+        node.add_property(Property::new_u32s("reg", reg));
+        if let Some(method) = &cpu.enable_method {
+            node.add_property(Property::new_str("enable-method", method));
+        }
+        if let Some(capacity) = cpu.capacity_dmips_mhz {
+            node.add_property(Property::new_u32("capacity-dmips-mhz", capacity));
+        }
+        cpu.cache.write(&mut node);
+        node.properties
+    }
+
+    // Write the topology into "/cpus", which is created if it doesn't
+    // exist.
+    //
+    // The CPU nodes are matched by their hardware IDs and updated in place,
+    // keeping their phandles, labels, sub nodes and the properties not in
+    // the topology. The CPU nodes without a match, the cache nodes and
+    // "cpu-map" are replaced, which fails if anything else still refers to
+    // them.
+    pub(crate) fn write(&self, tree: &mut DeviceTree) -> Result<NodeId, DeviceTreeError> {
+        self.validate()?;
+        let cpus = tree.find_node_by_path("/cpus");
+
+        let mut matched = BTreeMap::new();
+        let mut removed = vec![];
+        if let Some(cpus) = cpus {
+            let na = Address::cells(tree, cpus, "#address-cells", 2);
+            for id in tree.children(cpus).iter() {
+                let node = tree.node(*id);
+                if CpuTopology::is_cpu(node) {
+                    let hwid = node
+                        .read_u32_array("reg", na)
+                        .map(|reg| Address::read_number(&reg));
+                    match hwid {
+                        Ok(hwid) if self.cpus.iter().any(|cpu| cpu.hwid == hwid) => {
+                            matched.insert(hwid, *id);
+                        }
+                        _ => removed.push(*id),
+                    }
+                } else if node.name == "cpu-map" || CpuTopology::is_cache(node) {
+                    removed.push(*id);
+                }
+            }
+            // The caches may also be put in the CPU nodes
+            for id in matched.values() {
+                let children = tree.children(*id).iter();
+                removed.extend(children.filter(|c| CpuTopology::is_cache(tree.node(**c))));
+            }
+        }
+
+        // The replaced nodes may only be referred to by each other, and by
+        // the "next-level-cache" of the CPU nodes being updated
+        let removed_ids: Vec<NodeId> = removed.iter().flat_map(|id| tree.subtree(*id)).collect();
+        for reference in tree.phandle_references() {
+            let Some(target) = tree.find_node_by_phandle(reference.phandle) else {
+                continue;
+            };
+            let updated = reference.property == "next-level-cache"
+                && matched.values().any(|id| *id == reference.node);
+            if removed_ids.contains(&target) && !removed_ids.contains(&reference.node) && !updated {
+                return Err(CpuTopology::invalid(format!(
+                    "{} is referred to by {}:{}",
+                    tree.path_of(target),
+                    tree.path_of(reference.node),
+                    reference.property
+                )));
+            }
+        }
+
+        for id in removed {
+            tree.take_node(id)?;
+        }
+        let cpus = match cpus {
+            Some(cpus) => cpus,
+            None => tree.add_sub_node(tree.root(), Node::new("cpus")),
+        };
+        let mut node = tree.node_mut(cpus);
+        node.set_property(Property::new_u32("#address-cells", self.address_cells));
+        node.set_property(Property::new_u32("#size-cells", 0));
+        drop(node);
+
+        let mut cpu_ids = vec![];
+        for cpu in &self.cpus {
+            let properties = self.cpu_properties(cpu);
+            let id = match matched.get(&cpu.hwid) {
+                Some(id) => *id,
+                None => tree.add_sub_node(cpus, Node::new(&format!("cpu@{:x}", cpu.hwid))),
+            };
+            let mut node = tree.node_mut(id);
+            let stale: Vec<String> = node
+                .properties
+                .iter()
+                .map(|p| p.name.clone())
+                .filter(|name| {
+                    CpuTopology::is_modeled(name) && !properties.iter().any(|p| &p.name == name)
+                })
+                .collect();
+            for name in stale {
+                node.remove_property(&name);
+            }
+            for property in properties {
+                node.set_property(property);
+            }
+            cpu_ids.push(id);
+        }
+
+        let mut cache_ids = vec![];
+        for (i, cache) in self.caches.iter().enumerate() {
+            let n = self.caches[..i]
+                .iter()
+                .filter(|c| c.level == cache.level)
+                .count();
+            let mut node = Node::new(&format!("l{}-cache{}", cache.level, n));
+            node.add_property(Property::new_str("compatible", "cache"));
+            node.add_property(Property::new_u32("cache-level", cache.level));
+            cache.write(&mut node);
+            cache_ids.push(tree.add_sub_node(cpus, node));
+        }
+
+        let links = self
+            .cpus
+            .iter()
+            .map(|cpu| &cpu.cache)
+            .zip(&cpu_ids)
+            .chain(self.caches.iter().zip(&cache_ids));
+        let links: Vec<(NodeId, usize)> = links
+            .filter_map(|(cache, id)| Some((*id, cache.next_level?)))
+            .collect();
+        for (id, next) in links {
+            let phandle = tree.ensure_phandle(cache_ids[next]);
+            tree.node_mut(id)
+                .set_property(Property::new_u32("next-level-cache", phandle));
+        }
+
+        if self.cpus.iter().any(|cpu| cpu.location.is_some()) {
+            let map = tree.add_sub_node(cpus, Node::new("cpu-map"));
+            for (cpu, id) in self.cpus.iter().zip(&cpu_ids) {
+                let Some(location) = &cpu.location else {
+                    continue;
+                };
+                let mut parent = map;
+                for name in location.names() {
+                    parent = match tree.find_child_by_name(parent, &name) {
+                        Some(child) => child,
+                        None => tree.add_sub_node(parent, Node::new(&name)),
+                    };
+                }
+                let phandle = tree.ensure_phandle(*id);
+                tree.node_mut(parent)
+                    .add_property(Property::new_u32("cpu", phandle));
+            }
+        }
+        Ok(cpus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear_nodes(topology: &mut CpuTopology) {
+        for cpu in topology.cpus.iter_mut() {
+            cpu.node = None;
+        }
+        for cache in topology.caches.iter_mut() {
+            cache.node = None;
+        }
+    }
+
+    #[test]
+    fn test_cpu_topology_read() {
+        let dts = br#"/dts-v1/;
+/ {
+	cpus {
+		#address-cells = <2>;
+		#size-cells = <0>;
+
+		cpu-map {
+			socket0 {
+				cluster0 {
+					core0 {
+						thread0 {
+							cpu = <&cpu0>;
+						};
+						thread1 {
+							cpu = <&cpu1>;
+						};
+					};
+				};
+				cluster1 {
+					core0 {
+						cpu = <&cpu2>;
+					};
+				};
+			};
+		};
+
+		cpu0: cpu@0 {
+			phandle = <1>;
+			device_type = "cpu";
+			compatible = "arm,neoverse-n1";
+			reg = <0x0 0x0>;
+			enable-method = "psci";
+			capacity-dmips-mhz = <1024>;
+			i-cache-size = <0x10000>;
+			i-cache-sets = <256>;
+			i-cache-line-size = <64>;
+			d-cache-size = <0x10000>;
+			next-level-cache = <&l2_0>;
+		};
+
+		cpu1: cpu@1 {
+			phandle = <2>;
+			device_type = "cpu";
+			reg = <0x0 0x1>;
+			next-level-cache = <&l2_0>;
+		};
+
+		cpu2: cpu@100 {
+			phandle = <3>;
+			device_type = "cpu";
+			reg = <0x0 0x100>;
+			capacity-dmips-mhz = <512>;
+			next-level-cache = <&l3>;
+		};
+
+		l2_0: l2-cache0 {
+			phandle = <4>;
+			compatible = "cache";
+			cache-unified;
+			cache-size = <0x100000>;
+			next-level-cache = <&l3>;
+		};
+
+		l3: l3-cache {
+			phandle = <5>;
+			compatible = "cache";
+			cache-level = <3>;
+		};
+	};
+};
+"#;
+        let tree = DeviceTree::from_dts_bytes(dts);
+        let topology = tree.cpu_topology().unwrap();
+
+        assert_eq!(topology.address_cells, 2);
+        let hwids: Vec<u64> = topology.cpus.iter().map(|cpu| cpu.hwid).collect();
+        assert_eq!(hwids, vec![0x0, 0x1, 0x100]);
+
+        let cpu0 = &topology.cpus[0];
+        assert_eq!(cpu0.enable_method.as_deref(), Some("psci"));
+        assert_eq!(cpu0.capacity_dmips_mhz, Some(1024));
+        assert_eq!(
+            cpu0.cache.instruction,
+            Some(CacheGeometry::new(0x10000, 256, 64))
+        );
+        assert_eq!(cpu0.cache.data.as_ref().unwrap().size, Some(0x10000));
+        assert_eq!(
+            cpu0.location,
+            Some(CpuLocation {
+                socket: Some(0),
+                clusters: vec![0],
+                core: 0,
+                thread: Some(0)
+            })
+        );
+        assert_eq!(
+            topology.cpus[2].location.as_ref().unwrap().clusters,
+            vec![1]
+        );
+
+        // The L2 is shared by the first two CPUs, and the L3 by all
+        let levels = |cpu| -> Vec<u32> {
+            topology
+                .cache_chain(cpu)
+                .iter()
+                .map(|cache| cache.level)
+                .collect()
+        };
+        assert_eq!(levels(0), vec![1, 2, 3]);
+        assert_eq!(levels(2), vec![1, 3]);
+        assert_eq!(topology.caches.len(), 2);
+        assert_eq!(topology.cpus[1].cache.next_level, Some(0));
+        assert!(topology.caches[0].unified);
+
+        // Writing and reading back keeps the topology
+        let mut tree = DeviceTree::new(vec![], Node::new(""));
+        let mut expected = topology.clone();
+        tree.set_cpu_topology(&topology).unwrap();
+        let mut parsed = tree.cpu_topology().unwrap();
+        clear_nodes(&mut expected);
+        clear_nodes(&mut parsed);
+        assert_eq!(parsed, expected);
+        assert!(tree
+            .find_node_by_path("/cpus/cpu-map/socket0/cluster0/core0/thread1")
+            .is_some());
+        assert!(tree.find_node_by_path("/cpus/l3-cache0").is_some());
+    }
+
+    #[test]
+    fn test_cpu_topology_validate() {
+        let mut tree = DeviceTree::new(vec![], Node::new(""));
+        let mut topology = CpuTopology::new(1);
+        topology.add_cpu(Cpu::new(0));
+        topology.add_cpu(Cpu::new(0));
+        assert_eq!(
+            tree.set_cpu_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "duplicate hwid 0x0"
+            )))
+        );
+
+        topology.cpus[1].hwid = 1;
+        topology.cpus[0].location = Some(CpuLocation::new(0, 0));
+        topology.cpus[1].location = Some(CpuLocation::new(0, 0));
+        assert_eq!(
+            tree.set_cpu_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "cluster0/core0 used twice"
+            )))
+        );
+
+        topology.cpus[1].location = Some(CpuLocation::new(0, 1));
+        let l2 = topology.add_cache(Cache::new_unified(2, CacheGeometry::default()));
+        topology.caches[l2].next_level = Some(l2);
+        assert_eq!(
+            tree.set_cpu_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "level 2 cache after level 2"
+            )))
+        );
+
+        // Nothing is written by the failed calls
+        assert!(tree.find_node_by_path("/cpus").is_none());
+        topology.caches[l2].next_level = None;
+        assert!(tree.set_cpu_topology(&topology).is_ok());
+    }
+
+    #[test]
+    fn test_cpu_topology_write_in_place() {
+        let dts = br#"/dts-v1/;
+/ {
+	cpus {
+		#address-cells = <1>;
+		#size-cells = <0>;
+		timebase-frequency = <10000000>;
+
+		cpu0: cpu@0 {
+			phandle = <1>;
+			device_type = "cpu";
+			compatible = "riscv";
+			reg = <0>;
+			riscv,isa = "rv64imafdc";
+			i-cache-size = <0x8000>;
+			next-level-cache = <&l2>;
+
+			intc0: interrupt-controller {
+				phandle = <2>;
+				#interrupt-cells = <1>;
+				compatible = "riscv,cpu-intc";
+				interrupt-controller;
+			};
+		};
+
+		cpu1: cpu@1 {
+			phandle = <3>;
+			device_type = "cpu";
+			compatible = "riscv";
+			reg = <1>;
+			next-level-cache = <&l2>;
+		};
+
+		l2: l2-cache {
+			phandle = <4>;
+			compatible = "cache";
+			cache-level = <2>;
+		};
+	};
+
+	pmu {
+		interrupts-extended = <&intc0 13>;
+		interrupt-affinity = <&cpu0>;
+	};
+};
+"#;
+        let mut tree = DeviceTree::from_dts_bytes(dts);
+        let mut topology = tree.cpu_topology().unwrap();
+        topology.cpus[0].capacity_dmips_mhz = Some(1024);
+        topology.cpus[0].cache.instruction = None;
+        tree.set_cpu_topology(&topology).unwrap();
+        assert_eq!(tree.check_phandles(), Ok(()));
+
+        // The CPU nodes keep their phandles, sub nodes and other properties
+        let cpu0 = tree.find_node_by_path("/cpus/cpu@0").unwrap();
+        let node = tree.node(cpu0);
+        assert_eq!(node.read_u32("phandle"), Ok(1));
+        assert_eq!(node.read_string("riscv,isa"), Ok("rv64imafdc"));
+        assert_eq!(node.read_u32("capacity-dmips-mhz"), Ok(1024));
+        assert!(node.find_property("i-cache-size").is_none());
+        assert!(tree
+            .find_node_by_path("/cpus/cpu@0/interrupt-controller")
+            .is_some());
+        let cpus = tree.find_node_by_path("/cpus").unwrap();
+        assert_eq!(tree.node(cpus).read_u32("timebase-frequency"), Ok(10000000));
+
+        // The cache is replaced
+        assert!(tree.find_node_by_path("/cpus/l2-cache").is_none());
+        let mut parsed = tree.cpu_topology().unwrap();
+        clear_nodes(&mut topology);
+        clear_nodes(&mut parsed);
+        assert_eq!(parsed, topology);
+
+        // A CPU still referred to can't be removed
+        topology.cpus.remove(0);
+        assert_eq!(
+            tree.set_cpu_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "/cpus/cpu@0/interrupt-controller is referred to by /pmu:interrupts-extended"
+            )))
+        );
+        assert!(tree.find_node_by_path("/cpus/cpu@0").is_some());
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::NodeId;
+use crate::phandle::PhandleReference;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+/// A dependency of a consumer node on a supplier node, which must be probed
+/// before the consumer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dependency {
+    pub consumer: NodeId,
+    pub supplier: NodeId,
+    /// The property of the consumer referring to the supplier, `None` if the
+    /// supplier is the parent of the consumer
+    pub property: Option<String>,
+}
+
+/// The supplier/consumer dependencies between the nodes of a `DeviceTree`,
+/// like the device links created by `fw_devlink` of Linux.
+///
+/// A node depends on:
+///   - Its parent node
+///   - The nodes referred to by "interrupt-parent", "interrupts-extended",
+///     "clocks", "resets", "power-domains", "*-supply", "gpios", "*-gpios"
+///     and "pinctrl-*"
+///   - Its interrupt parent if it has "interrupts", see
+///     `DeviceTree::interrupt_parent`
+///
+/// Like Linux, a node never depends on itself or its descendants.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::DeviceTree;
+///
+/// let dts = r#"/dts-v1/;
+/// / {
+///     uart {
+///         clocks = <&clk>;
+///     };
+///     clk: clock {
+///         phandle = <1>;
+///         #clock-cells = <0>;
+///     };
+/// };"#;
+/// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+/// let graph = tree.dependency_graph();
+///
+/// let order: Vec<String> = graph
+///     .probe_order()
+///     .unwrap()
+///     .into_iter()
+///     .map(|id| tree.path_of(id))
+///     .collect();
+/// assert_eq!(order, vec!["/", "/clock", "/uart"]);
+/// ```
+pub struct DependencyGraph {
+    nodes: Vec<NodeId>,
+    paths: BTreeMap<NodeId, String>,
+    dependencies: Vec<Dependency>,
+    // The indexes of the dependencies of each consumer and supplier
+    by_consumer: BTreeMap<NodeId, Vec<usize>>,
+    by_supplier: BTreeMap<NodeId, Vec<usize>>,
+}
+
+impl DependencyGraph {
+    /// Build the dependency graph of a tree.
+    pub fn new(tree: &DeviceTree) -> Self {
+        let nodes = tree.subtree(tree.root());
+        let mut dependencies = vec![];
+        for id in &nodes {
+            let id = *id;
+            let mut add = |supplier: NodeId, property: Option<&str>| {
+                if !tree.is_within(supplier, id) {
+                    dependencies.push(Dependency {
+                        consumer: id,
+                        supplier,
+                        property: property.map(String::from),
+                    });
+                }
+            };
+
+            if let Some(parent) = tree.parent(id) {
+                add(parent, None);
+            }
+            let node = tree.node(id);
+            for prop in &node.properties {
+                if !DependencyGraph::is_supplier_property(&prop.name) {
+                    continue;
+                }
+                for (_, phandle) in PhandleReference::find_in_property(tree, id, &prop.name) {
+                    if let Some(supplier) = tree.find_node_by_phandle(phandle) {
+                        add(supplier, Some(&prop.name));
+                    }
+                }
+            }
+            if node.find_property("interrupts").is_some()
+                && node.find_property("interrupt-parent").is_none()
+            {
+                if let Some(supplier) = tree.interrupt_parent(id) {
+                    add(supplier, Some("interrupts"));
+                }
+            }
+        }
+
+        let mut by_consumer: BTreeMap<NodeId, Vec<usize>> = BTreeMap::new();
+        let mut by_supplier: BTreeMap<NodeId, Vec<usize>> = BTreeMap::new();
+        for (i, dependency) in dependencies.iter().enumerate() {
+            by_consumer.entry(dependency.consumer).or_default().push(i);
+            by_supplier.entry(dependency.supplier).or_default().push(i);
+        }
+        let paths = nodes.iter().map(|id| (*id, tree.path_of(*id))).collect();
+        DependencyGraph {
+            nodes,
+            paths,
+            dependencies,
+            by_consumer,
+            by_supplier,
+        }
+    }
+
+    fn is_supplier_property(name: &str) -> bool {
+        matches!(
+            name,
+            "interrupt-parent"
+                | "interrupts-extended"
+                | "clocks"
+                | "resets"
+                | "power-domains"
+                | "gpios"
+        ) || name.ends_with("-supply")
+            || name.ends_with("-gpios")
+            || name.ends_with("-gpio")
+            || name
+                .strip_prefix("pinctrl-")
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    /// Get all the dependencies, in the depth-first order of the consumers.
+    pub fn dependencies(&self) -> &[Dependency] {
+        &self.dependencies
+    }
+
+    /// Get the dependencies of a node on its suppliers.
+    pub fn suppliers(&self, id: NodeId) -> Vec<&Dependency> {
+        self.get(&self.by_consumer, id)
+    }
+
+    /// Get the dependencies of the consumers on a node.
+    pub fn consumers(&self, id: NodeId) -> Vec<&Dependency> {
+        self.get(&self.by_supplier, id)
+    }
+
+    fn get(&self, index: &BTreeMap<NodeId, Vec<usize>>, id: NodeId) -> Vec<&Dependency> {
+        index
+            .get(&id)
+            .map(|indexes| indexes.iter().map(|i| &self.dependencies[*i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Get the order to probe the nodes, where every node comes after its
+    /// suppliers. The nodes that don't depend on each other keep the
+    /// depth-first order of the tree.
+    ///
+    /// It fails with the first dependency cycle found, see `cycles()`.
+    pub fn probe_order(&self) -> Result<Vec<NodeId>, DeviceTreeError> {
+        let position: BTreeMap<NodeId, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i))
+            .collect();
+        let mut waiting: BTreeMap<NodeId, usize> = self
+            .nodes
+            .iter()
+            .map(|id| (*id, self.suppliers(*id).len()))
+            .collect();
+
+        // Always probe the ready node that comes first in the tree
+        let mut ready: BinaryHeap<Reverse<usize>> = waiting
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(id, _)| Reverse(position[id]))
+            .collect();
+        let mut order = vec![];
+        while let Some(Reverse(i)) = ready.pop() {
+            let id = self.nodes[i];
+            order.push(id);
+            for dependency in self.consumers(id) {
+                let count = waiting.get_mut(&dependency.consumer).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(Reverse(position[&dependency.consumer]));
+                }
+            }
+        }
+
+        if order.len() < self.nodes.len() {
+            // Describe the cycle like "/a:clocks -> /b:resets -> /a"
+            let cycle = self.cycles().into_iter().next().unwrap();
+            let mut text: Vec<String> = cycle
+                .iter()
+                .map(|d| {
+                    let property = d.property.as_deref().unwrap_or("parent");
+                    format!("{}:{}", self.paths[&d.consumer], property)
+                })
+                .collect();
+            text.push(self.paths[&cycle[0].consumer].clone());
+            return Err(DeviceTreeError::DependencyCycle(text.join(" -> ")));
+        }
+        Ok(order)
+    }
+
+    /// Find the dependency cycles, one for each group of nodes depending on
+    /// each other. A cycle is the list of dependencies where the supplier of
+    /// each dependency is the consumer of the next one, and the supplier of
+    /// the last one is the consumer of the first one.
+    pub fn cycles(&self) -> Vec<Vec<Dependency>> {
+        let mut cycles = vec![];
+        for group in self.strongly_connected() {
+            if group.len() < 2 {
+                continue;
+            }
+            cycles.push(self.find_cycle(&group));
+        }
+        cycles
+    }
+
+    // Walk the dependencies inside a group of nodes depending on each other
+    // from its first node until a node is visited again.
+    fn find_cycle(&self, group: &BTreeSet<NodeId>) -> Vec<Dependency> {
+        let mut path: Vec<&Dependency> = vec![];
+        let mut visited: Vec<NodeId> = vec![];
+        let mut id = *group.iter().next().unwrap();
+        while !visited.contains(&id) {
+            visited.push(id);
+            let dependency = self
+                .suppliers(id)
+                .into_iter()
+                .find(|d| group.contains(&d.supplier))
+                .unwrap();
+            path.push(dependency);
+            id = dependency.supplier;
+        }
+        let start = visited.iter().position(|visited| *visited == id).unwrap();
+        path[start..].iter().map(|d| (*d).clone()).collect()
+    }
+
+    // Group the nodes by strongly connected components, with Tarjan's
+    // algorithm.
+    fn strongly_connected(&self) -> Vec<BTreeSet<NodeId>> {
+        struct State {
+            index: BTreeMap<NodeId, usize>,
+            low: BTreeMap<NodeId, usize>,
+            stack: Vec<NodeId>,
+            on_stack: BTreeSet<NodeId>,
+            groups: Vec<BTreeSet<NodeId>>,
+        }
+
+        fn connect(graph: &DependencyGraph, id: NodeId, state: &mut State) {
+            let index = state.index.len();
+            state.index.insert(id, index);
+            state.low.insert(id, index);
+            state.stack.push(id);
+            state.on_stack.insert(id);
+
+            for dependency in graph.suppliers(id) {
+                let supplier = dependency.supplier;
+                if !state.index.contains_key(&supplier) {
+                    connect(graph, supplier, state);
+                    let low = state.low[&id].min(state.low[&supplier]);
+                    state.low.insert(id, low);
+                } else if state.on_stack.contains(&supplier) {
+                    let low = state.low[&id].min(state.index[&supplier]);
+                    state.low.insert(id, low);
+                }
+            }
+
+            if state.low[&id] == state.index[&id] {
+                let mut group = BTreeSet::new();
+                while let Some(member) = state.stack.pop() {
+                    state.on_stack.remove(&member);
+                    group.insert(member);
+                    if member == id {
+                        break;
+                    }
+                }
+                state.groups.push(group);
+            }
+        }
+
+        let mut state = State {
+            index: BTreeMap::new(),
+            low: BTreeMap::new(),
+            stack: vec![],
+            on_stack: BTreeSet::new(),
+            groups: vec![],
+        };
+        for id in &self.nodes {
+            if !state.index.contains_key(id) {
+                connect(self, *id, &mut state);
+            }
+        }
+        state.groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dependency_probe_order() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let tree = DeviceTree::from_dts_bytes(&dts);
+        let graph = tree.dependency_graph();
+
+        let uart = tree.find_node_by_path("/pl011@9000000").unwrap();
+        let suppliers: Vec<(String, Option<String>)> = graph
+            .suppliers(uart)
+            .iter()
+            .map(|d| (tree.path_of(d.supplier), d.property.clone()))
+            .collect();
+        assert_eq!(
+            suppliers,
+            vec![
+                (String::from("/"), None),
+                (String::from("/apb-pclk"), Some(String::from("clocks"))),
+                (String::from("/intc"), Some(String::from("interrupts"))),
+            ]
+        );
+
+        // The root refers to its descendant "/intc", and "/intc" inherits
+        // itself as the interrupt parent, neither is a dependency
+        assert!(graph.suppliers(tree.root()).is_empty());
+        let intc = tree.find_node_by_path("/intc").unwrap();
+        assert_eq!(graph.suppliers(intc).len(), 1);
+
+        let order = graph.probe_order().unwrap();
+        assert_eq!(order.len(), tree.subtree(tree.root()).len());
+        for dependency in graph.dependencies() {
+            let consumer = order.iter().position(|id| *id == dependency.consumer);
+            let supplier = order.iter().position(|id| *id == dependency.supplier);
+            assert!(supplier < consumer);
+        }
+        assert!(graph.cycles().is_empty());
+    }
+
+    #[test]
+    fn test_dependency_cycles() {
+        let tree = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	uart {
+		clocks = <&clk>;
+	};
+
+	clk: clock {
+		phandle = <1>;
+		#clock-cells = <0>;
+		vdd-supply = <&reg>;
+	};
+
+	reg: regulator {
+		phandle = <2>;
+		pinctrl-0 = <&pins>;
+	};
+
+	pinctrl {
+		pins: pins {
+			phandle = <3>;
+			enable-gpios = <&gpio 1 0>;
+		};
+	};
+
+	gpio: gpio {
+		phandle = <4>;
+		#gpio-cells = <2>;
+		clocks = <&clk>;
+	};
+};
+"#,
+        );
+        let graph = tree.dependency_graph();
+
+        let cycles = graph.cycles();
+        assert_eq!(cycles.len(), 1);
+        let cycle: Vec<(String, String, String)> = cycles[0]
+            .iter()
+            .map(|d| {
+                (
+                    tree.path_of(d.consumer),
+                    d.property.clone().unwrap(),
+                    tree.path_of(d.supplier),
+                )
+            })
+            .collect();
+        let edge = |consumer: &str, property: &str, supplier: &str| {
+            (
+                String::from(consumer),
+                String::from(property),
+                String::from(supplier),
+            )
+        };
+        assert_eq!(
+            cycle,
+            vec![
+                edge("/clock", "vdd-supply", "/regulator"),
+                edge("/regulator", "pinctrl-0", "/pinctrl/pins"),
+                edge("/pinctrl/pins", "enable-gpios", "/gpio"),
+                edge("/gpio", "clocks", "/clock"),
+            ]
+        );
+        assert_eq!(
+            graph.probe_order(),
+            Err(DeviceTreeError::DependencyCycle(String::from(
+                "/clock:vdd-supply -> /regulator:pinctrl-0 -> /pinctrl/pins:enable-gpios \
+                 -> /gpio:clocks -> /clock"
+            )))
+        );
+    }
+}
@@ -1,78 +1,2366 @@
 // Copyright (c) 2023, Michael Zhao
 // SPDX-License-Identifier: MIT
 
+use crate::address::{Address, DmaRange};
+use crate::cpu::CpuTopology;
+use crate::dependency::DependencyGraph;
+use crate::diff::{DiffOptions, TreeDiff};
 use crate::dtb_generator::DtbGenerator;
 use crate::dtb_parser::DtbParser;
 use crate::dts_generator::DtsGenerator;
 use crate::dts_parser::DtsParser;
-use crate::node::Node;
+use crate::error::DeviceTreeError;
+use crate::graft::{Graft, GraftOptions};
+use crate::iommu::Iommu;
+use crate::irq::{InterruptSpecifier, Irq};
+use crate::memory::{MemoryMap, MemoryRegion};
+use crate::node::{Node, NodeId, NodeMut, NodeRef};
+use crate::numa::NumaTopology;
+use crate::of::{self, OfDeviceId};
+use crate::phandle::{CellCount, PhandleArgs, PhandleIndex, PhandleNumbering, PhandleReference};
+use crate::property::Property;
+use crate::reference::ReferenceGraph;
 use crate::reservation::Reservation;
+use crate::selector::Selector;
+use crate::virt::VirtMachine;
+use crate::visit::{self, BreadthFirstIter, DepthFirstIter, PropertyIter, Visitor, VisitorMut};
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 
+// A node stored in the arena of a `DeviceTree`, the sub nodes of the node are
+// linked with `children` and `node.sub_nodes` is always empty.
+struct NodeEntry {
+    node: Node,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
 /// A device tree.
 ///
 /// The `Tree` struct consists of:
 ///   - The root node of the device tree (mandatory)
 ///   - And the memory reservation blocks (optional)
+///
+/// The nodes are stored in an arena owned by the tree. Each node is
+/// identified by a `NodeId`, and is accessed with `node()` or `node_mut()`.
+/// The structure of the tree is navigated with `root()`, `parent()` and
+/// `children()`.
 pub struct DeviceTree {
-    pub reservations: Vec<Arc<Mutex<Reservation>>>,
-    pub root: Arc<Mutex<Node>>,
+    pub reservations: Vec<Reservation>,
+    nodes: Vec<Option<NodeEntry>>,
+    // The slots of the removed nodes, reused by the nodes added later
+    free: Vec<usize>,
+    root: NodeId,
+    phandles: PhandleIndex,
 }
 
-impl DeviceTree {
-    /// Create a new device tree with a vector of reservation block and the root node.
-    /// If there is not any reservation block, the vector should be empty.
+impl DeviceTree {
+    /// Create a new device tree with a vector of reservation block and the root node.
+    /// If there is not any reservation block, the vector should be empty.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Reservation;
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let tree = DeviceTree::new(vec![], Node::new(""));
+    ///
+    /// assert_eq!(format!("{}", tree), "/dts-v1/;\n\n/ {\n};\n\n");
+    /// ```
+    pub fn new(reservations: Vec<Reservation>, root: Node) -> Self {
+        let mut tree = DeviceTree {
+            reservations,
+            nodes: vec![],
+            free: vec![],
+            root: NodeId(0),
+            phandles: PhandleIndex::default(),
+        };
+        tree.root = tree.insert_entry(root, None);
+        tree
+    }
+
+    // Move a detached node and all its sub nodes into the arena.
+    fn insert_entry(&mut self, mut node: Node, parent: Option<NodeId>) -> NodeId {
+        let sub_nodes = std::mem::take(&mut node.sub_nodes);
+        let entry = Some(NodeEntry {
+            node,
+            parent,
+            children: Vec::with_capacity(sub_nodes.len()),
+        });
+        let id = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = entry;
+                NodeId(slot)
+            }
+            None => {
+                self.nodes.push(entry);
+                NodeId(self.nodes.len() - 1)
+            }
+        };
+        if let Some(phandle) = self.node(id).phandle() {
+            self.phandles.insert(phandle, id);
+        }
+        for sub_node in sub_nodes {
+            let child = self.insert_entry(sub_node, Some(id));
+            self.entry_mut(id).children.push(child);
+        }
+        id
+    }
+
+    fn entry(&self, id: NodeId) -> &NodeEntry {
+        match self.nodes.get(id.0) {
+            Some(Some(entry)) => entry,
+            _ => panic!("node {:?} is not in the tree", id),
+        }
+    }
+
+    fn entry_mut(&mut self, id: NodeId) -> &mut NodeEntry {
+        match self.nodes.get_mut(id.0) {
+            Some(Some(entry)) => entry,
+            _ => panic!("node {:?} is not in the tree", id),
+        }
+    }
+
+    /// Get the `NodeId` of the root node.
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Check if a node is in the tree, a node is no longer in the tree after
+    /// it is removed.
+    pub fn contains(&self, id: NodeId) -> bool {
+        matches!(self.nodes.get(id.0), Some(Some(_)))
+    }
+
+    /// Get a node of the tree.
+    ///
+    /// The `sub_nodes` of the returned node is always empty, use `children()`
+    /// to get the sub nodes. Panic if the node is not in the tree.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_property(Property::new_u32("#address-cells", 2));
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let root = tree.node(tree.root());
+    /// assert_eq!(root.properties[0].name, "#address-cells");
+    /// ```
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.entry(id).node
+    }
+
+    /// Get a node of the tree for modification.
+    ///
+    /// The node is borrowed through a `NodeMut`. The sub nodes put in
+    /// `sub_nodes` of the node are moved into the tree after its other sub
+    /// nodes once the `NodeMut` is dropped. Panic if the node is not in the
+    /// tree.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut tree = DeviceTree::new(vec![], Node::new(""));
+    ///
+    /// let root = tree.root();
+    /// tree.node_mut(root).add_property(Property::new_u32("#size-cells", 2));
+    /// tree.node_mut(root).add_sub_node(Node::new("cpus"));
+    ///
+    /// assert_eq!(tree.node(root).properties.len(), 1);
+    /// assert_eq!(tree.children(root).len(), 1);
+    /// assert!(tree.find_node_by_path("/cpus").is_some());
+    /// ```
+    pub fn node_mut(&mut self, id: NodeId) -> NodeMut<'_> {
+        // The node may change its phandle
+        self.refresh_phandles();
+        self.phandles.mark_stale(id);
+        self.entry(id);
+        NodeMut::new(self, id)
+    }
+
+    // Get a node of the tree for modification, without taking over its sub
+    // nodes.
+    pub(crate) fn node_mut_unchecked(&mut self, id: NodeId) -> &mut Node {
+        &mut self.entry_mut(id).node
+    }
+
+    // Move the sub nodes put in `sub_nodes` of a node of the tree into the
+    // tree.
+    pub(crate) fn adopt_sub_nodes(&mut self, id: NodeId) {
+        let sub_nodes = std::mem::take(&mut self.entry_mut(id).node.sub_nodes);
+        for sub_node in sub_nodes {
+            self.add_sub_node(id, sub_node);
+        }
+    }
+
+    // Index the phandles of the nodes that have been borrowed mutably.
+    fn refresh_phandles(&mut self) {
+        for id in self.phandles.take_stale() {
+            if !self.contains(id) {
+                continue;
+            }
+            if let Some(phandle) = self.node(id).phandle() {
+                self.phandles.insert(phandle, id);
+            }
+        }
+    }
+
+    /// Get the parent of a node, the root node has no parent.
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.entry(id).parent
+    }
+
+    /// Get the sub nodes of a node in order.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("cpus"));
+    /// root.add_sub_node(Node::new("memory"));
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let children = tree.children(tree.root());
+    /// assert_eq!(tree.node(children[1]).name, "memory");
+    /// assert_eq!(tree.parent(children[1]), Some(tree.root()));
+    /// ```
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.entry(id).children
+    }
+
+    /// Get a `NodeRef` of a node, which can navigate to the parent and sub
+    /// nodes and tell the path of the node. Panic if the node is not in the tree.
+    pub fn node_ref(&self, id: NodeId) -> NodeRef<'_> {
+        assert!(self.contains(id), "node {:?} is not in the tree", id);
+        NodeRef::new(self, id)
+    }
+
+    /// Get the full path of a node, the path of the root node is "/".
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// let mut cpus = Node::new("cpus");
+    /// cpus.add_sub_node(Node::new_with_label("cpu@0", "cpu0"));
+    /// root.add_sub_node(cpus);
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let cpu = tree.find_node_by_label("cpu0").unwrap();
+    ///
+    /// assert_eq!(tree.path_of(cpu), "/cpus/cpu@0");
+    /// assert_eq!(tree.path_of(tree.root()), "/");
+    /// ```
+    pub fn path_of(&self, id: NodeId) -> String {
+        let mut names = vec![];
+        let mut node = id;
+        while let Some(parent) = self.parent(node) {
+            names.push(self.node(node).name.as_str());
+            node = parent;
+        }
+        if names.is_empty() {
+            return String::from("/");
+        }
+        names
+            .iter()
+            .rev()
+            .fold(String::new(), |path, name| path + "/" + name)
+    }
+
+    /// Iterate over all the nodes of the tree in depth-first order.
+    ///
+    /// Each item is the path, the depth and the node, the depth of the root
+    /// node is 0.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// let mut cpus = Node::new("cpus");
+    /// cpus.add_sub_node(Node::new("cpu@0"));
+    /// root.add_sub_node(cpus);
+    /// root.add_sub_node(Node::new("memory"));
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let paths: Vec<(String, usize)> = tree
+    ///     .iter_depth_first()
+    ///     .map(|(path, depth, _node)| (path, depth))
+    ///     .collect();
+    ///
+    /// assert_eq!(paths[2], (String::from("/cpus/cpu@0"), 2));
+    /// assert_eq!(paths[3], (String::from("/memory"), 1));
+    /// ```
+    pub fn iter_depth_first(&self) -> DepthFirstIter<'_> {
+        DepthFirstIter::new(self, self.root)
+    }
+
+    /// Iterate over the subtree at a node in depth-first order, the depth of
+    /// the node is 0.
+    pub fn iter_depth_first_from(&self, id: NodeId) -> DepthFirstIter<'_> {
+        DepthFirstIter::new(self, id)
+    }
+
+    /// Iterate over all the nodes of the tree in breadth-first order.
+    ///
+    /// Each item is the path, the depth and the node, the depth of the root
+    /// node is 0.
+    pub fn iter_breadth_first(&self) -> BreadthFirstIter<'_> {
+        BreadthFirstIter::new(self, self.root)
+    }
+
+    /// Iterate over the subtree at a node in breadth-first order, the depth
+    /// of the node is 0.
+    pub fn iter_breadth_first_from(&self, id: NodeId) -> BreadthFirstIter<'_> {
+        BreadthFirstIter::new(self, id)
+    }
+
+    /// Iterate over the properties of all the nodes of the tree.
+    ///
+    /// Each item is the path of the node and the property.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// let mut uart = Node::new("uart@0");
+    /// uart.add_property(Property::new_str("status", "okay"));
+    /// root.add_sub_node(uart);
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let (path, prop) = tree.iter_properties().next().unwrap();
+    ///
+    /// assert_eq!(path, "/uart@0");
+    /// assert_eq!(prop.name, "status");
+    /// ```
+    pub fn iter_properties(&self) -> PropertyIter<'_> {
+        PropertyIter::new(self.iter_depth_first())
+    }
+
+    /// Walk the tree with a `Visitor`.
+    pub fn walk<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        visit::walk(self, self.root, "/", 0, visitor);
+    }
+
+    /// Walk the tree with a `VisitorMut` that can modify the nodes.
+    pub fn walk_mut<V: VisitorMut + ?Sized>(&mut self, visitor: &mut V) {
+        visit::walk_mut(self, self.root, "/", 0, visitor);
+    }
+
+    /// Find a sub node of a node by name.
+    pub fn find_child_by_name(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        self.children(id)
+            .iter()
+            .find(|child| self.node(**child).name == name)
+            .copied()
+    }
+
+    /// Find the next node compatible with a string after the node `from`, like
+    /// `of_find_compatible_node` of Linux.
+    ///
+    /// The nodes are searched in depth-first order, from the root if `from`
+    /// is `None`. If `device_type` is given, the "device_type" property of
+    /// the node must match it too.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// for name in ["gpio@9020000", "gpio@9030000"] {
+    ///     let mut gpio = Node::new(name);
+    ///     gpio.add_property(Property::new_strs("compatible", vec!["arm,pl061", "arm,primecell"]));
+    ///     root.add_sub_node(gpio);
+    /// }
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let first = tree.find_compatible_node(None, None, "arm,pl061").unwrap();
+    /// assert_eq!(tree.node(first).name, "gpio@9020000");
+    ///
+    /// let second = tree.find_compatible_node(Some(first), None, "arm,pl061").unwrap();
+    /// assert_eq!(tree.node(second).name, "gpio@9030000");
+    ///
+    /// assert_eq!(tree.find_compatible_node(Some(second), None, "arm,pl061"), None);
+    /// ```
+    pub fn find_compatible_node(
+        &self,
+        from: Option<NodeId>,
+        device_type: Option<&str>,
+        compatible: &str,
+    ) -> Option<NodeId> {
+        let start = match from {
+            Some(from) if self.contains(from) => self.next_depth_first(from),
+            Some(_) => None,
+            None => Some(self.root),
+        };
+        std::iter::successors(start, |id| self.next_depth_first(*id))
+            .find(|id| of::match_score(self.node(*id), Some(compatible), device_type, None) > 0)
+    }
+
+    // Get the node after a node in depth-first order: the first sub node, or
+    // else the next sibling of the node or of its closest ancestor having one.
+    fn next_depth_first(&self, id: NodeId) -> Option<NodeId> {
+        if let Some(child) = self.children(id).first() {
+            return Some(*child);
+        }
+        let mut id = id;
+        while let Some(parent) = self.parent(id) {
+            let siblings = self.children(parent);
+            let index = siblings.iter().position(|sibling| *sibling == id)?;
+            if let Some(next) = siblings.get(index + 1) {
+                return Some(*next);
+            }
+            id = parent;
+        }
+        None
+    }
+
+    /// Find a sub node of a node by the name without the unit address, like
+    /// `of_get_child_by_name` of Linux.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node};
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("memory@40000000"));
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let memory = tree.get_child_by_name(tree.root(), "memory").unwrap();
+    /// assert_eq!(tree.node(memory).name, "memory@40000000");
+    /// ```
+    pub fn get_child_by_name(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        self.children(id)
+            .iter()
+            .find(|child| self.node(**child).base_name() == name)
+            .copied()
+    }
+
+    /// Get the next available sub node of a node after the sub node `prev`,
+    /// like `of_get_next_available_child` of Linux.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// let mut uart0 = Node::new("serial@9000000");
+    /// uart0.add_property(Property::new_str("status", "disabled"));
+    /// root.add_sub_node(uart0);
+    /// root.add_sub_node(Node::new("serial@9040000"));
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let uart1 = tree.get_next_available_child(tree.root(), None).unwrap();
+    /// assert_eq!(tree.node(uart1).name, "serial@9040000");
+    /// assert_eq!(tree.get_next_available_child(tree.root(), Some(uart1)), None);
+    /// ```
+    pub fn get_next_available_child(&self, id: NodeId, prev: Option<NodeId>) -> Option<NodeId> {
+        let children = self.children(id);
+        let start = match prev {
+            Some(prev) => children.iter().position(|child| *child == prev)? + 1,
+            None => 0,
+        };
+        children[start..]
+            .iter()
+            .copied()
+            .find(|child| self.node(*child).is_available())
+    }
+
+    /// Find the entry of a match table that best matches a node, like
+    /// `of_match_node` of Linux, see `OfDeviceId`.
+    ///
+    /// Matching an earlier string of the "compatible" property is better,
+    /// then matching the device type and the name. The first entry wins if
+    /// several match equally well.
+    pub fn match_node<'m, 'a, T>(
+        &self,
+        matches: &'m [OfDeviceId<'a, T>],
+        id: NodeId,
+    ) -> Option<&'m OfDeviceId<'a, T>> {
+        let node = self.node(id);
+        let mut best: Option<(&OfDeviceId<'a, T>, u32)> = None;
+        for entry in matches {
+            let score = entry.score(node);
+            if score > best.map_or(0, |(_, best_score)| best_score) {
+                best = Some((entry, score));
+            }
+        }
+        best.map(|(entry, _)| entry)
+    }
+
+    /// Find the node with a phandle, like `of_find_node_by_phandle` of Linux.
+    ///
+    /// The phandle of a node is the value of its "phandle" property, or the
+    /// legacy "linux,phandle" property. If several nodes have the phandle,
+    /// the first one in depth-first order is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// let mut intc = Node::new("intc");
+    /// intc.add_property(Property::new_u32("phandle", 1));
+    /// root.add_sub_node(intc);
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let intc = tree.find_node_by_phandle(1).unwrap();
+    /// assert_eq!(tree.node(intc).name, "intc");
+    /// assert_eq!(tree.find_node_by_phandle(2), None);
+    /// ```
+    pub fn find_node_by_phandle(&self, phandle: u32) -> Option<NodeId> {
+        if phandle == 0 || phandle == u32::MAX {
+            return None;
+        }
+        let ids: Vec<NodeId> = self
+            .phandles
+            .candidates(phandle)
+            .filter(|id| self.contains(*id) && self.node(*id).phandle() == Some(phandle))
+            .collect();
+        if ids.len() > 1 {
+            // Prefer the first node in depth-first order
+            return self
+                .subtree(self.root)
+                .into_iter()
+                .find(|id| ids.contains(id));
+        }
+        ids.first().copied()
+    }
+
+    /// Get the phandle of a node, giving the node a new phandle if it has
+    /// none.
+    ///
+    /// The new phandle is the next value after the largest phandle in the
+    /// tree, and is set to the "phandle" property of the node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// let mut intc = Node::new("intc");
+    /// intc.add_property(Property::new_u32("phandle", 5));
+    /// root.add_sub_node(intc);
+    /// root.add_sub_node(Node::new("gpio"));
+    /// let mut tree = DeviceTree::new(vec![], root);
+    ///
+    /// let intc = tree.find_node_by_path("/intc").unwrap();
+    /// let gpio = tree.find_node_by_path("/gpio").unwrap();
+    /// assert_eq!(tree.ensure_phandle(intc), 5);
+    /// assert_eq!(tree.ensure_phandle(gpio), 6);
+    /// assert_eq!(tree.find_node_by_phandle(6), Some(gpio));
+    /// ```
+    pub fn ensure_phandle(&mut self, id: NodeId) -> u32 {
+        if let Some(phandle) = self.node(id).phandle() {
+            return phandle;
+        }
+        self.refresh_phandles();
+        let max = self
+            .subtree(self.root)
+            .into_iter()
+            .filter_map(|id| self.node(id).phandle())
+            .filter(|phandle| *phandle != u32::MAX)
+            .max()
+            .unwrap_or(0);
+        // Wrap around to the lowest free value if the largest one is taken
+        let phandle = match max.checked_add(1).filter(|p| *p != u32::MAX) {
+            Some(phandle) => phandle,
+            None => (1..u32::MAX)
+                .find(|p| self.find_node_by_phandle(*p).is_none())
+                .unwrap(),
+        };
+        self.entry_mut(id)
+            .node
+            .set_property(Property::new_u32("phandle", phandle));
+        self.phandles.insert(phandle, id);
+        phandle
+    }
+
+    /// Find all the phandles held by the properties of the tree.
+    ///
+    /// The phandles are found in the well-known properties that refer to
+    /// other nodes, like "interrupt-parent", "clocks", "*-gpios",
+    /// "*-supply", "interrupt-map" and the nexus maps like "gpio-map". The
+    /// number of cells after each phandle is read from the `#*-cells` of the
+    /// node it refers to.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, PhandleReference};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     clk: clock-controller {
+    ///         phandle = <1>;
+    ///         #clock-cells = <1>;
+    ///     };
+    ///     serial {
+    ///         clocks = <&clk 3>, <&clk 5>;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let serial = tree.find_node_by_path("/serial").unwrap();
+    ///
+    /// let references = tree.phandle_references();
+    /// assert_eq!(references.len(), 2);
+    /// assert_eq!(
+    ///     references[1],
+    ///     PhandleReference { node: serial, property: String::from("clocks"), cell: 2, phandle: 1 }
+    /// );
+    /// ```
+    pub fn phandle_references(&self) -> Vec<PhandleReference> {
+        PhandleReference::find_all(self)
+    }
+
+    /// Find the phandles used by more than one node, with the nodes using
+    /// them in depth-first order.
+    pub fn duplicate_phandles(&self) -> Vec<(u32, Vec<NodeId>)> {
+        let mut nodes: BTreeMap<u32, Vec<NodeId>> = BTreeMap::new();
+        for id in self.subtree(self.root) {
+            if let Some(phandle) = self.node(id).phandle() {
+                nodes.entry(phandle).or_default().push(id);
+            }
+        }
+        nodes.into_iter().filter(|(_, ids)| ids.len() > 1).collect()
+    }
+
+    /// Find the phandles held by the properties of the tree that refer to no
+    /// node, see `phandle_references`.
+    pub fn dangling_phandles(&self) -> Vec<PhandleReference> {
+        self.phandle_references()
+            .into_iter()
+            .filter(|reference| self.find_node_by_phandle(reference.phandle).is_none())
+            .collect()
+    }
+
+    /// Check that no phandle is used by more than one node, and every phandle
+    /// held by the properties refers to a node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, DeviceTreeError, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_property(Property::new_u32("interrupt-parent", 1));
+    /// let mut tree = DeviceTree::new(vec![], root);
+    /// assert_eq!(tree.check_phandles(), Err(DeviceTreeError::DanglingPhandle(1)));
+    ///
+    /// tree.get_or_create_node("/intc").unwrap();
+    /// tree.set_property_at("/intc", Property::new_u32("phandle", 1)).unwrap();
+    /// assert_eq!(tree.check_phandles(), Ok(()));
+    /// ```
+    pub fn check_phandles(&self) -> Result<(), DeviceTreeError> {
+        if let Some((phandle, _)) = self.duplicate_phandles().first() {
+            return Err(DeviceTreeError::DuplicatePhandle(*phandle));
+        }
+        if let Some(reference) = self.dangling_phandles().first() {
+            return Err(DeviceTreeError::DanglingPhandle(reference.phandle));
+        }
+        Ok(())
+    }
+
+    /// Build the graph of the references between the nodes by phandles and
+    /// paths, see `ReferenceGraph`.
+    pub fn reference_graph(&self) -> ReferenceGraph {
+        ReferenceGraph::new(self)
+    }
+
+    /// Get the number of cells of an address in the "reg" of a node, which
+    /// is the "#address-cells" of its parent, like `of_n_addr_cells` of
+    /// Linux.
+    ///
+    /// If the parent doesn't have "#address-cells", the closest ancestor
+    /// having it is used, and the default is 2.
+    pub fn address_cells(&self, id: NodeId) -> usize {
+        let bus = self.parent(id).unwrap_or(id);
+        Address::cells(self, bus, "#address-cells", 2)
+    }
+
+    /// Get the number of cells of a size in the "reg" of a node, which is the
+    /// "#size-cells" of its parent, like `of_n_size_cells` of Linux.
+    ///
+    /// If the parent doesn't have "#size-cells", the closest ancestor having
+    /// it is used, and the default is 1.
+    pub fn size_cells(&self, id: NodeId) -> usize {
+        let bus = self.parent(id).unwrap_or(id);
+        Address::cells(self, bus, "#size-cells", 1)
+    }
+
+    /// Translate an address of a node, which is in the address space of the
+    /// parent bus, to a CPU physical address, like `of_translate_address` of
+    /// Linux.
+    ///
+    /// The address is translated through the "ranges" of every ancestor,
+    /// where an empty "ranges" maps the addresses 1:1. On a PCI bus the
+    /// addresses have 3 cells, and the space code in the first cell must
+    /// match the range. It fails if an ancestor has no "ranges" or none of
+    /// its ranges covers the address.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     #address-cells = <2>;
+    ///     #size-cells = <2>;
+    ///     soc {
+    ///         #address-cells = <1>;
+    ///         #size-cells = <1>;
+    ///         ranges = <0x0 0x0 0x10000000 0x1000000>;
+    ///         uart@1000 {
+    ///             reg = <0x1000 0x100>;
+    ///         };
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let uart = tree.find_node_by_path("/soc/uart@1000").unwrap();
+    ///
+    /// assert_eq!(tree.translate_address(uart, &[0x1000]), Ok(0x10001000));
+    /// assert!(tree.translate_address(uart, &[0x2000000]).is_err());
+    /// ```
+    pub fn translate_address(&self, id: NodeId, addr: &[u32]) -> Result<u64, DeviceTreeError> {
+        Address::translate(self, id, addr, "ranges")
+    }
+
+    /// Get the entries of the "reg" of a node as pairs of the CPU physical
+    /// address and the size, see `translate_address`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     #address-cells = <2>;
+    ///     #size-cells = <2>;
+    ///     soc {
+    ///         #address-cells = <1>;
+    ///         #size-cells = <1>;
+    ///         ranges = <0x0 0x0 0x10000000 0x1000000>;
+    ///         uart@1000 {
+    ///             reg = <0x1000 0x100>, <0x2000 0x10>;
+    ///         };
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let uart = tree.find_node_by_path("/soc/uart@1000").unwrap();
+    ///
+    /// assert_eq!(tree.reg(uart), Ok(vec![(0x10001000, 0x100), (0x10002000, 0x10)]));
+    /// ```
+    pub fn reg(&self, id: NodeId) -> Result<Vec<(u64, u64)>, DeviceTreeError> {
+        Address::reg(self, id, "reg")
+    }
+
+    /// Read the CPU topology from "/cpus", see `CpuTopology`.
+    ///
+    /// The CPUs are the children of "/cpus" with device_type "cpu", and
+    /// their hardware IDs are the first "#address-cells" cells of "reg". The
+    /// caches are found by following "next-level-cache", where a cache node
+    /// without "cache-level" is taken to be one level above the previous
+    /// cache. The places of the CPUs are read from "cpu-map".
+    pub fn cpu_topology(&self) -> Result<CpuTopology, DeviceTreeError> {
+        CpuTopology::read(self)
+    }
+
+    /// Write a CPU topology into "/cpus": the CPU nodes, the
+    /// "l<level>-cache<n>" nodes of the caches and "cpu-map". The phandles
+    /// of the CPUs and the caches are allocated with `ensure_phandle`.
+    ///
+    /// The existing CPU nodes are matched by hardware ID and updated in
+    /// place, so their phandles, labels, sub nodes and other properties
+    /// (e.g. "timebase-frequency" or the interrupt controller of a RISC-V
+    /// hart) are kept. The CPU nodes not in the topology, the old cache
+    /// nodes and "cpu-map" are removed, and `InvalidTopology` is returned if
+    /// anything else in the tree still refers to them.
+    ///
+    /// The topology is checked first, and the tree is not changed if it
+    /// can't be written: the hardware IDs must be unique and fit
+    /// "#address-cells", "next-level-cache" must go to higher levels, the
+    /// cores must be in clusters, and no place of "cpu-map" may be used
+    /// twice.
+    pub fn set_cpu_topology(&mut self, topology: &CpuTopology) -> Result<NodeId, DeviceTreeError> {
+        topology.write(self)
+    }
+
+    /// Read the NUMA description of the tree, see `NumaTopology`.
+    ///
+    /// The NUMA nodes are collected from the "numa-node-id" of all the
+    /// nodes. If any node has it, every CPU and memory node must have it.
+    /// The "distance-matrix" of "/distance-map" must be symmetric, where a
+    /// distance given in one direction only is used for both. The distance
+    /// of a node to itself must be 10, and the others must be larger, with
+    /// 20 as the default.
+    pub fn numa_topology(&self) -> Result<NumaTopology, DeviceTreeError> {
+        NumaTopology::read(self)
+    }
+
+    /// Write a NUMA description: the "numa-node-id" of the members of the
+    /// NUMA nodes, and "/distance-map" with the full distance matrix. The
+    /// "numa-node-id" of the other nodes is removed.
+    ///
+    /// The description is checked first, and the tree is not changed if the
+    /// IDs are not unique, a node is a member twice or is in the wrong list
+    /// for its device_type, a CPU or memory node is not a member of any NUMA
+    /// node, or the distance matrix is not a valid symmetric matrix. Without
+    /// NUMA nodes, "numa-node-id" and "/distance-map" are all removed.
+    pub fn set_numa_topology(&mut self, topology: &NumaTopology) -> Result<(), DeviceTreeError> {
+        topology.write(self)
+    }
+
+    /// Add a `/memreserve/` entry.
+    ///
+    /// The label of the entry, if any, must not be used by another entry or
+    /// a node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Reservation};
+    ///
+    /// let mut tree = DeviceTree::new(vec![], Node::new(""));
+    /// tree.add_reservation(Reservation::new_with_label(0x1000, 0x1000, "fw"))
+    ///     .unwrap();
+    ///
+    /// assert!(tree.add_reservation(Reservation::new_with_label(0x0, 0x1000, "fw")).is_err());
+    /// assert_eq!(tree.find_reservation_by_label("fw").unwrap().address, 0x1000);
+    /// assert_eq!(tree.remove_reservation(0x1000, 0x1000).unwrap().address, 0x1000);
+    /// assert!(tree.reservations.is_empty());
+    /// ```
+    pub fn add_reservation(&mut self, reservation: Reservation) -> Result<(), DeviceTreeError> {
+        if let Some(label) = &reservation.label {
+            if self.find_reservation_by_label(label).is_some()
+                || self.find_node_by_label(label).is_some()
+            {
+                return Err(DeviceTreeError::DuplicateLabel(label.clone()));
+            }
+        }
+        self.reservations.push(reservation);
+        Ok(())
+    }
+
+    /// Remove the first `/memreserve/` entry with the address and the length.
+    pub fn remove_reservation(&mut self, address: u64, length: u64) -> Option<Reservation> {
+        let index = self
+            .reservations
+            .iter()
+            .position(|r| r.address == address && r.length == length)?;
+        Some(self.reservations.remove(index))
+    }
+
+    /// Find a `/memreserve/` entry by label.
+    pub fn find_reservation_by_label(&self, label: &str) -> Option<&Reservation> {
+        self.reservations
+            .iter()
+            .find(|r| r.label.as_deref() == Some(label))
+    }
+
+    /// Sort the `/memreserve/` entries by address, and then by length.
+    pub fn sort_reservations(&mut self) {
+        self.reservations.sort_by_key(|r| (r.address, r.length));
+    }
+
+    /// Sort the `/memreserve/` entries, and merge the overlapping and
+    /// adjacent ones. A merged entry keeps the label of either entry, and two
+    /// entries with labels are kept apart so that no label is lost.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Reservation};
+    ///
+    /// let reservations = vec![
+    ///     Reservation::new(0x2000, 0x1000),
+    ///     Reservation::new(0x0, 0x1000),
+    ///     Reservation::new(0x1000, 0x800),
+    /// ];
+    /// let mut tree = DeviceTree::new(reservations, Node::new(""));
+    /// tree.coalesce_reservations();
+    ///
+    /// assert_eq!(tree.reservations, vec![Reservation::new(0x0, 0x1800), Reservation::new(0x2000, 0x1000)]);
+    /// ```
+    pub fn coalesce_reservations(&mut self) {
+        let reservations = std::mem::take(&mut self.reservations);
+        self.reservations = Reservation::coalesce(reservations);
+    }
+
+    /// Check the `/memreserve/` entries against the memory nodes, and get
+    /// the indexes of the entries that are not entirely in the RAM, see
+    /// `memory_map`.
+    pub fn check_reservations(&self) -> Result<Vec<usize>, DeviceTreeError> {
+        let ram: Vec<Reservation> = MemoryMap::ram(self)?
+            .into_iter()
+            .map(|(address, size)| Reservation::new(address, size))
+            .collect();
+        let ram = Reservation::coalesce(ram);
+        Ok(self
+            .reservations
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| {
+                !ram.iter()
+                    .any(|m| m.address <= r.address && r.end() <= m.end())
+            })
+            .map(|(i, _)| i)
+            .collect())
+    }
+
+    /// Move the `/memreserve/` entries into "memreserve@<address>" children
+    /// of "/reserved-memory", which is created if it is missing. The labels
+    /// of the entries go to the nodes. The new nodes are returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property, Reservation};
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_property(Property::new_u32("#address-cells", 1));
+    /// root.add_property(Property::new_u32("#size-cells", 1));
+    /// let reservations = vec![Reservation::new_with_label(0x40000000, 0x1000, "fw")];
+    /// let mut tree = DeviceTree::new(reservations, root);
+    ///
+    /// let nodes = tree.memreserve_to_reserved_memory().unwrap();
+    /// assert_eq!(tree.path_of(nodes[0]), "/reserved-memory/memreserve@40000000");
+    /// assert_eq!(tree.find_node_by_label("fw"), Some(nodes[0]));
+    /// assert!(tree.reservations.is_empty());
+    ///
+    /// let reservations = tree.reserved_memory_to_memreserve().unwrap();
+    /// assert_eq!(reservations, vec![Reservation::new_with_label(0x40000000, 0x1000, "fw")]);
+    /// assert_eq!(tree.reservations, reservations);
+    /// ```
+    pub fn memreserve_to_reserved_memory(&mut self) -> Result<Vec<NodeId>, DeviceTreeError> {
+        MemoryMap::from_memreserve(self)
+    }
+
+    /// Move the children of "/reserved-memory" that have nothing but "reg"
+    /// into `/memreserve/` entries, keeping their labels. The children with
+    /// other properties, like "no-map" or "compatible", are left, since
+    /// `/memreserve/` can't express them. The new entries are returned.
+    pub fn reserved_memory_to_memreserve(&mut self) -> Result<Vec<Reservation>, DeviceTreeError> {
+        MemoryMap::to_memreserve(self)
+    }
+
+    /// Write the "reg" of a node from pairs of address and size, using the
+    /// "#address-cells" and "#size-cells" of the parent, see `address_cells`
+    /// and `size_cells`.
+    ///
+    /// It fails if an address or a size doesn't fit its cells.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_property(Property::new_u32("#address-cells", 2));
+    /// root.add_property(Property::new_u32("#size-cells", 1));
+    /// root.add_sub_node(Node::new("memory@100000000"));
+    /// let mut tree = DeviceTree::new(vec![], root);
+    /// let memory = tree.find_node_by_path("/memory@100000000").unwrap();
+    ///
+    /// tree.set_reg(memory, &[(0x1_0000_0000, 0x1000)]).unwrap();
+    /// assert_eq!(tree.node(memory).read_u32_array("reg", 3), Ok(vec![0x1, 0x0, 0x1000]));
+    /// assert_eq!(tree.read_reg(memory), Ok(vec![(0x1_0000_0000, 0x1000)]));
+    /// assert!(tree.set_reg(memory, &[(0x0, 0x1_0000_0000)]).is_err());
+    /// ```
+    pub fn set_reg(&mut self, id: NodeId, entries: &[(u64, u64)]) -> Result<(), DeviceTreeError> {
+        let cells = [self.address_cells(id), self.size_cells(id)];
+        let entries: Vec<Vec<u64>> = entries.iter().map(|(a, s)| vec![*a, *s]).collect();
+        Address::write_entries(self, id, "reg", &cells, &entries)
+    }
+
+    /// Read the "reg" of a node as pairs of address and size, in the address
+    /// space of the parent bus. Use `reg` for CPU physical addresses.
+    pub fn read_reg(&self, id: NodeId) -> Result<Vec<(u64, u64)>, DeviceTreeError> {
+        let cells = [self.address_cells(id), self.size_cells(id)];
+        let entries = Address::read_entries(self, id, "reg", &cells)?;
+        Ok(entries.iter().map(|e| (e[0], e[1])).collect())
+    }
+
+    /// Write the "ranges" of a node from the child bus address, the parent
+    /// bus address and the size of every range.
+    ///
+    /// The child addresses and the sizes use the "#address-cells" and
+    /// "#size-cells" of the node, and the parent addresses use the
+    /// "#address-cells" of its parent. It fails if a number doesn't fit its
+    /// cells. No ranges make an empty "ranges", which maps 1:1.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_property(Property::new_u32("#address-cells", 2));
+    /// let mut soc = Node::new("soc");
+    /// soc.add_property(Property::new_u32("#address-cells", 1));
+    /// soc.add_property(Property::new_u32("#size-cells", 1));
+    /// root.add_sub_node(soc);
+    /// let mut tree = DeviceTree::new(vec![], root);
+    /// let soc = tree.find_node_by_path("/soc").unwrap();
+    ///
+    /// tree.set_ranges(soc, &[(0x0, 0x1_0000_0000, 0x1000_0000)]).unwrap();
+    /// assert_eq!(
+    ///     tree.node(soc).read_u32_array("ranges", 4),
+    ///     Ok(vec![0x0, 0x1, 0x0, 0x1000_0000])
+    /// );
+    /// assert_eq!(tree.read_ranges(soc), Ok(vec![(0x0, 0x1_0000_0000, 0x1000_0000)]));
+    /// ```
+    pub fn set_ranges(
+        &mut self,
+        id: NodeId,
+        entries: &[(u64, u64, u64)],
+    ) -> Result<(), DeviceTreeError> {
+        self.set_ranges_property(id, "ranges", entries)
+    }
+
+    /// Write the "dma-ranges" of a node, like `set_ranges`.
+    pub fn set_dma_ranges(
+        &mut self,
+        id: NodeId,
+        entries: &[(u64, u64, u64)],
+    ) -> Result<(), DeviceTreeError> {
+        self.set_ranges_property(id, "dma-ranges", entries)
+    }
+
+    /// Read the "ranges" of a node as the child bus address, the parent bus
+    /// address and the size of every range, see `set_ranges`. An empty
+    /// "ranges" has no ranges.
+    pub fn read_ranges(&self, id: NodeId) -> Result<Vec<(u64, u64, u64)>, DeviceTreeError> {
+        self.read_ranges_property(id, "ranges")
+    }
+
+    /// Read the "dma-ranges" of a node, like `read_ranges`.
+    pub fn read_dma_ranges(&self, id: NodeId) -> Result<Vec<(u64, u64, u64)>, DeviceTreeError> {
+        self.read_ranges_property(id, "dma-ranges")
+    }
+
+    fn set_ranges_property(
+        &mut self,
+        id: NodeId,
+        name: &str,
+        entries: &[(u64, u64, u64)],
+    ) -> Result<(), DeviceTreeError> {
+        let cells = Address::ranges_cells(self, id);
+        let entries: Vec<Vec<u64>> = entries.iter().map(|(c, p, s)| vec![*c, *p, *s]).collect();
+        Address::write_entries(self, id, name, &cells, &entries)
+    }
+
+    fn read_ranges_property(
+        &self,
+        id: NodeId,
+        name: &str,
+    ) -> Result<Vec<(u64, u64, u64)>, DeviceTreeError> {
+        let property = self
+            .node(id)
+            .find_property(name)
+            .ok_or_else(|| DeviceTreeError::PropertyNotFound(String::from(name)))?;
+        if property.value.is_empty() {
+            return Ok(vec![]);
+        }
+        let cells = Address::ranges_cells(self, id);
+        let entries = Address::read_entries(self, id, name, &cells)?;
+        Ok(entries.iter().map(|e| (e[0], e[1], e[2])).collect())
+    }
+
+    /// Get the map of the physical RAM, like the memory the OS can use after
+    /// the early boot.
+    ///
+    /// The RAM is read from the "linux,usable-memory" or "reg" of the nodes
+    /// with device_type "memory", and is split by the `/memreserve/` entries
+    /// and the children of "/reserved-memory" having "reg". The regions are
+    /// sorted, don't overlap, and the adjacent regions of the same kind are
+    /// merged. The reservations outside the RAM are left out.
+    ///
+    /// Where reservations overlap, the "no-map" children of
+    /// "/reserved-memory" come first, then its other children, and then the
+    /// `/memreserve/` entries.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, MemoryRegion, MemoryRegionKind};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// /memreserve/ 0x40000000 0x1000;
+    /// / {
+    ///     #address-cells = <1>;
+    ///     #size-cells = <1>;
+    ///     memory@40000000 {
+    ///         device_type = "memory";
+    ///         reg = <0x40000000 0x10000000>;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    ///
+    /// let usable: Vec<(u64, u64)> = tree
+    ///     .memory_map()
+    ///     .unwrap()
+    ///     .iter()
+    ///     .filter(|region| region.kind == MemoryRegionKind::Usable)
+    ///     .map(|region| (region.address, region.size))
+    ///     .collect();
+    /// assert_eq!(usable, vec![(0x40001000, 0xffff000)]);
+    /// ```
+    pub fn memory_map(&self) -> Result<Vec<MemoryRegion>, DeviceTreeError> {
+        MemoryMap::build(self)
+    }
+
+    /// Place the dynamic reserved memory regions, which are the children of
+    /// "/reserved-memory" that have "size" but no "reg", and write the "reg"
+    /// of them, like the early boot of Linux does.
+    ///
+    /// The regions are placed in the order of the nodes, at the highest
+    /// address that is free in the `memory_map`, aligned to "alignment" (4
+    /// KiB by default), and within the first possible range of
+    /// "alloc-ranges" if it is present. The nodes placed are returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     #address-cells = <1>;
+    ///     #size-cells = <1>;
+    ///     memory@40000000 {
+    ///         device_type = "memory";
+    ///         reg = <0x40000000 0x10000000>;
+    ///     };
+    ///     reserved-memory {
+    ///         #address-cells = <1>;
+    ///         #size-cells = <1>;
+    ///         ranges;
+    ///         linux,cma {
+    ///             compatible = "shared-dma-pool";
+    ///             reusable;
+    ///             size = <0x4000000>;
+    ///             alignment = <0x400000>;
+    ///         };
+    ///     };
+    /// };"#;
+    /// let mut tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let cma = tree.find_node_by_path("/reserved-memory/linux,cma").unwrap();
+    ///
+    /// assert_eq!(tree.allocate_reserved_memory(), Ok(vec![cma]));
+    /// assert_eq!(tree.reg(cma), Ok(vec![(0x4c000000, 0x4000000)]));
+    /// ```
+    pub fn allocate_reserved_memory(&mut self) -> Result<Vec<NodeId>, DeviceTreeError> {
+        MemoryMap::allocate_reserved(self)
+    }
+
+    /// Translate a DMA address of a node, which is in the DMA address space
+    /// of the parent bus, to a CPU physical address, like
+    /// `of_translate_dma_address` of Linux.
+    ///
+    /// It works like `translate_address` with "dma-ranges" in place of
+    /// "ranges", except that an ancestor without "dma-ranges" maps the
+    /// addresses 1:1.
+    pub fn translate_dma_address(&self, id: NodeId, addr: &[u32]) -> Result<u64, DeviceTreeError> {
+        Address::translate(self, id, addr, "dma-ranges")
+    }
+
+    /// Get the DMA ranges that apply to a node, like `of_dma_get_range` of
+    /// Linux.
+    ///
+    /// The "dma-ranges" is looked up on the node and then its parent, going
+    /// further up only through empty "dma-ranges". The CPU addresses of the
+    /// ranges are translated with `translate_dma_address`. No ranges are
+    /// returned if none is found, which means that the DMA addresses are the
+    /// CPU physical addresses.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, DmaRange};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     #address-cells = <2>;
+    ///     #size-cells = <2>;
+    ///     soc {
+    ///         #address-cells = <1>;
+    ///         #size-cells = <1>;
+    ///         ranges;
+    ///         dma-ranges = <0x0 0x0 0x80000000 0x40000000>;
+    ///         dma@1000 {
+    ///             reg = <0x1000 0x100>;
+    ///         };
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let dma = tree.find_node_by_path("/soc/dma@1000").unwrap();
+    ///
+    /// let range = DmaRange {
+    ///     cpu_start: 0x80000000,
+    ///     dma_start: 0,
+    ///     size: 0x40000000,
+    /// };
+    /// assert_eq!(tree.dma_ranges(dma), Ok(vec![range]));
+    /// assert_eq!(tree.translate_dma_address(dma, &[0x1000]), Ok(0x80001000));
+    /// ```
+    pub fn dma_ranges(&self, id: NodeId) -> Result<Vec<DmaRange>, DeviceTreeError> {
+        Address::dma_ranges(self, id)
+    }
+
+    /// Get the IOMMU specifiers in the "iommus" of a node, where every
+    /// phandle is followed by the "#iommu-cells" of the IOMMU. The empty
+    /// entries are left out, and a node without "iommus" has none.
+    pub fn iommus(&self, id: NodeId) -> Result<Vec<PhandleArgs>, DeviceTreeError> {
+        Iommu::iommus(self, id)
+    }
+
+    /// Map an ID, like a PCI requester ID, through a map property of a node
+    /// like "iommu-map" or "msi-map", like `of_map_id` of Linux.
+    ///
+    /// Every entry of the map has the base of the input IDs, the phandle of
+    /// the target, the base of the output IDs and the number of IDs. The ID
+    /// is masked with the mask property first, which defaults to all ones.
+    /// If `target` is given, only the entries for that node are used. The
+    /// target node and the output ID are returned as a `PhandleArgs`, or
+    /// `None` if the node has no map or no entry covers the ID, where Linux
+    /// passes the ID through untranslated.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     smmu: iommu {
+    ///         phandle = <1>;
+    ///         #iommu-cells = <1>;
+    ///     };
+    ///     pcie {
+    ///         iommu-map = <0x0 &smmu 0x10000 0x10000>;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let pcie = tree.find_node_by_path("/pcie").unwrap();
+    ///
+    /// let mapped = tree
+    ///     .map_id(pcie, 0x108, "iommu-map", "iommu-map-mask", None)
+    ///     .unwrap()
+    ///     .unwrap();
+    /// assert_eq!(mapped.node, tree.find_node_by_path("/iommu").unwrap());
+    /// assert_eq!(mapped.args, vec![0x10108]);
+    /// ```
+    pub fn map_id(
+        &self,
+        id: NodeId,
+        input: u32,
+        map_name: &str,
+        mask_name: &str,
+        target: Option<NodeId>,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        Iommu::map_id(self, id, input, map_name, mask_name, target)
+    }
+
+    /// Get the IOMMU and the stream ID of a PCI requester ID below the host
+    /// bridge node `host`, through its "iommu-map", see `map_id`.
+    pub fn iommu_map(
+        &self,
+        host: NodeId,
+        rid: u32,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        Iommu::map_id(self, host, rid, "iommu-map", "iommu-map-mask", None)
+    }
+
+    /// Get the MSI controller and the device ID of a requester ID, through
+    /// the "msi-map" of the closest node having it, from the node up to the
+    /// root, like `of_msi_map_id` of Linux. See `map_id` for `target`.
+    ///
+    /// Only the closest "msi-map" is used. If none of its entries matches,
+    /// the ID is passed through unchanged to `target`, and `None` is
+    /// returned without `target`.
+    pub fn msi_map(
+        &self,
+        id: NodeId,
+        rid: u32,
+        target: Option<NodeId>,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        Iommu::msi_map(self, id, rid, target)
+    }
+
+    /// Build the graph of the supplier/consumer dependencies between the
+    /// nodes, see `DependencyGraph`.
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        DependencyGraph::new(self)
+    }
+
+    /// Find the interrupt parent of a node, like `of_irq_find_parent` of
+    /// Linux.
+    ///
+    /// Starting from the node, follow its "interrupt-parent", or its parent
+    /// node if it has none, until a node with "#interrupt-cells" is reached.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     interrupt-parent = <&gic>;
+    ///     gic: intc {
+    ///         phandle = <1>;
+    ///         #interrupt-cells = <3>;
+    ///     };
+    ///     soc {
+    ///         uart {
+    ///             interrupts = <0 1 4>;
+    ///         };
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let uart = tree.find_node_by_path("/soc/uart").unwrap();
+    ///
+    /// assert_eq!(tree.interrupt_parent(uart), tree.find_node_by_path("/intc"));
+    /// ```
+    pub fn interrupt_parent(&self, id: NodeId) -> Option<NodeId> {
+        let mut node = id;
+        // Stop at a loop of "interrupt-parent" without "#interrupt-cells"
+        let mut visited = vec![];
+        loop {
+            let next = match self.node(node).read_u32("interrupt-parent") {
+                Ok(phandle) => self.find_node_by_phandle(phandle)?,
+                Err(_) => self.parent(node)?,
+            };
+            if self.node(next).find_property("#interrupt-cells").is_some() {
+                return Some(next);
+            }
+            if visited.contains(&next) {
+                return None;
+            }
+            visited.push(next);
+            node = next;
+        }
+    }
+
+    /// Resolve an interrupt of a node to the interrupt controller and the
+    /// specifier it ends up at, like `of_irq_parse_one` of Linux.
+    ///
+    /// The interrupt is read from "interrupts-extended", or "interrupts" with
+    /// the `interrupt_parent` of the node. Then the "interrupt-map" of every
+    /// nexus node on the way is applied, matching the unit address from
+    /// "reg" and the specifier under "interrupt-map-mask", until a node with
+    /// "interrupt-controller" is reached. `None` is returned if the index is
+    /// out of range or the entry of "interrupts-extended" is empty.
+    ///
+    /// A malformed or unmatched map is reported as `CellMismatch`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     #address-cells = <1>;
+    ///     gic: intc {
+    ///         phandle = <1>;
+    ///         #interrupt-cells = <3>;
+    ///         interrupt-controller;
+    ///     };
+    ///     nexus {
+    ///         phandle = <2>;
+    ///         #address-cells = <0>;
+    ///         #interrupt-cells = <1>;
+    ///         interrupt-map = <0 &gic 0 32 4>, <1 &gic 0 33 4>;
+    ///         device {
+    ///             interrupts = <1>;
+    ///         };
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let gic = tree.find_node_by_path("/intc").unwrap();
+    /// let device = tree.find_node_by_path("/nexus/device").unwrap();
+    ///
+    /// let irq = tree.parse_interrupt(device, 0).unwrap().unwrap();
+    /// assert_eq!(irq.node, gic);
+    /// assert_eq!(irq.args, vec![0, 33, 4]);
+    /// assert_eq!(tree.parse_interrupt(device, 1), Ok(None));
+    /// ```
+    pub fn parse_interrupt(
+        &self,
+        id: NodeId,
+        index: usize,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        Irq::parse_one(self, id, index)
+    }
+
+    /// Resolve all the interrupts of a node, see `parse_interrupt`. The empty
+    /// entries of "interrupts-extended" are left out.
+    pub fn parse_interrupts(&self, id: NodeId) -> Result<Vec<PhandleArgs>, DeviceTreeError> {
+        let mut irqs = vec![];
+        for index in 0..Irq::count(self, id)? {
+            irqs.extend(Irq::parse_one(self, id, index)?);
+        }
+        Ok(irqs)
+    }
+
+    /// Resolve the INTx interrupt of a PCI function below the host bridge
+    /// node `host`, like `of_irq_parse_pci` of Linux.
+    ///
+    /// `devices` are the bus, device and function numbers of the path from
+    /// the device on the root bus of the host bridge down to the function.
+    /// The bridges on the path are taken to have no nodes, so the pin is
+    /// swizzled at each of them before the "interrupt-map" of the host
+    /// bridge is applied. `pin` is 1 to 4 for INTA to INTD, and `None` is
+    /// returned for pin 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     gic: intc {
+    ///         phandle = <1>;
+    ///         #interrupt-cells = <1>;
+    ///         interrupt-controller;
+    ///     };
+    ///     pcie {
+    ///         phandle = <2>;
+    ///         #address-cells = <3>;
+    ///         #interrupt-cells = <1>;
+    ///         interrupt-map-mask = <0 0 0 7>;
+    ///         interrupt-map = <0 0 0 1 &gic 10>, <0 0 0 2 &gic 11>,
+    ///                         <0 0 0 3 &gic 12>, <0 0 0 4 &gic 13>;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let pcie = tree.find_node_by_path("/pcie").unwrap();
+    ///
+    /// // INTA of device 1 behind the bridge at 00:00.0 arrives as INTB
+    /// let irq = tree
+    ///     .parse_pci_interrupt(pcie, &[(0, 0, 0), (1, 1, 0)], 1)
+    ///     .unwrap()
+    ///     .unwrap();
+    /// assert_eq!(irq.args, vec![11]);
+    /// ```
+    pub fn parse_pci_interrupt(
+        &self,
+        host: NodeId,
+        devices: &[(u8, u8, u8)],
+        pin: u8,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        Irq::parse_pci(self, host, devices, pin)
+    }
+
+    /// Encode an interrupt specifier to the cells of an interrupt controller.
+    ///
+    /// The GIC, PLIC and APLIC specifiers are only accepted by controllers
+    /// compatible with them, and a `Raw` specifier by any controller. It
+    /// fails with `CellMismatch` if the specifier doesn't fit the kind or
+    /// the "#interrupt-cells" of the controller, and with
+    /// `InvalidPropertyValue` if a number or the trigger type is out of the
+    /// range of the controller.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, InterruptSpecifier, InterruptTrigger};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     intc {
+    ///         compatible = "arm,cortex-a15-gic";
+    ///         #interrupt-cells = <3>;
+    ///         interrupt-controller;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let gic = tree.find_node_by_path("/intc").unwrap();
+    ///
+    /// let timer = InterruptSpecifier::GicPpi {
+    ///     number: 13,
+    ///     trigger: InterruptTrigger::LevelLow,
+    ///     cpus: 0xf,
+    /// };
+    /// assert_eq!(tree.encode_interrupt(gic, &timer), Ok(vec![1, 13, 0xf08]));
+    ///
+    /// let uart = InterruptSpecifier::GicSpi {
+    ///     number: 1,
+    ///     trigger: InterruptTrigger::LevelHigh,
+    /// };
+    /// assert_eq!(tree.encode_interrupt(gic, &uart), Ok(vec![0, 1, 4]));
+    /// ```
+    pub fn encode_interrupt(
+        &self,
+        controller: NodeId,
+        specifier: &InterruptSpecifier,
+    ) -> Result<Vec<u32>, DeviceTreeError> {
+        Irq::encode(self, controller, specifier)
+    }
+
+    /// Decode the specifier of an interrupt controller, like the ones from
+    /// `parse_interrupt`. The specifiers of unknown controllers or of an
+    /// unknown form are decoded as `Raw`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, InterruptSpecifier, InterruptTrigger, PhandleArgs};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     aplic {
+    ///         compatible = "riscv,qemu-aplic", "riscv,aplic";
+    ///         #interrupt-cells = <2>;
+    ///         interrupt-controller;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let aplic = tree.find_node_by_path("/aplic").unwrap();
+    ///
+    /// assert_eq!(
+    ///     tree.decode_interrupt(&PhandleArgs { node: aplic, args: vec![10, 4] }),
+    ///     InterruptSpecifier::Aplic {
+    ///         source: 10,
+    ///         trigger: InterruptTrigger::LevelHigh
+    ///     }
+    /// );
+    /// assert_eq!(
+    ///     tree.decode_interrupt(&PhandleArgs { node: aplic, args: vec![10] }),
+    ///     InterruptSpecifier::Raw(vec![10])
+    /// );
+    /// ```
+    pub fn decode_interrupt(&self, irq: &PhandleArgs) -> InterruptSpecifier {
+        Irq::decode(self, irq)
+    }
+
+    /// Set the interrupts of a node to specifiers of an interrupt controller.
+    ///
+    /// "interrupts" is written with the encoded specifiers, see
+    /// `encode_interrupt`. "interrupt-parent" is written as well, unless the
+    /// controller is already the `interrupt_parent` of the node, and
+    /// "interrupts-extended" is removed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, InterruptSpecifier};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     plic {
+    ///         compatible = "sifive,fu540-c000-plic", "sifive,plic-1.0.0";
+    ///         #interrupt-cells = <1>;
+    ///         interrupt-controller;
+    ///     };
+    ///     serial {
+    ///     };
+    /// };"#;
+    /// let mut tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let plic = tree.find_node_by_path("/plic").unwrap();
+    /// let serial = tree.find_node_by_path("/serial").unwrap();
+    ///
+    /// let specifier = InterruptSpecifier::Plic { source: 10, trigger: None };
+    /// tree.set_interrupts(serial, plic, &[specifier.clone()]).unwrap();
+    ///
+    /// assert_eq!(tree.interrupt_parent(serial), Some(plic));
+    /// assert_eq!(tree.node(serial).read_u32("interrupts"), Ok(10));
+    /// assert_eq!(tree.interrupt_specifiers(serial), Ok(vec![(plic, specifier)]));
+    /// ```
+    pub fn set_interrupts(
+        &mut self,
+        id: NodeId,
+        controller: NodeId,
+        specifiers: &[InterruptSpecifier],
+    ) -> Result<(), DeviceTreeError> {
+        let mut cells = vec![];
+        for specifier in specifiers {
+            cells.extend(Irq::encode(self, controller, specifier)?);
+        }
+        if self.interrupt_parent(id) != Some(controller) {
+            let phandle = self.ensure_phandle(controller);
+            self.node_mut(id)
+                .set_property(Property::new_u32("interrupt-parent", phandle));
+        }
+        let mut node = self.node_mut(id);
+        node.remove_property("interrupts-extended");
+        node.set_property(Property::new_u32s("interrupts", cells));
+        Ok(())
+    }
+
+    /// Set the interrupts of a node to specifiers of any interrupt
+    /// controllers, as "interrupts-extended". "interrupts" is removed.
+    pub fn set_interrupts_extended(
+        &mut self,
+        id: NodeId,
+        specifiers: &[(NodeId, InterruptSpecifier)],
+    ) -> Result<(), DeviceTreeError> {
+        let mut cells = vec![];
+        for (controller, specifier) in specifiers {
+            let encoded = Irq::encode(self, *controller, specifier)?;
+            cells.push(self.ensure_phandle(*controller));
+            cells.extend(encoded);
+        }
+        let mut node = self.node_mut(id);
+        node.remove_property("interrupts");
+        node.set_property(Property::new_u32s("interrupts-extended", cells));
+        Ok(())
+    }
+
+    /// Resolve all the interrupts of a node with `parse_interrupts`, and
+    /// decode them with `decode_interrupt`.
+    pub fn interrupt_specifiers(
+        &self,
+        id: NodeId,
+    ) -> Result<Vec<(NodeId, InterruptSpecifier)>, DeviceTreeError> {
+        Ok(self
+            .parse_interrupts(id)?
+            .iter()
+            .map(|irq| (irq.node, Irq::decode(self, irq)))
+            .collect())
+    }
+
+    /// Set the "msi-parent" of a node to an MSI controller, like an IMSIC of
+    /// RISC-V or an ITS of GICv3, with the arguments of its "#msi-cells".
+    ///
+    /// A controller without "#msi-cells" takes no arguments. It fails with
+    /// `CellMismatch` if the number of arguments is different.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, PhandleArgs};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     imsics {
+    ///         compatible = "qemu,imsics", "riscv,imsics";
+    ///         msi-controller;
+    ///         #msi-cells = <0>;
+    ///     };
+    ///     aplic {
+    ///     };
+    /// };"#;
+    /// let mut tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let imsics = tree.find_node_by_path("/imsics").unwrap();
+    /// let aplic = tree.find_node_by_path("/aplic").unwrap();
+    ///
+    /// tree.set_msi_parent(aplic, imsics, &[]).unwrap();
+    /// assert_eq!(tree.msi_parent(aplic), Ok(Some(PhandleArgs { node: imsics, args: vec![] })));
+    /// assert!(tree.set_msi_parent(aplic, imsics, &[1]).is_err());
+    /// ```
+    pub fn set_msi_parent(
+        &mut self,
+        id: NodeId,
+        controller: NodeId,
+        args: &[u32],
+    ) -> Result<(), DeviceTreeError> {
+        let cells = self.node(controller).read_u32("#msi-cells").unwrap_or(0);
+        if cells as usize != args.len() {
+            return Err(DeviceTreeError::CellMismatch(format!(
+                "{}:#msi-cells",
+                self.path_of(controller)
+            )));
+        }
+        let mut value = vec![self.ensure_phandle(controller)];
+        value.extend(args);
+        self.node_mut(id)
+            .set_property(Property::new_u32s("msi-parent", value));
+        Ok(())
+    }
+
+    /// Get the MSI controller of a node and the arguments from the first
+    /// entry of "msi-parent". A controller without "#msi-cells" takes no
+    /// arguments. `None` is returned if the node has no "msi-parent".
+    pub fn msi_parent(&self, id: NodeId) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        if self.node(id).find_property("msi-parent").is_none() {
+            return Ok(None);
+        }
+        let phandle = self.node(id).read_u32_array("msi-parent", 1)?[0];
+        let controller = self
+            .find_node_by_phandle(phandle)
+            .ok_or(DeviceTreeError::DanglingPhandle(phandle))?;
+        let cells = self.node(controller).read_u32("#msi-cells").unwrap_or(0);
+        self.parse_phandle_with_fixed_args(id, "msi-parent", cells as usize, 0)
+    }
+
+    /// Give new phandles to all the nodes having phandles, and rewrite the
+    /// references to them, see `phandle_references`.
+    ///
+    /// The references to a phandle used by several nodes are rewritten to
+    /// the first node, and the dangling references are kept as they are.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, PhandleNumbering, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_property(Property::new_u32("interrupt-parent", 0x8001));
+    /// let mut intc = Node::new("intc");
+    /// intc.add_property(Property::new_u32("phandle", 0x8001));
+    /// root.add_sub_node(intc);
+    /// let mut tree = DeviceTree::new(vec![], root);
+    ///
+    /// tree.renumber_phandles(PhandleNumbering::Sequential);
+    ///
+    /// let root = tree.node(tree.root());
+    /// assert_eq!(root.find_property("interrupt-parent").unwrap().value, vec![0, 0, 0, 1]);
+    /// assert_eq!(tree.find_node_by_phandle(1), tree.find_node_by_path("/intc"));
+    /// ```
+    pub fn renumber_phandles(&mut self, numbering: PhandleNumbering) {
+        self.refresh_phandles();
+        let references = self.phandle_references();
+        let ids: Vec<NodeId> = self
+            .subtree(self.root)
+            .into_iter()
+            .filter(|id| self.node(*id).phandle().is_some())
+            .collect();
+        let new_phandles = numbering.assign(self, &ids);
+
+        // Map the old phandles to the new ones of the nodes they refer to
+        let mut phandle_map = BTreeMap::new();
+        for (id, new_phandle) in ids.iter().zip(&new_phandles) {
+            let old_phandle = self.node(*id).phandle().unwrap();
+            if self.find_node_by_phandle(old_phandle) == Some(*id) {
+                phandle_map.insert(old_phandle, *new_phandle);
+            }
+        }
+
+        for reference in references {
+            if let Some(new_phandle) = phandle_map.get(&reference.phandle) {
+                let node = &mut self.entry_mut(reference.node).node;
+                let prop = node.find_property_mut(&reference.property).unwrap();
+                let offset = reference.cell * 4;
+                prop.value[offset..(offset + 4)].copy_from_slice(&new_phandle.to_be_bytes());
+            }
+        }
+
+        self.phandles = PhandleIndex::default();
+        for (id, new_phandle) in ids.into_iter().zip(new_phandles) {
+            let node = &mut self.entry_mut(id).node;
+            for name in ["phandle", "linux,phandle"] {
+                if let Some(prop) = node.find_property_mut(name) {
+                    *prop = Property::new_u32(name, new_phandle);
+                }
+            }
+            self.phandles.insert(new_phandle, id);
+        }
+    }
+
+    /// Parse an entry of a phandle list property of a node, like
+    /// `of_parse_phandle_with_args` of Linux.
+    ///
+    /// The number of argument cells of each entry is read from the
+    /// `cells_name` property (like "#clock-cells") of the node the phandle
+    /// refers to. The entries are counted from 0, and `None` is returned for
+    /// an index after the last entry or an empty entry with phandle 0.
+    ///
+    /// It fails if the property doesn't exist, a phandle refers to no node,
+    /// or the cells don't fit the `cells_name` of the nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, PhandleArgs};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     clk: clock-controller {
+    ///         phandle = <1>;
+    ///         #clock-cells = <1>;
+    ///     };
+    ///     serial {
+    ///         clocks = <&clk 3>, <&clk 5>;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let serial = tree.find_node_by_path("/serial").unwrap();
+    /// let clk = tree.find_node_by_path("/clock-controller").unwrap();
+    ///
+    /// assert_eq!(
+    ///     tree.parse_phandle_with_args(serial, "clocks", "#clock-cells", 1),
+    ///     Ok(Some(PhandleArgs { node: clk, args: vec![5] }))
+    /// );
+    /// assert_eq!(tree.parse_phandle_with_args(serial, "clocks", "#clock-cells", 2), Ok(None));
+    /// ```
+    pub fn parse_phandle_with_args(
+        &self,
+        id: NodeId,
+        list_name: &str,
+        cells_name: &str,
+        index: usize,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        let list = PhandleArgs::parse_list(self, id, list_name, &CellCount::Property(cells_name))?;
+        Ok(list.into_iter().nth(index).flatten())
+    }
+
+    /// Parse an entry of a phandle list property of a node, where every
+    /// phandle is followed by `cell_count` argument cells, like
+    /// `of_parse_phandle_with_fixed_args` of Linux.
+    pub fn parse_phandle_with_fixed_args(
+        &self,
+        id: NodeId,
+        list_name: &str,
+        cell_count: usize,
+        index: usize,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        let list = PhandleArgs::parse_list(self, id, list_name, &CellCount::Fixed(cell_count))?;
+        Ok(list.into_iter().nth(index).flatten())
+    }
+
+    /// Count the entries of a phandle list property of a node, including the
+    /// empty entries, like `of_count_phandle_with_args` of Linux.
+    pub fn count_phandle_with_args(
+        &self,
+        id: NodeId,
+        list_name: &str,
+        cells_name: &str,
+    ) -> Result<usize, DeviceTreeError> {
+        let list = PhandleArgs::parse_list(self, id, list_name, &CellCount::Property(cells_name))?;
+        Ok(list.len())
+    }
+
+    /// Parse an entry of a phandle list property of a node, and translate it
+    /// through the "<stem>-map" properties, like
+    /// `of_parse_phandle_with_args_map` of Linux.
+    ///
+    /// The argument cells are counted by "#<stem>-cells". While the node the
+    /// entry refers to has a "<stem>-map" property (like "gpio-map"), the
+    /// entry is replaced by the first available map entry that matches it
+    /// under "<stem>-map-mask", keeping the bits of "<stem>-map-pass-thru".
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, PhandleArgs};
+    ///
+    /// let dts = r#"/dts-v1/;
+    /// / {
+    ///     gpio: gpio-controller {
+    ///         phandle = <1>;
+    ///         #gpio-cells = <2>;
+    ///     };
+    ///     connector: connector {
+    ///         phandle = <2>;
+    ///         #gpio-cells = <2>;
+    ///         gpio-map = <0 0 &gpio 12 0>, <1 0 &gpio 13 0>;
+    ///         gpio-map-mask = <0xf 0>;
+    ///         gpio-map-pass-thru = <0 0x1>;
+    ///     };
+    ///     led {
+    ///         gpios = <&connector 1 1>;
+    ///     };
+    /// };"#;
+    /// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+    /// let led = tree.find_node_by_path("/led").unwrap();
+    /// let gpio = tree.find_node_by_path("/gpio-controller").unwrap();
+    ///
+    /// assert_eq!(
+    ///     tree.parse_phandle_with_args_map(led, "gpios", "gpio", 0),
+    ///     Ok(Some(PhandleArgs { node: gpio, args: vec![13, 1] }))
+    /// );
+    /// ```
+    pub fn parse_phandle_with_args_map(
+        &self,
+        id: NodeId,
+        list_name: &str,
+        stem_name: &str,
+        index: usize,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        let cells_name = format!("#{stem_name}-cells");
+        match self.parse_phandle_with_args(id, list_name, &cells_name, index)? {
+            Some(args) => Ok(Some(args.map(self, stem_name)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Parse all the entries of a phandle list property of a node, see
+    /// `parse_phandle_with_args`. The empty entries are `None`.
+    pub fn parse_phandles_with_args(
+        &self,
+        id: NodeId,
+        list_name: &str,
+        cells_name: &str,
+    ) -> Result<Vec<Option<PhandleArgs>>, DeviceTreeError> {
+        PhandleArgs::parse_list(self, id, list_name, &CellCount::Property(cells_name))
+    }
+
+    // Get the nodes of the subtree at a node in depth-first order.
+    pub(crate) fn subtree(&self, id: NodeId) -> Vec<NodeId> {
+        let mut ids = vec![];
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            ids.push(id);
+            stack.extend(self.children(id).iter().rev());
+        }
+        ids
+    }
+
+    // Check if `id` is `ancestor` or one of its descendants.
+    pub(crate) fn is_within(&self, id: NodeId, ancestor: NodeId) -> bool {
+        let mut node = Some(id);
+        while let Some(id) = node {
+            if id == ancestor {
+                return true;
+            }
+            node = self.parent(id);
+        }
+        false
+    }
+
+    /// Add a node with all its sub nodes to the end of the sub nodes of a node.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut tree = DeviceTree::new(vec![], Node::new(""));
+    ///
+    /// let mut cpus = Node::new("cpus");
+    /// cpus.add_sub_node(Node::new("cpu@0"));
+    /// let cpus = tree.add_sub_node(tree.root(), cpus);
+    ///
+    /// assert_eq!(tree.node(cpus).sub_nodes.len(), 0);
+    /// assert_eq!(tree.node(tree.children(cpus)[0]).name, "cpu@0");
+    /// ```
+    pub fn add_sub_node(&mut self, parent: NodeId, node: Node) -> NodeId {
+        let id = self.insert_entry(node, Some(parent));
+        self.entry_mut(parent).children.push(id);
+        id
+    }
+
+    /// Insert a node with all its sub nodes to a position of the sub nodes of a node.
+    ///
+    /// The sub nodes after the position are shifted, a position beyond the
+    /// end appends the node.
+    pub fn insert_sub_node_at(&mut self, parent: NodeId, index: usize, node: Node) -> NodeId {
+        let id = self.insert_entry(node, Some(parent));
+        let children = &mut self.entry_mut(parent).children;
+        let index = index.min(children.len());
+        children.insert(index, id);
+        id
+    }
+
+    /// Make a copy of the node with all its sub nodes, the copy is not in
+    /// any tree and has its sub nodes in `sub_nodes`.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("chosen"));
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let root = tree.to_node(tree.root());
+    /// assert_eq!(root.sub_nodes[0].name, "chosen");
+    /// ```
+    pub fn to_node(&self, id: NodeId) -> Node {
+        let mut node = self.node(id).clone();
+        node.sub_nodes = self
+            .children(id)
+            .iter()
+            .map(|child| self.to_node(*child))
+            .collect();
+        node
+    }
+
+    /// Get a copy of the root node in the shared form of the API before
+    /// `NodeId`, when the tree kept `root: Arc<Mutex<Node>>`.
+    ///
+    /// The copy is detached from the tree like `to_node`, so changes made
+    /// through it are not seen by the tree.
+    #[deprecated(note = "use `root()` with `node()`, `node_mut()` or `to_node()`")]
+    pub fn root_shared(&self) -> Arc<Mutex<Node>> {
+        Arc::new(Mutex::new(self.to_node(self.root)))
+    }
+
+    /// Get copies of the `/memreserve/` entries in the shared form of the API
+    /// before `NodeId`, when `reservations` was `Vec<Arc<Mutex<Reservation>>>`.
+    #[deprecated(note = "use the `reservations` field")]
+    pub fn reservations_shared(&self) -> Vec<Arc<Mutex<Reservation>>> {
+        self.reservations
+            .iter()
+            .map(|r| Arc::new(Mutex::new(r.clone())))
+            .collect()
+    }
+
+    /// Find a node by path like `find_node_by_path`, and get a copy of it in
+    /// the shared form of the API before `NodeId`.
+    ///
+    /// The copy is detached from the tree like `to_node`, so changes made
+    /// through it are not seen by the tree.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// # #![allow(deprecated)]
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// let mut node_l1 = Node::new("node_l1");
+    /// node_l1.add_sub_node(Node::new_with_label("node_l2", "l2"));
+    /// root.add_sub_node(node_l1);
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let node_l2 = tree.find_node_by_path_shared("/node_l1/node_l2").unwrap();
+    /// assert_eq!(node_l2.lock().unwrap().name, "node_l2");
+    ///
+    /// let node_l1 = tree.find_node_by_path_shared("/node_l1").unwrap();
+    /// assert_eq!(node_l1.lock().unwrap().sub_nodes[0].name, "node_l2");
+    /// assert!(tree.find_node_by_label_shared("l2").is_some());
+    /// ```
+    #[deprecated(note = "use `find_node_by_path()` with `node()` or `to_node()`")]
+    pub fn find_node_by_path_shared(&self, path: &str) -> Option<Arc<Mutex<Node>>> {
+        let id = self.find_node_by_path(path)?;
+        Some(Arc::new(Mutex::new(self.to_node(id))))
+    }
+
+    /// Find a node by label like `find_node_by_label`, and get a copy of it
+    /// in the shared form of the API before `NodeId`.
+    ///
+    /// The copy is detached from the tree like `to_node`, so changes made
+    /// through it are not seen by the tree.
+    #[deprecated(note = "use `find_node_by_label()` with `node()` or `to_node()`")]
+    pub fn find_node_by_label_shared(&self, label: &str) -> Option<Arc<Mutex<Node>>> {
+        let id = self.find_node_by_label(label)?;
+        Some(Arc::new(Mutex::new(self.to_node(id))))
+    }
+
+    /// Take a node with all its sub nodes out of the tree.
+    ///
+    /// The node and its sub nodes are no longer in the tree, the `NodeId`s of
+    /// all the other nodes stay valid. The slots of the taken nodes are reused
+    /// by the nodes added later. The root node can't be taken.
+    pub fn take_node(&mut self, id: NodeId) -> Result<Node, DeviceTreeError> {
+        let parent = match self.parent(id) {
+            Some(parent) => parent,
+            None => return Err(DeviceTreeError::InvalidPath(String::from("/"))),
+        };
+        self.entry_mut(parent).children.retain(|child| *child != id);
+        Ok(self.take_entry(id))
+    }
+
+    fn take_entry(&mut self, id: NodeId) -> Node {
+        let entry = self.nodes[id.0].take().unwrap();
+        self.free.push(id.0);
+        let mut node = entry.node;
+        node.sub_nodes = entry
+            .children
+            .into_iter()
+            .map(|child| self.take_entry(child))
+            .collect();
+        node
+    }
+
+    /// Find a 'Node' by label.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    ///
+    /// // Add some nodes
+    /// root.add_sub_node(Node::new_with_label("node1", "label1"));
+    /// root.add_sub_node(Node::new_with_label("node2", "label2"));
+    ///
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// // Find the nodes by their labels
+    /// let node1 = tree.find_node_by_label("label1").unwrap();
+    /// assert_eq!(tree.node(node1).name, "node1");
+    ///
+    /// let node2 = tree.find_node_by_label("label2").unwrap();
+    /// assert_eq!(tree.node(node2).name, "node2");
+    /// ```
+    pub fn find_node_by_label(&self, label: &str) -> Option<NodeId> {
+        self.subtree(self.root)
+            .into_iter()
+            .find(|id| self.node(*id).label.as_deref() == Some(label))
+    }
+
+    /// Find a 'Node' by path.
+    ///
+    /// The path is looked up like `of_find_node_by_path()` of Linux:
+    ///   - "/" is the root node, and trailing slashes are ignored
+    ///   - A path not starting with "/" begins with an alias defined in
+    ///     `/aliases`, like "serial0" or "serial0/sub-node"
+    ///   - Anything after a ":" is the options of the path and is ignored
+    ///   - A name without unit address, like "memory", matches the node
+    ///     "memory@40000000" if it is the only node with that name
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    ///
+    /// // Create a node with sub node
+    /// let mut node_l1 = Node::new("node_l1");
+    /// node_l1.add_sub_node(Node::new("node_l2@1000"));
+    ///
+    /// root.add_sub_node(node_l1);
+    ///
+    /// let mut aliases = Node::new("aliases");
+    /// aliases.add_property(Property::new_str("l2", "/node_l1/node_l2@1000"));
+    /// root.add_sub_node(aliases);
+    ///
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let node_l2 = tree.find_node_by_path("/node_l1/node_l2@1000").unwrap();
+    ///
+    /// assert_eq!(tree.node(node_l2).name, "node_l2@1000");
+    /// assert_eq!(tree.find_node_by_path("/node_l1/node_l2/"), Some(node_l2));
+    /// assert_eq!(tree.find_node_by_path("l2:115200n8"), Some(node_l2));
+    /// assert_eq!(tree.find_node_by_path("/"), Some(tree.root()));
+    /// ```
+    pub fn find_node_by_path(&self, path: &str) -> Option<NodeId> {
+        self.find_node_by_path_opts(path)
+            .map(|(node, _options)| node)
+    }
+
+    /// Find a 'Node' by path like `find_node_by_path()`, also return the
+    /// options of the path, which is the text after the first ":".
     ///
     /// Example:
     ///
     /// ```
-    /// use devicetree_tool::Reservation;
     /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
     /// use devicetree_tool::DeviceTree;
     ///
-    /// let tree = DeviceTree::new(vec![], Node::new(""));
+    /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("pl011@9000000"));
+    /// let mut aliases = Node::new("aliases");
+    /// aliases.add_property(Property::new_str("serial0", "/pl011@9000000"));
+    /// root.add_sub_node(aliases);
+    /// let tree = DeviceTree::new(vec![], root);
     ///
-    /// assert_eq!(format!("{}", tree), "/dts-v1/;\n\n/ {\n};\n\n");
+    /// let (serial, options) = tree.find_node_by_path_opts("serial0:115200n8").unwrap();
+    ///
+    /// assert_eq!(tree.node(serial).name, "pl011@9000000");
+    /// assert_eq!(options, Some("115200n8"));
     /// ```
-    pub fn new(reservations: Vec<Reservation>, root: Node) -> Self {
-        let mut reserv_refs = vec![];
-        for r in reservations {
-            reserv_refs.push(Arc::new(Mutex::new(r)));
+    pub fn find_node_by_path_opts<'a>(&self, path: &'a str) -> Option<(NodeId, Option<&'a str>)> {
+        let (path, options) = match path.split_once(':') {
+            Some((path, options)) => (path, Some(options)),
+            None => (path, None),
+        };
+
+        let (mut node, rest) = match path.strip_prefix('/') {
+            Some(rest) => (self.root, rest),
+            None => {
+                let (alias, rest) = path.split_once('/').unwrap_or((path, ""));
+                (self.find_alias(alias)?, rest)
+            }
+        };
+        for name in rest.split('/').filter(|name| !name.is_empty()) {
+            node = self.find_child_by_path_component(node, name)?;
+        }
+        Some((node, options))
+    }
+
+    // Resolve an alias defined in `/aliases` to a node.
+    fn find_alias(&self, alias: &str) -> Option<NodeId> {
+        if alias.is_empty() {
+            return None;
         }
-        DeviceTree {
-            reservations: reserv_refs,
-            root: Arc::new(Mutex::new(root)),
+        let aliases = self.find_child_by_name(self.root, "aliases")?;
+        let prop = self.node(aliases).find_property(alias)?;
+        let path = std::str::from_utf8(&prop.value).ok()?;
+        let path = path.trim_end_matches('\0');
+        // An alias is always a full path
+        if !path.starts_with('/') {
+            return None;
         }
+        self.find_node_by_path(path)
     }
 
-    /// Find a 'Node' by label.
+    // Find a sub node by a path component, a component without unit address
+    // matches a sub node with unit address if it is the only one.
+    fn find_child_by_path_component(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        if let Some(child) = self.find_child_by_name(id, name) {
+            return Some(child);
+        }
+        if name.contains('@') {
+            return None;
+        }
+        let mut matches = self.children(id).iter().filter(|child| {
+            let child_name = &self.node(**child).name;
+            child_name.split_once('@').map(|(base, _)| base) == Some(name)
+        });
+        match (matches.next(), matches.next()) {
+            (Some(child), None) => Some(*child),
+            _ => None,
+        }
+    }
+
+    /// Get the node at a path, creating the missing nodes along the path.
+    ///
+    /// Like `mkdir -p`, every node on the path that doesn't exist is created
+    /// as an empty node. The existing nodes are matched like
+    /// `find_node_by_path()`, so "pcie" reuses "pcie@10000000" if it is the
+    /// only node with that name. The name of each created node must follow
+    /// the node name syntax, otherwise nothing is created.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut tree = DeviceTree::new(vec![], Node::new(""));
+    ///
+    /// let node = tree.get_or_create_node("/soc/pcie@10000000").unwrap();
+    /// tree.node_mut(node).add_property(Property::new_str("device_type", "pci"));
+    ///
+    /// // The existing nodes are reused
+    /// tree.get_or_create_node("/soc/serial@9000000").unwrap();
+    /// assert_eq!(tree.get_or_create_node("/soc/pcie"), Ok(node));
+    ///
+    /// let soc = tree.find_node_by_path("/soc").unwrap();
+    /// assert_eq!(tree.children(soc).len(), 2);
+    /// ```
+    pub fn get_or_create_node(&mut self, path: &str) -> Result<NodeId, DeviceTreeError> {
+        if !path.starts_with('/') {
+            return Err(DeviceTreeError::InvalidPath(String::from(path)));
+        }
+        let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+        // Check all the names before creating anything
+        if let Some(name) = names.iter().find(|name| !Node::is_valid_name(name)) {
+            return Err(DeviceTreeError::InvalidNodeName(String::from(*name)));
+        }
+
+        let mut node = self.root;
+        for name in names {
+            node = match self.find_child_by_path_component(node, name) {
+                Some(sub_node) => sub_node,
+                None => self.add_sub_node(node, Node::new(name)),
+            };
+        }
+        Ok(node)
+    }
+
+    // Get the node at a full path.
+    fn node_at(&self, path: &str) -> Result<NodeId, DeviceTreeError> {
+        if !path.starts_with('/') {
+            return Err(DeviceTreeError::InvalidPath(String::from(path)));
+        }
+        self.find_node_by_path(path)
+            .ok_or_else(|| DeviceTreeError::NodeNotFound(String::from(path)))
+    }
+
+    /// Set a `Property` of the node at a path.
+    ///
+    /// An existing property with the same name is replaced and returned,
+    /// otherwise the property is added.
     ///
     /// Example:
     ///
     /// ```
     /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
     /// use devicetree_tool::DeviceTree;
     ///
     /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("chosen"));
+    /// let mut tree = DeviceTree::new(vec![], root);
     ///
-    /// // Add some nodes
-    /// root.add_sub_node(Node::new_with_label("node1", "label1"));
-    /// root.add_sub_node(Node::new_with_label("node2", "label2"));
+    /// tree.set_property_at("/chosen", Property::new_str("bootargs", "console=ttyS0")).unwrap();
+    /// tree.set_property_at("/chosen", Property::new_str("bootargs", "console=hvc0")).unwrap();
     ///
-    /// let tree = DeviceTree::new(vec![], root);
+    /// let chosen = tree.node(tree.find_node_by_path("/chosen").unwrap());
+    /// assert_eq!(chosen.properties.len(), 1);
+    /// assert_eq!(chosen.properties[0].value, b"console=hvc0\0".to_vec());
+    /// ```
+    pub fn set_property_at(
+        &mut self,
+        path: &str,
+        prop: Property,
+    ) -> Result<Option<Property>, DeviceTreeError> {
+        let node = self.node_at(path)?;
+        Ok(self.node_mut(node).set_property(prop))
+    }
+
+    /// Remove a `Property` by name from the node at a path.
     ///
-    /// // Find the nodes by their labels
-    /// let node1 = tree.find_node_by_label("label1").unwrap();
-    /// assert_eq!(node1.lock().unwrap().name, "node1");
+    /// Example:
     ///
-    /// let node2 = tree.find_node_by_label("label2").unwrap();
-    /// assert_eq!(node2.lock().unwrap().name, "node2");
     /// ```
-    pub fn find_node_by_label(&self, label: &str) -> Option<Arc<Mutex<Node>>> {
-        self.root.lock().unwrap().find_subnode_by_label(label)
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_property(Property::new_u32("interrupt-parent", 1));
+    /// let mut tree = DeviceTree::new(vec![], root);
+    ///
+    /// let prop = tree.remove_property_at("/", "interrupt-parent").unwrap();
+    ///
+    /// assert_eq!(prop.name, "interrupt-parent");
+    /// assert_eq!(tree.node(tree.root()).properties.len(), 0);
+    /// ```
+    pub fn remove_property_at(
+        &mut self,
+        path: &str,
+        name: &str,
+    ) -> Result<Property, DeviceTreeError> {
+        let node = self.node_at(path)?;
+        self.node_mut(node)
+            .remove_property(name)
+            .ok_or_else(|| DeviceTreeError::PropertyNotFound(String::from(name)))
     }
 
-    /// Find a 'Node' by path.
+    /// Remove the node at a path from the tree.
+    ///
+    /// The node is found like `find_node_by_path()`. The removed node is
+    /// returned with all its properties and sub nodes. The root node can't
+    /// be removed.
     ///
     /// Example:
     ///
@@ -81,34 +2369,163 @@ impl DeviceTree {
     /// use devicetree_tool::DeviceTree;
     ///
     /// let mut root = Node::new("");
+    /// let mut soc = Node::new("soc");
+    /// soc.add_sub_node(Node::new_with_label("uart@0", "uart0"));
+    /// root.add_sub_node(soc);
+    /// let mut tree = DeviceTree::new(vec![], root);
     ///
-    /// // Create a node with sub node
-    /// let mut node_l1 = Node::new("node_l1");
-    /// node_l1.add_sub_node(Node::new("node_l2"));
+    /// let uart = tree.remove_node("/soc/uart@0").unwrap();
     ///
-    /// root.add_sub_node(node_l1);
+    /// assert_eq!(uart.name, "uart@0");
+    /// assert_eq!(tree.find_node_by_path("/soc/uart@0").is_none(), true);
+    /// assert_eq!(tree.find_node_by_label("uart0").is_none(), true);
+    /// ```
+    pub fn remove_node(&mut self, path: &str) -> Result<Node, DeviceTreeError> {
+        let node = self.node_at(path)?;
+        self.take_node(node)
+    }
+
+    // Get a non-root node at a full path, with its parent.
+    fn child_at(&self, path: &str) -> Result<(NodeId, NodeId), DeviceTreeError> {
+        let node = self.node_at(path)?;
+        let parent = self
+            .parent(node)
+            .ok_or_else(|| DeviceTreeError::InvalidPath(String::from(path)))?;
+        Ok((parent, node))
+    }
+
+    /// Rename the node at a path.
     ///
-    /// let tree = DeviceTree::new(vec![], root);
+    /// The node is found like `find_node_by_path()`. The new name must follow
+    /// the node name syntax and must not be used by any sibling of the node.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("memory"));
+    /// let mut tree = DeviceTree::new(vec![], root);
     ///
-    /// let node_l2 = tree.find_node_by_path("/node_l1/node_l2").unwrap();
+    /// tree.rename_node("/memory", "memory@40000000").unwrap();
     ///
-    /// assert_eq!(node_l2.lock().unwrap().name, "node_l2");
+    /// assert_eq!(tree.find_node_by_path("/memory@40000000").is_some(), true);
+    /// ```
+    pub fn rename_node(&mut self, path: &str, new_name: &str) -> Result<(), DeviceTreeError> {
+        if !Node::is_valid_name(new_name) {
+            return Err(DeviceTreeError::InvalidNodeName(String::from(new_name)));
+        }
+        let (parent, node) = self.child_at(path)?;
+        if new_name != self.node(node).name && self.find_child_by_name(parent, new_name).is_some() {
+            return Err(DeviceTreeError::NodeExists(String::from(new_name)));
+        }
+        self.node_mut(node).name = String::from(new_name);
+        Ok(())
+    }
+
+    /// Move the node at a path, with all its sub nodes, under a new parent node.
+    ///
+    /// Both nodes are found like `find_node_by_path()`. The node is appended
+    /// to the sub nodes of the new parent. A node can't be moved into its own
+    /// subtree, or next to a node with the same name.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::DeviceTree;
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("soc"));
+    /// root.add_sub_node(Node::new("pl011@9000000"));
+    /// let mut tree = DeviceTree::new(vec![], root);
+    ///
+    /// tree.move_node("/pl011@9000000", "/soc").unwrap();
+    ///
+    /// assert_eq!(tree.find_node_by_path("/soc/pl011@9000000").is_some(), true);
+    /// assert_eq!(tree.children(tree.root()).len(), 1);
     /// ```
-    pub fn find_node_by_path(&self, path: &str) -> Option<Arc<Mutex<Node>>> {
-        let path: Vec<&str> = path.split("/").collect();
-        if path.len() == 0 {
-            Some(self.root.clone())
-        } else {
-            self.root
-                .lock()
-                .unwrap()
-                .find_subnode_by_path(path[1..].to_vec())
+    pub fn move_node(&mut self, path: &str, new_parent_path: &str) -> Result<(), DeviceTreeError> {
+        let (parent, node) = self.child_at(path)?;
+        let new_parent = self.node_at(new_parent_path)?;
+        if self.is_within(new_parent, node) {
+            return Err(DeviceTreeError::InvalidPath(String::from(new_parent_path)));
         }
+        if parent == new_parent {
+            return Ok(());
+        }
+        let name = &self.node(node).name;
+        if self.find_child_by_name(new_parent, name).is_some() {
+            return Err(DeviceTreeError::NodeExists(name.clone()));
+        }
+
+        self.entry_mut(parent)
+            .children
+            .retain(|child| *child != node);
+        self.entry_mut(new_parent).children.push(node);
+        self.entry_mut(node).parent = Some(new_parent);
+        Ok(())
+    }
+
+    /// Copy the subtree at a path of another tree under a node of this tree.
+    ///
+    /// The copy is a deep copy, the source tree is not changed. The conflicts
+    /// of phandles and labels with this tree are handled as `options` tells.
+    /// The copied node is returned.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, GraftOptions, LabelConflict, Node, Property};
+    ///
+    /// // The template tree has a labeled serial device
+    /// let mut root = Node::new("");
+    /// let mut uart = Node::new_with_label("pl011@9000000", "uart0");
+    /// uart.add_property(Property::new_u32("phandle", 1));
+    /// root.add_sub_node(uart);
+    /// let template = DeviceTree::new(vec![], root);
+    ///
+    /// // The guest tree uses the same phandle and label
+    /// let mut root = Node::new("");
+    /// let mut gic = Node::new_with_label("intc@8000000", "uart0");
+    /// gic.add_property(Property::new_u32("phandle", 1));
+    /// root.add_sub_node(gic);
+    /// let mut guest = DeviceTree::new(vec![], root);
+    ///
+    /// let options = GraftOptions {
+    ///     renumber_phandles: true,
+    ///     label_conflict: LabelConflict::Prefix(String::from("host_")),
+    ///     ..Default::default()
+    /// };
+    /// let uart = guest.graft_node(&template, "/pl011@9000000", "/", &options).unwrap();
+    ///
+    /// assert_eq!(guest.node(uart).label, Some(String::from("host_uart0")));
+    /// assert_eq!(
+    ///     guest.node(uart).find_property("phandle").unwrap().value,
+    ///     vec![0u8, 0u8, 0u8, 2u8]
+    /// );
+    /// ```
+    pub fn graft_node(
+        &mut self,
+        source: &DeviceTree,
+        source_path: &str,
+        parent_path: &str,
+        options: &GraftOptions,
+    ) -> Result<NodeId, DeviceTreeError> {
+        let node = source.node_at(source_path)?;
+        let parent = self.node_at(parent_path)?;
+        let copy = Graft::copy_into(source.to_node(node), self, options)?;
+        if self.find_child_by_name(parent, &copy.name).is_some() {
+            return Err(DeviceTreeError::NodeExists(copy.name));
+        }
+        Ok(self.add_sub_node(parent, copy))
     }
 
     /// Create a `Tree` from DTS text byte array.
     pub fn from_dts_bytes(dts: &[u8]) -> Self {
-        DtsParser::from_bytes(&dts).parse()
+        DtsParser::from_bytes(dts).parse()
     }
 
     /// Generate the DTS text of a `Tree`.
@@ -118,16 +2535,95 @@ impl DeviceTree {
 
     /// Create a `Tree` from DTB binary byte array.
     pub fn from_dtb_bytes(dtb: &[u8]) -> Self {
-        DtbParser::from_bytes(&dtb).parse()
+        DtbParser::from_bytes(dtb).parse()
+    }
+
+    /// Generate the device tree of an arm64 virtual machine, see
+    /// `VirtMachine`.
+    ///
+    /// It fails with `InvalidMachine` if the vCPUs don't fit the GIC
+    /// redistributors, there is no memory, or a PCIe window doesn't fit its
+    /// address space.
+    pub fn from_virt_machine(machine: &VirtMachine) -> Result<Self, DeviceTreeError> {
+        machine.build()
     }
 
     /// Generate the DTB binary of a `Tree`.
     pub fn generate_dtb(&self) -> Vec<u8> {
-        let mut reservations = vec![];
-        for reservation in &self.reservations {
-            reservations.push(reservation.clone());
-        }
-        DtbGenerator::from_tree(&self.root.lock().unwrap(), reservations).generate()
+        DtbGenerator::from_tree(self).generate()
+    }
+
+    /// Compare the `Tree` with another one.
+    ///
+    /// The returned `TreeDiff` lists the nodes, properties and reservations
+    /// that are added, removed or changed in `other`.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, DiffEntry, DiffOptions, Node};
+    ///
+    /// let old = DeviceTree::new(vec![], Node::new(""));
+    ///
+    /// let mut root = Node::new("");
+    /// root.add_sub_node(Node::new("memory@40000000"));
+    /// let new = DeviceTree::new(vec![], root);
+    ///
+    /// let diff = old.diff(&new, DiffOptions::default());
+    ///
+    /// assert_eq!(
+    ///     diff.entries,
+    ///     vec![DiffEntry::NodeAdded { path: String::from("/memory@40000000") }]
+    /// );
+    /// ```
+    pub fn diff(&self, other: &DeviceTree, options: DiffOptions) -> TreeDiff {
+        TreeDiff::from_trees(self, other, options)
+    }
+
+    /// Find the nodes matching a selector, see `Selector` for the syntax.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// let mut uart = Node::new("serial@9000000");
+    /// uart.add_property(Property::new_str("compatible", "arm,pl011"));
+    /// root.add_sub_node(uart);
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let uarts = tree.select_nodes("/serial@*[compatible=arm,pl011]").unwrap();
+    /// assert_eq!(tree.path_of(uarts[0]), "/serial@9000000");
+    /// assert!(tree.select_nodes("serial").is_err());
+    /// ```
+    pub fn select_nodes(&self, selector: &str) -> Result<Vec<NodeId>, DeviceTreeError> {
+        Ok(Selector::parse(selector)?.select_nodes(self))
+    }
+
+    /// Find the properties matching a selector, see `Selector` for the syntax.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property};
+    ///
+    /// let mut root = Node::new("");
+    /// let mut cpu = Node::new("cpu@0");
+    /// cpu.add_property(Property::new_str("device_type", "cpu"));
+    /// cpu.add_property(Property::new_str("enable-method", "psci"));
+    /// root.add_sub_node(cpu);
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let props = tree.select_properties("[device_type=cpu]::enable-*").unwrap();
+    /// assert_eq!(props.len(), 1);
+    /// assert_eq!(props[0].1.name, "enable-method");
+    /// ```
+    pub fn select_properties(
+        &self,
+        selector: &str,
+    ) -> Result<Vec<(NodeId, &Property)>, DeviceTreeError> {
+        Ok(Selector::parse(selector)?.select_properties(self))
     }
 }
 
@@ -141,6 +2637,7 @@ impl std::fmt::Display for DeviceTree {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::graft::LabelConflict;
     use crate::node::Node;
     use crate::property::Property;
 
@@ -157,4 +2654,414 @@ mod tests {
             "/dts-v1/;\n\n/ {\n\tprop = <0x0 0x0 0x0 0x2a>;\n\n\tsub_node {\n\t};\n};\n\n"
         );
     }
+
+    #[test]
+    fn test_tree_arena() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let mut tree = DeviceTree::from_dts_bytes(&dts);
+
+        let cpus = tree.find_node_by_path("/cpus").unwrap();
+        let cpu2 = tree.find_node_by_path("/cpus/cpu@2").unwrap();
+        let msic = tree.find_node_by_path("/intc/msic").unwrap();
+        assert_eq!(tree.parent(cpu2), Some(cpus));
+        assert_eq!(tree.parent(cpus), Some(tree.root()));
+        assert_eq!(tree.parent(tree.root()), None);
+        assert_eq!(tree.children(cpus).len(), 4);
+
+        // Removing nodes keeps the other node IDs valid
+        let intc = tree.take_node(tree.parent(msic).unwrap()).unwrap();
+        assert_eq!(intc.sub_nodes[0].name, "msic");
+        assert!(!tree.contains(msic));
+        assert_eq!(tree.node(cpu2).name, "cpu@2");
+        assert_eq!(
+            tree.take_node(tree.root()).err(),
+            Some(DeviceTreeError::InvalidPath(String::from("/")))
+        );
+
+        // The slots of the removed nodes are reused
+        let slots = tree.nodes.len();
+        let cpu = tree.insert_sub_node_at(cpus, 1, Node::new("cpu@4"));
+        assert_eq!(tree.nodes.len(), slots);
+        assert_eq!(tree.children(cpus)[1], cpu);
+        assert_eq!(tree.to_node(cpus).sub_nodes[1].name, "cpu@4");
+        assert_eq!(tree.to_node(cpus).sub_nodes[2].name, "cpu@1");
+
+        // The sub nodes added through node_mut are moved into the tree
+        let mut node = Node::new("thermal-zones");
+        node.add_sub_node(Node::new("cpu-thermal"));
+        tree.node_mut(tree.root()).add_sub_node(node);
+        assert!(tree.node(tree.root()).sub_nodes.is_empty());
+        assert!(tree
+            .find_node_by_path("/thermal-zones/cpu-thermal")
+            .is_some());
+        assert!(tree.generate_dts().contains("cpu-thermal {"));
+    }
+
+    #[test]
+    fn test_tree_node_path() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let mut tree = DeviceTree::from_dts_bytes(&dts);
+
+        let msic = tree.find_node_by_path("/intc/msic").unwrap();
+        assert_eq!(tree.path_of(msic), "/intc/msic");
+        let msic_ref = tree.node_ref(msic);
+        assert_eq!(msic_ref.parent().unwrap().name, "intc");
+        assert_eq!(msic_ref.parent().unwrap().parent().unwrap().path(), "/");
+        assert!(tree.node_ref(tree.root()).parent().is_none());
+        let cpus = tree.node_ref(tree.find_node_by_path("/cpus").unwrap());
+        let paths: Vec<String> = cpus.children().map(|cpu| cpu.path()).collect();
+        assert_eq!(paths[3], "/cpus/cpu@3");
+
+        // The path follows the node when it is moved or renamed
+        tree.get_or_create_node("/soc").unwrap();
+        tree.move_node("/intc", "/soc").unwrap();
+        tree.rename_node("/soc/intc", "intc@8000000").unwrap();
+        assert_eq!(tree.path_of(msic), "/soc/intc@8000000/msic");
+    }
+
+    #[test]
+    fn test_tree_find_node_by_path() {
+        let dts = std::fs::read("test/dts_8.dts").unwrap();
+        let tree = DeviceTree::from_dts_bytes(&dts);
+
+        assert_eq!(tree.find_node_by_path("/"), Some(tree.root()));
+        assert_eq!(tree.find_node_by_path("//"), Some(tree.root()));
+        let soc = tree.find_node_by_path("/soc").unwrap();
+        assert_eq!(tree.find_node_by_path("/soc/"), Some(soc));
+
+        // The unit address can be left out if the name is unique
+        let memory = tree.find_node_by_path("/memory").unwrap();
+        assert_eq!(tree.path_of(memory), "/memory@40000000");
+        assert!(tree.find_node_by_path("/soc/gpio").is_none());
+        assert!(tree.find_node_by_path("/memory@0").is_none());
+
+        // Aliases, with sub paths and options
+        let serial = tree.find_node_by_path("/pl011@9000000").unwrap();
+        assert_eq!(tree.find_node_by_path("serial0"), Some(serial));
+        assert_eq!(
+            tree.find_node_by_path_opts("serial0:115200n8"),
+            Some((serial, Some("115200n8")))
+        );
+        let hog = tree.find_node_by_path("gpio0/line@3").unwrap();
+        assert_eq!(tree.path_of(hog), "/soc/gpio@9030000/line@3");
+        assert_eq!(
+            tree.find_node_by_path_opts("/soc:opt"),
+            Some((soc, Some("opt")))
+        );
+        // An alias must be a full path
+        assert!(tree.find_node_by_path("ethernet0").is_none());
+        assert!(tree.find_node_by_path("serial1").is_none());
+        assert!(tree.find_node_by_path("").is_none());
+    }
+
+    #[test]
+    fn test_tree_share_between_threads() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let tree = std::sync::Arc::new(DeviceTree::from_dts_bytes(&dts));
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let tree = tree.clone();
+                std::thread::spawn(move || {
+                    let path = format!("/cpus/cpu@{i}");
+                    let cpu = tree.find_node_by_path(&path).unwrap();
+                    tree.node(cpu).properties.len()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 4);
+        }
+    }
+
+    #[test]
+    fn test_tree_get_or_create_node() {
+        let dts = std::fs::read("test/dts_2.dts").unwrap();
+        let mut tree = DeviceTree::from_dts_bytes(&dts);
+
+        // An existing node is returned as is
+        let cpu = tree.get_or_create_node("/cpus/cpu@1").unwrap();
+        assert_eq!(tree.node(cpu).properties.len(), 4);
+        let root = tree.get_or_create_node("/").unwrap();
+        assert_eq!(tree.children(root).len(), 1);
+
+        let map = tree.get_or_create_node("/cpus/cpu-map/cluster0/").unwrap();
+        assert_eq!(tree.node(map).name, "cluster0");
+        assert!(tree.find_node_by_path("/cpus/cpu-map/cluster0").is_some());
+
+        // A name without unit address finds the node like find_node_by_path
+        let cpus = tree.find_node_by_path("/cpus").unwrap();
+        let count = tree.children(cpus).len();
+        let pcie = tree.get_or_create_node("/soc/pcie@10000000").unwrap();
+        assert_eq!(tree.get_or_create_node("/soc/pcie"), Ok(pcie));
+        assert_eq!(tree.find_node_by_path("/soc/pcie"), Some(pcie));
+        assert_eq!(tree.children(tree.parent(pcie).unwrap()).len(), 1);
+        assert_eq!(tree.get_or_create_node("/cpus/cpu@1/"), Ok(cpu));
+        assert_eq!(tree.children(cpus).len(), count);
+        tree.take_node(tree.parent(pcie).unwrap()).unwrap();
+
+        assert_eq!(
+            tree.get_or_create_node("/cpus/cpu@").err(),
+            Some(DeviceTreeError::InvalidNodeName(String::from("cpu@")))
+        );
+        assert_eq!(
+            tree.get_or_create_node("cpus").err(),
+            Some(DeviceTreeError::InvalidPath(String::from("cpus")))
+        );
+        // Nothing is created for a failed path
+        assert_eq!(
+            tree.get_or_create_node("/soc/uart 0").err(),
+            Some(DeviceTreeError::InvalidNodeName(String::from("uart 0")))
+        );
+        assert!(tree.find_node_by_path("/soc").is_none());
+    }
+
+    #[test]
+    fn test_tree_rename_and_move() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let mut tree = DeviceTree::from_dts_bytes(&dts);
+
+        tree.rename_node("/intc/msic", "its@8cd0000").unwrap();
+        assert!(tree.find_node_by_path("/intc/its@8cd0000").is_some());
+        assert_eq!(
+            tree.rename_node("/cpus/cpu@0", "cpu@1").err(),
+            Some(DeviceTreeError::NodeExists(String::from("cpu@1")))
+        );
+        assert_eq!(
+            tree.rename_node("/cpus/cpu@0", "cpu 0").err(),
+            Some(DeviceTreeError::InvalidNodeName(String::from("cpu 0")))
+        );
+
+        let intc = tree.find_node_by_path("/intc").unwrap();
+        let soc = tree.get_or_create_node("/soc").unwrap();
+        tree.move_node("/intc", "/soc").unwrap();
+        assert_eq!(tree.find_node_by_path("/soc/intc"), Some(intc));
+        assert_eq!(tree.parent(intc), Some(soc));
+        assert!(tree.find_node_by_path("/soc/intc/its@8cd0000").is_some());
+        assert!(tree.find_node_by_path("/intc").is_none());
+
+        // A node can't be moved into itself
+        assert_eq!(
+            tree.move_node("/soc", "/soc/intc").err(),
+            Some(DeviceTreeError::InvalidPath(String::from("/soc/intc")))
+        );
+        tree.get_or_create_node("/intc").unwrap();
+        assert_eq!(
+            tree.move_node("/soc/intc", "/").err(),
+            Some(DeviceTreeError::NodeExists(String::from("intc")))
+        );
+
+        // The paths are resolved like find_node_by_path
+        let uart = tree.get_or_create_node("/soc/uart@1000").unwrap();
+        tree.rename_node("/soc/uart", "serial@1000").unwrap();
+        assert_eq!(tree.node(uart).name, "serial@1000");
+        tree.move_node("/soc/serial/", "/intc/").unwrap();
+        assert_eq!(tree.find_node_by_path("/intc/serial"), Some(uart));
+        assert_eq!(
+            tree.rename_node("/", "root").err(),
+            Some(DeviceTreeError::InvalidPath(String::from("/")))
+        );
+    }
+
+    #[test]
+    fn test_tree_graft_node() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let mut host = DeviceTree::from_dts_bytes(&dts);
+        let dts = std::fs::read("test/dts_7.dts").unwrap();
+        let mut guest = DeviceTree::from_dts_bytes(&dts);
+        // The guest interrupt controller uses phandle 0, make it conflict
+        guest
+            .set_property_at("/intc", Property::new_u32("phandle", 1))
+            .unwrap();
+        guest
+            .set_property_at("/", Property::new_u32("interrupt-parent", 1))
+            .unwrap();
+
+        // Phandle 1 is used by both trees
+        assert_eq!(
+            guest
+                .graft_node(&host, "/intc", "/chosen", &GraftOptions::default())
+                .err(),
+            Some(DeviceTreeError::DuplicatePhandle(1))
+        );
+
+        let options = GraftOptions {
+            renumber_phandles: true,
+            ..Default::default()
+        };
+        let mut soc = Node::new("soc");
+        soc.add_property(Property::new_u32("interrupt-parent", 1));
+        host.add_sub_node(host.root(), soc);
+        host.move_node("/intc", "/soc").unwrap();
+        let soc = guest.graft_node(&host, "/soc", "/", &options).unwrap();
+        let intc = guest.find_child_by_name(soc, "intc").unwrap();
+        let phandle = guest.node(intc).find_property("phandle").unwrap();
+        // Phandle 2 is used by the copied subtree
+        assert_eq!(phandle.value, 3u32.to_be_bytes().to_vec());
+        // A phandle not in conflict is kept
+        let msic = guest.find_child_by_name(intc, "msic").unwrap();
+        let phandle = guest.node(msic).find_property("phandle").unwrap();
+        assert_eq!(phandle.value, 2u32.to_be_bytes().to_vec());
+        // The reference inside the subtree is rewritten
+        let parent = guest.node(soc).find_property("interrupt-parent").unwrap();
+        assert_eq!(parent.value, 3u32.to_be_bytes().to_vec());
+        // The source tree is not changed
+        let intc = host.find_node_by_path("/soc/intc").unwrap();
+        let phandle = host.node(intc).find_property("phandle").unwrap();
+        assert_eq!(phandle.value, 1u32.to_be_bytes().to_vec());
+        // The guest interrupt controller still has its own phandle
+        let parent = guest.node(guest.root()).find_property("interrupt-parent");
+        assert_eq!(parent.unwrap().value, 1u32.to_be_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_tree_graft_node_phandle_args() {
+        let template = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	soc {
+		clock-controller {
+			phandle = <1>;
+			#clock-cells = <1>;
+		};
+
+		intc {
+			phandle = <2>;
+			#interrupt-cells = <2>;
+			interrupt-controller;
+		};
+
+		serial {
+			clocks = <1 2>;
+			interrupts-extended = <2 1 2>, <3 7>;
+		};
+	};
+};
+"#,
+        );
+        let mut tree = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	intc {
+		phandle = <1>;
+		#interrupt-cells = <1>;
+		interrupt-controller;
+	};
+
+	gpio {
+		phandle = <2>;
+	};
+
+	gpio-intc {
+		phandle = <3>;
+		#interrupt-cells = <1>;
+		interrupt-controller;
+	};
+};
+"#,
+        );
+        let options = GraftOptions {
+            renumber_phandles: true,
+            ..Default::default()
+        };
+        tree.graft_node(&template, "/soc", "/", &options).unwrap();
+
+        // Only the phandle cells are rewritten, the arguments are kept even
+        // if they look like renumbered phandles, and the reference to the
+        // destination tree is kept
+        let serial = tree.find_node_by_path("/soc/serial").unwrap();
+        assert_eq!(
+            tree.node(serial).read_u32_array("clocks", 2),
+            Ok(vec![4, 2])
+        );
+        assert_eq!(
+            tree.node(serial).read_u32_array("interrupts-extended", 5),
+            Ok(vec![5, 1, 2, 3, 7])
+        );
+        assert_eq!(tree.check_phandles(), Ok(()));
+        let clock = tree.find_node_by_path("/soc/clock-controller").unwrap();
+        assert_eq!(
+            tree.parse_phandle_with_args(serial, "clocks", "#clock-cells", 0),
+            Ok(Some(PhandleArgs {
+                node: clock,
+                args: vec![2]
+            }))
+        );
+    }
+
+    #[test]
+    fn test_tree_graft_node_labels() {
+        let mut root = Node::new("");
+        root.add_sub_node(Node::new_with_label("uart@0", "uart"));
+        let template = DeviceTree::new(vec![], root);
+        let mut root = Node::new("");
+        root.add_sub_node(Node::new_with_label("uart@1000", "uart"));
+        let mut tree = DeviceTree::new(vec![], root);
+
+        assert_eq!(
+            tree.graft_node(&template, "/uart@0", "/", &GraftOptions::default())
+                .err(),
+            Some(DeviceTreeError::DuplicateLabel(String::from("uart")))
+        );
+        let options = GraftOptions {
+            label_conflict: LabelConflict::Drop,
+            ..Default::default()
+        };
+        let uart = tree
+            .graft_node(&template, "/uart@0", "/", &options)
+            .unwrap();
+        assert_eq!(tree.node(uart).label, None);
+        assert_eq!(
+            tree.graft_node(&template, "/uart@0", "/", &options).err(),
+            Some(DeviceTreeError::NodeExists(String::from("uart@0")))
+        );
+    }
+
+    #[test]
+    fn test_tree_edit_by_path() {
+        let dts = std::fs::read("test/dts_7.dts").unwrap();
+        let mut tree = DeviceTree::from_dts_bytes(&dts);
+
+        // Replace the property of the root node, add a property to a sub node
+        let old = tree
+            .set_property_at("/", Property::new_str("compatible", "linux,virt"))
+            .unwrap();
+        assert!(old.is_some());
+        assert!(tree
+            .set_property_at("/chosen", Property::new_str("stdout-path", "/pl011"))
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            tree.set_property_at("/none", Property::new_empty("prop"))
+                .err(),
+            Some(DeviceTreeError::NodeNotFound(String::from("/none")))
+        );
+
+        assert_eq!(
+            tree.remove_property_at("/chosen", "none").err(),
+            Some(DeviceTreeError::PropertyNotFound(String::from("none")))
+        );
+        tree.remove_property_at("/chosen", "bootargs").unwrap();
+        let chosen = tree.find_node_by_path("/chosen").unwrap();
+        assert_eq!(tree.node(chosen).properties.len(), 1);
+
+        // The labels of the removed nodes are gone with the nodes
+        assert!(tree.find_node_by_label("interrupt_controller").is_some());
+        tree.remove_node("/intc").unwrap();
+        assert!(tree.find_node_by_label("interrupt_controller").is_none());
+        assert_eq!(
+            tree.remove_node("/").err(),
+            Some(DeviceTreeError::InvalidPath(String::from("/")))
+        );
+        assert_eq!(
+            tree.remove_node("/intc").err(),
+            Some(DeviceTreeError::NodeNotFound(String::from("/intc")))
+        );
+
+        // The path is resolved like find_node_by_path
+        tree.get_or_create_node("/soc/uart@1000").unwrap();
+        assert_eq!(tree.remove_node("/soc/uart").unwrap().name, "uart@1000");
+        assert_eq!(tree.remove_node("/soc/").unwrap().name, "soc");
+        assert!(tree.find_node_by_path("/soc").is_none());
+    }
 }
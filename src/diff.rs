@@ -0,0 +1,677 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::node::NodeId;
+use crate::phandle::PhandleReference;
+use crate::property::{Property, PropertyValue};
+use crate::reservation::Reservation;
+use crate::utils::Utils;
+use crate::visit::sub_path;
+use std::collections::HashMap;
+
+/// Options that control how two device trees are compared.
+#[derive(Clone, Copy, Default)]
+pub struct DiffOptions {
+    /// Ignore the numbering of phandles.
+    ///
+    /// The `phandle` and `linux,phandle` properties are not compared, and
+    /// the phandles held by properties (like `interrupt-parent`, `clocks`
+    /// or `interrupt-map`) are compared by the paths of the nodes they point
+    /// at, while their arguments are compared by value.
+    pub ignore_phandles: bool,
+    /// Ignore the order of properties and sub nodes inside a node.
+    pub ignore_order: bool,
+}
+
+/// A single difference between two device trees.
+///
+/// Nodes are identified by their full path, property values are interpreted
+/// with `Property::typed_value()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffEntry {
+    NodeAdded {
+        path: String,
+    },
+    NodeRemoved {
+        path: String,
+    },
+    PropertyAdded {
+        path: String,
+        name: String,
+        value: PropertyValue,
+    },
+    PropertyRemoved {
+        path: String,
+        name: String,
+        value: PropertyValue,
+    },
+    PropertyChanged {
+        path: String,
+        name: String,
+        old: PropertyValue,
+        new: PropertyValue,
+    },
+    OrderChanged {
+        path: String,
+    },
+    ReservationAdded(Reservation),
+    ReservationRemoved(Reservation),
+    ReservationOrderChanged,
+}
+
+/// The semantic differences between two device trees.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, DiffEntry, DiffOptions, Node, Property};
+///
+/// let mut root = Node::new("");
+/// root.add_sub_node(Node::new("chosen"));
+/// let old = DeviceTree::new(vec![], root);
+///
+/// let mut root = Node::new("");
+/// let mut chosen = Node::new("chosen");
+/// chosen.add_property(Property::new_str("bootargs", "console=ttyAMA0"));
+/// root.add_sub_node(chosen);
+/// let new = DeviceTree::new(vec![], root);
+///
+/// let diff = old.diff(&new, DiffOptions::default());
+///
+/// assert_eq!(diff.entries.len(), 1);
+/// assert_eq!(format!("{}", diff), "+ /chosen: bootargs = \"console=ttyAMA0\";\n");
+/// ```
+pub struct TreeDiff {
+    pub entries: Vec<DiffEntry>,
+}
+
+// The phandles of a tree, mapped to the paths of the nodes owning them.
+struct PhandleMap {
+    paths: HashMap<u32, String>,
+}
+
+impl PhandleMap {
+    fn from_tree(tree: &DeviceTree) -> Self {
+        let mut paths = HashMap::new();
+        for (path, prop) in tree.iter_properties() {
+            if (prop.name == "phandle" || prop.name == "linux,phandle") && prop.value.len() == 4 {
+                let phandle = u32::from_be_bytes(prop.value[0..4].try_into().unwrap());
+                paths.insert(phandle, path);
+            }
+        }
+        PhandleMap { paths }
+    }
+}
+
+// A cell of a property compared with `ignore_phandles`: a phandle is
+// compared by the path of the node it refers to.
+#[derive(PartialEq)]
+enum Cell {
+    Value(u32),
+    Phandle(String),
+}
+
+// The trees being compared, and how to compare them.
+struct DiffContext<'a> {
+    old: &'a DeviceTree,
+    new: &'a DeviceTree,
+    old_phandles: PhandleMap,
+    new_phandles: PhandleMap,
+    options: DiffOptions,
+}
+
+impl TreeDiff {
+    /// Compare two device trees, reporting what has to change in `old` to get `new`.
+    pub fn from_trees(old: &DeviceTree, new: &DeviceTree, options: DiffOptions) -> Self {
+        let mut diff = TreeDiff { entries: vec![] };
+
+        let ctx = DiffContext {
+            old,
+            new,
+            old_phandles: PhandleMap::from_tree(old),
+            new_phandles: PhandleMap::from_tree(new),
+            options,
+        };
+        diff.diff_node(old.root(), new.root(), "/", &ctx);
+
+        diff.diff_reservations(&old.reservations, &new.reservations, &options);
+        diff
+    }
+
+    /// Check if the two trees are the same.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Generate the JSON text of the differences.
+    ///
+    /// The output is an object with a single `changes` array. Each change has
+    /// a `kind` and the fields of the matching `DiffEntry`, property values are
+    /// objects with a `type` (`empty`, `strings`, `cells` or `bytes`) and a `value`.
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self.entries.iter().map(TreeDiff::entry_json).collect();
+        format!("{{\"changes\":[{}]}}", entries.join(","))
+    }
+
+    // Compare the reservations one to one, so that a reservation given twice
+    // on one side is matched only once. The labels of the reservations are
+    // not compared, like the labels of the nodes.
+    fn diff_reservations(
+        &mut self,
+        old: &[Reservation],
+        new: &[Reservation],
+        options: &DiffOptions,
+    ) {
+        let same =
+            |a: &Reservation, b: &Reservation| a.address == b.address && a.length == b.length;
+        // The index in `new` matched by each reservation of `old`
+        let mut matched: Vec<Option<usize>> = vec![];
+        for r in old {
+            let index = (0..new.len()).find(|i| !matched.contains(&Some(*i)) && same(r, &new[*i]));
+            if index.is_none() {
+                self.entries.push(DiffEntry::ReservationRemoved(r.clone()));
+            }
+            matched.push(index);
+        }
+        for (i, r) in new.iter().enumerate() {
+            if !matched.contains(&Some(i)) {
+                self.entries.push(DiffEntry::ReservationAdded(r.clone()));
+            }
+        }
+
+        // The matched reservations are in the same order on both sides if
+        // their indexes in `new` are increasing
+        let order: Vec<usize> = matched.into_iter().flatten().collect();
+        if !options.ignore_order && order.windows(2).any(|w| w[0] > w[1]) {
+            self.entries.push(DiffEntry::ReservationOrderChanged);
+        }
+    }
+
+    fn diff_node(&mut self, old_id: NodeId, new_id: NodeId, path: &str, ctx: &DiffContext) {
+        let (old, new) = (ctx.old.node(old_id), ctx.new.node(new_id));
+        let options = &ctx.options;
+        let skipped = |prop: &Property| {
+            options.ignore_phandles && (prop.name == "phandle" || prop.name == "linux,phandle")
+        };
+
+        // Properties
+        let old_props: Vec<&Property> = old.properties.iter().filter(|p| !skipped(p)).collect();
+        let new_props: Vec<&Property> = new.properties.iter().filter(|p| !skipped(p)).collect();
+
+        for old_prop in &old_props {
+            match new_props.iter().find(|p| p.name == old_prop.name) {
+                Some(new_prop) => {
+                    let resolved = || {
+                        let old_cells = TreeDiff::resolve_phandles(
+                            ctx.old,
+                            old_id,
+                            old_prop,
+                            &ctx.old_phandles,
+                        );
+                        let new_cells = TreeDiff::resolve_phandles(
+                            ctx.new,
+                            new_id,
+                            new_prop,
+                            &ctx.new_phandles,
+                        );
+                        old_cells.is_some() && old_cells == new_cells
+                    };
+                    let same =
+                        old_prop.value == new_prop.value || (options.ignore_phandles && resolved());
+                    if !same {
+                        self.entries.push(DiffEntry::PropertyChanged {
+                            path: String::from(path),
+                            name: old_prop.name.clone(),
+                            old: old_prop.typed_value(),
+                            new: new_prop.typed_value(),
+                        });
+                    }
+                }
+                None => self.entries.push(DiffEntry::PropertyRemoved {
+                    path: String::from(path),
+                    name: old_prop.name.clone(),
+                    value: old_prop.typed_value(),
+                }),
+            }
+        }
+        for new_prop in &new_props {
+            if !old_props.iter().any(|p| p.name == new_prop.name) {
+                self.entries.push(DiffEntry::PropertyAdded {
+                    path: String::from(path),
+                    name: new_prop.name.clone(),
+                    value: new_prop.typed_value(),
+                });
+            }
+        }
+
+        // Sub nodes
+        let old_nodes: Vec<_> = ctx
+            .old
+            .children(old_id)
+            .iter()
+            .map(|id| (*id, ctx.old.node(*id)))
+            .collect();
+        let new_nodes: Vec<_> = ctx
+            .new
+            .children(new_id)
+            .iter()
+            .map(|id| (*id, ctx.new.node(*id)))
+            .collect();
+        let mut sub_node_pairs = vec![];
+        for (old_sub_id, old_node) in &old_nodes {
+            let sub_path = sub_path(path, &old_node.name);
+            match new_nodes.iter().find(|(_, n)| n.name == old_node.name) {
+                Some((new_sub_id, _)) => sub_node_pairs.push((sub_path, *old_sub_id, *new_sub_id)),
+                None => self.entries.push(DiffEntry::NodeRemoved { path: sub_path }),
+            }
+        }
+        for (_, new_node) in &new_nodes {
+            if !old_nodes.iter().any(|(_, n)| n.name == new_node.name) {
+                let sub_path = sub_path(path, &new_node.name);
+                self.entries.push(DiffEntry::NodeAdded { path: sub_path });
+            }
+        }
+
+        // Order of the properties and sub nodes that exist on both sides
+        if !options.ignore_order {
+            let common = |names: Vec<&String>, others: &Vec<&String>| -> Vec<String> {
+                names
+                    .into_iter()
+                    .filter(|n| others.contains(n))
+                    .cloned()
+                    .collect()
+            };
+            let old_prop_names: Vec<&String> = old_props.iter().map(|p| &p.name).collect();
+            let new_prop_names: Vec<&String> = new_props.iter().map(|p| &p.name).collect();
+            let old_node_names: Vec<&String> = old_nodes.iter().map(|(_, n)| &n.name).collect();
+            let new_node_names: Vec<&String> = new_nodes.iter().map(|(_, n)| &n.name).collect();
+            if common(old_prop_names.clone(), &new_prop_names)
+                != common(new_prop_names, &old_prop_names)
+                || common(old_node_names.clone(), &new_node_names)
+                    != common(new_node_names, &old_node_names)
+            {
+                self.entries.push(DiffEntry::OrderChanged {
+                    path: String::from(path),
+                });
+            }
+        }
+
+        for (sub_path, old_node, new_node) in sub_node_pairs {
+            self.diff_node(old_node, new_node, &sub_path, ctx);
+        }
+    }
+
+    // Get the cells of a property, where the phandles are resolved to the
+    // paths of the nodes they refer to. The phandle cells are found by the
+    // layout of the property, so the arguments after the phandles and the
+    // other cells of the maps are kept as they are. `None` is returned if
+    // the property is not made of cells.
+    fn resolve_phandles(
+        tree: &DeviceTree,
+        id: NodeId,
+        prop: &Property,
+        phandles: &PhandleMap,
+    ) -> Option<Vec<Cell>> {
+        if !prop.value.len().is_multiple_of(4) {
+            return None;
+        }
+        let mut cells: Vec<Cell> = prop
+            .value
+            .chunks(4)
+            .map(|c| Cell::Value(u32::from_be_bytes(c.try_into().unwrap())))
+            .collect();
+        for (cell, phandle) in PhandleReference::find_in_property(tree, id, &prop.name) {
+            if let Some(path) = phandles.paths.get(&phandle) {
+                cells[cell] = Cell::Phandle(path.clone());
+            }
+        }
+        Some(cells)
+    }
+
+    fn value_text(value: &PropertyValue) -> String {
+        match value {
+            PropertyValue::Empty => String::from("(empty)"),
+            _ => format!("{}", value),
+        }
+    }
+
+    fn entry_json(entry: &DiffEntry) -> String {
+        match entry {
+            DiffEntry::NodeAdded { path } => format!(
+                "{{\"kind\":\"node-added\",\"path\":{}}}",
+                Utils::json_string(path)
+            ),
+            DiffEntry::NodeRemoved { path } => format!(
+                "{{\"kind\":\"node-removed\",\"path\":{}}}",
+                Utils::json_string(path)
+            ),
+            DiffEntry::PropertyAdded { path, name, value } => format!(
+                "{{\"kind\":\"property-added\",\"path\":{},\"name\":{},\"value\":{}}}",
+                Utils::json_string(path),
+                Utils::json_string(name),
+                value.to_json()
+            ),
+            DiffEntry::PropertyRemoved { path, name, value } => format!(
+                "{{\"kind\":\"property-removed\",\"path\":{},\"name\":{},\"value\":{}}}",
+                Utils::json_string(path),
+                Utils::json_string(name),
+                value.to_json()
+            ),
+            DiffEntry::PropertyChanged {
+                path,
+                name,
+                old,
+                new,
+            } => format!(
+                "{{\"kind\":\"property-changed\",\"path\":{},\"name\":{},\"old\":{},\"new\":{}}}",
+                Utils::json_string(path),
+                Utils::json_string(name),
+                old.to_json(),
+                new.to_json()
+            ),
+            DiffEntry::OrderChanged { path } => format!(
+                "{{\"kind\":\"order-changed\",\"path\":{}}}",
+                Utils::json_string(path)
+            ),
+            DiffEntry::ReservationAdded(r) => format!(
+                "{{\"kind\":\"reservation-added\",\"address\":{},\"length\":{}}}",
+                r.address, r.length
+            ),
+            DiffEntry::ReservationRemoved(r) => format!(
+                "{{\"kind\":\"reservation-removed\",\"address\":{},\"length\":{}}}",
+                r.address, r.length
+            ),
+            DiffEntry::ReservationOrderChanged => {
+                String::from("{\"kind\":\"reservation-order-changed\"}")
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for DiffEntry {
+    /// Print a `DiffEntry` as a line of a human readable diff
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiffEntry::NodeAdded { path } => write!(f, "+ {path}"),
+            DiffEntry::NodeRemoved { path } => write!(f, "- {path}"),
+            DiffEntry::PropertyAdded { path, name, value } => match value {
+                PropertyValue::Empty => write!(f, "+ {path}: {name};"),
+                _ => write!(f, "+ {path}: {name} = {value};"),
+            },
+            DiffEntry::PropertyRemoved { path, name, value } => match value {
+                PropertyValue::Empty => write!(f, "- {path}: {name};"),
+                _ => write!(f, "- {path}: {name} = {value};"),
+            },
+            DiffEntry::PropertyChanged {
+                path,
+                name,
+                old,
+                new,
+            } => write!(
+                f,
+                "~ {path}: {name} = {} -> {}",
+                TreeDiff::value_text(old),
+                TreeDiff::value_text(new)
+            ),
+            DiffEntry::OrderChanged { path } => write!(f, "* {path}: order changed"),
+            DiffEntry::ReservationAdded(r) => write!(f, "+ {r}"),
+            DiffEntry::ReservationRemoved(r) => write!(f, "- {r}"),
+            DiffEntry::ReservationOrderChanged => write!(f, "* /memreserve/: order changed"),
+        }
+    }
+}
+
+impl std::fmt::Display for TreeDiff {
+    /// Print the differences one per line
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+
+    fn tree_with_intc(phandle: u32, reorder: bool) -> DeviceTree {
+        let mut root = Node::new("");
+        root.add_property(Property::new_u32("interrupt-parent", phandle));
+        let mut intc = Node::new("intc");
+        intc.add_property(Property::new_empty("interrupt-controller"));
+        intc.add_property(Property::new_u32("phandle", phandle));
+        let memory = Node::new("memory");
+        if reorder {
+            root.add_sub_node(memory);
+            root.add_sub_node(intc);
+        } else {
+            root.add_sub_node(intc);
+            root.add_sub_node(memory);
+        }
+        DeviceTree::new(vec![], root)
+    }
+
+    #[test]
+    fn test_diff_same() {
+        let a = tree_with_intc(1, false);
+        let b = tree_with_intc(1, false);
+        assert!(TreeDiff::from_trees(&a, &b, DiffOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_nodes_and_properties() {
+        let dts = std::fs::read("test/dts_2.dts").unwrap();
+        let a = DeviceTree::from_dts_bytes(&dts);
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let b = DeviceTree::from_dts_bytes(&dts);
+
+        let diff = TreeDiff::from_trees(&a, &b, DiffOptions::default());
+        assert!(diff.entries.contains(&DiffEntry::NodeAdded {
+            path: String::from("/cpus/cpu@2")
+        }));
+        assert!(diff.entries.contains(&DiffEntry::NodeAdded {
+            path: String::from("/memory")
+        }));
+        assert!(!diff
+            .entries
+            .iter()
+            .any(|e| matches!(e, DiffEntry::NodeRemoved { .. })));
+
+        let diff = TreeDiff::from_trees(&b, &a, DiffOptions::default());
+        assert!(diff.entries.contains(&DiffEntry::NodeRemoved {
+            path: String::from("/intc")
+        }));
+    }
+
+    #[test]
+    fn test_diff_property_changed() {
+        let mut root = Node::new("");
+        root.add_property(Property::new_str("status", "okay"));
+        root.add_property(Property::new_empty("dma-coherent"));
+        let a = DeviceTree::new(vec![], root);
+
+        let mut root = Node::new("");
+        root.add_property(Property::new_str("status", "disabled"));
+        root.add_property(Property::new_u32("#size-cells", 2));
+        let b = DeviceTree::new(vec![], root);
+
+        let diff = TreeDiff::from_trees(&a, &b, DiffOptions::default());
+        assert_eq!(
+            diff.entries[0],
+            DiffEntry::PropertyChanged {
+                path: String::from("/"),
+                name: String::from("status"),
+                old: PropertyValue::Strings(vec![String::from("okay")]),
+                new: PropertyValue::Strings(vec![String::from("disabled")]),
+            }
+        );
+        assert_eq!(
+            format!("{}", diff),
+            "~ /: status = \"okay\" -> \"disabled\"\n\
+            - /: dma-coherent;\n\
+            + /: #size-cells = <0x2>;\n"
+        );
+        assert_eq!(
+            diff.to_json(),
+            "{\"changes\":[\
+            {\"kind\":\"property-changed\",\"path\":\"/\",\"name\":\"status\",\
+            \"old\":{\"type\":\"strings\",\"value\":[\"okay\"]},\
+            \"new\":{\"type\":\"strings\",\"value\":[\"disabled\"]}},\
+            {\"kind\":\"property-removed\",\"path\":\"/\",\"name\":\"dma-coherent\",\
+            \"value\":{\"type\":\"empty\"}},\
+            {\"kind\":\"property-added\",\"path\":\"/\",\"name\":\"#size-cells\",\
+            \"value\":{\"type\":\"cells\",\"value\":[2]}}]}"
+        );
+    }
+
+    #[test]
+    fn test_diff_ignore_phandles() {
+        let a = tree_with_intc(1, false);
+        let b = tree_with_intc(5, false);
+
+        let diff = TreeDiff::from_trees(&a, &b, DiffOptions::default());
+        assert_eq!(diff.entries.len(), 2);
+
+        let options = DiffOptions {
+            ignore_phandles: true,
+            ..Default::default()
+        };
+        assert!(TreeDiff::from_trees(&a, &b, options).is_empty());
+    }
+
+    #[test]
+    fn test_diff_ignore_phandles_with_args() {
+        let tree = |clock: u32, intc: u32, clock_arg: u32| {
+            DeviceTree::from_dts_bytes(
+                format!(
+                    r#"/dts-v1/;
+/ {{
+	clock-controller {{
+		phandle = <{clock}>;
+		#clock-cells = <1>;
+	}};
+
+	intc {{
+		phandle = <{intc}>;
+		#interrupt-cells = <2>;
+		interrupt-controller;
+	}};
+
+	serial {{
+		clocks = <{clock} {clock_arg}>;
+		interrupts-extended = <{intc} 1 2>;
+	}};
+}};
+"#
+                )
+                .as_bytes(),
+            )
+        };
+        let options = DiffOptions {
+            ignore_phandles: true,
+            ..Default::default()
+        };
+
+        let a = tree(1, 2, 3);
+        let b = tree(2, 1, 3);
+        assert_eq!(
+            TreeDiff::from_trees(&a, &b, DiffOptions::default())
+                .entries
+                .len(),
+            4
+        );
+        assert!(TreeDiff::from_trees(&a, &b, options).is_empty());
+
+        // The arguments are still compared by value
+        let c = tree(2, 1, 4);
+        let diff = TreeDiff::from_trees(&a, &c, options);
+        assert_eq!(diff.entries.len(), 1);
+        assert!(matches!(
+            &diff.entries[0],
+            DiffEntry::PropertyChanged { name, .. } if name == "clocks"
+        ));
+    }
+
+    #[test]
+    fn test_diff_ignore_order() {
+        let a = tree_with_intc(1, false);
+        let b = tree_with_intc(1, true);
+
+        let diff = TreeDiff::from_trees(&a, &b, DiffOptions::default());
+        assert_eq!(
+            diff.entries,
+            vec![DiffEntry::OrderChanged {
+                path: String::from("/")
+            }]
+        );
+
+        let options = DiffOptions {
+            ignore_order: true,
+            ..Default::default()
+        };
+        assert!(TreeDiff::from_trees(&a, &b, options).is_empty());
+    }
+
+    #[test]
+    fn test_diff_reservations() {
+        let a = DeviceTree::new(vec![Reservation::new(0x0, 0x1000)], Node::new(""));
+        let b = DeviceTree::new(vec![Reservation::new(0x1000, 0x1000)], Node::new(""));
+
+        let diff = TreeDiff::from_trees(&a, &b, DiffOptions::default());
+        assert_eq!(
+            diff.entries,
+            vec![
+                DiffEntry::ReservationRemoved(Reservation::new(0x0, 0x1000)),
+                DiffEntry::ReservationAdded(Reservation::new(0x1000, 0x1000)),
+            ]
+        );
+        assert_eq!(
+            format!("{}", diff),
+            "- /memreserve/ 0x0000000000000000 0x0000000000001000;\n\
+            + /memreserve/ 0x0000000000001000 0x0000000000001000;\n"
+        );
+
+        // The reservations are matched one to one
+        let twice = vec![Reservation::new(0x1000, 0x1000); 2];
+        let c = DeviceTree::new(twice, Node::new(""));
+        assert_eq!(
+            TreeDiff::from_trees(&b, &c, DiffOptions::default()).entries,
+            vec![DiffEntry::ReservationAdded(Reservation::new(
+                0x1000, 0x1000
+            ))]
+        );
+        assert_eq!(
+            TreeDiff::from_trees(&c, &b, DiffOptions::default()).entries,
+            vec![DiffEntry::ReservationRemoved(Reservation::new(
+                0x1000, 0x1000
+            ))]
+        );
+
+        // The order is compared unless it is ignored
+        let d = DeviceTree::new(
+            vec![
+                Reservation::new(0x0, 0x1000),
+                Reservation::new(0x1000, 0x1000),
+            ],
+            Node::new(""),
+        );
+        let e = DeviceTree::new(
+            vec![
+                Reservation::new(0x1000, 0x1000),
+                Reservation::new(0x0, 0x1000),
+            ],
+            Node::new(""),
+        );
+        let diff = TreeDiff::from_trees(&d, &e, DiffOptions::default());
+        assert_eq!(diff.entries, vec![DiffEntry::ReservationOrderChanged]);
+        assert_eq!(format!("{}", diff), "* /memreserve/: order changed\n");
+        let options = DiffOptions {
+            ignore_order: true,
+            ..Default::default()
+        };
+        assert!(TreeDiff::from_trees(&d, &e, options).is_empty());
+    }
+}
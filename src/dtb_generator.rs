@@ -1,23 +1,21 @@
 // Copyright (c) 2023, Michael Zhao
 // SPDX-License-Identifier: MIT
 
+use crate::devicetree::DeviceTree;
 use crate::dtb::DtbHeader;
-use crate::node::Node;
+use crate::node::NodeId;
 use crate::property::Property;
-use crate::reservation::Reservation;
-use std::sync::{Arc, Mutex};
 
 #[allow(dead_code)]
 pub struct DtbGenerator<'a> {
     header: DtbHeader,
-    reservations: Vec<Arc<Mutex<Reservation>>>,
     strings_block: Vec<u8>,
     structure_block: Vec<u8>,
-    root_node: &'a Node,
+    tree: &'a DeviceTree,
 }
 
 impl DtbGenerator<'_> {
-    pub fn from_tree(root_node: &Node, reservations: Vec<Arc<Mutex<Reservation>>>) -> DtbGenerator {
+    pub fn from_tree(tree: &DeviceTree) -> DtbGenerator<'_> {
         let header = DtbHeader {
             magic: 0u32,
             total_size: 0u32,
@@ -35,10 +33,9 @@ impl DtbGenerator<'_> {
         let structure_block: Vec<u8> = vec![];
         DtbGenerator {
             header,
-            reservations,
             strings_block,
             structure_block,
-            root_node,
+            tree,
         }
     }
 
@@ -108,7 +105,9 @@ impl DtbGenerator<'_> {
         bytes
     }
 
-    fn generate_node(&mut self, node: &Node) -> Vec<u8> {
+    fn generate_node(&mut self, id: NodeId) -> Vec<u8> {
+        let tree = self.tree;
+        let node = tree.node(id);
         let mut token = 1u32.to_be_bytes().to_vec();
         let mut name = if &node.name != "/" {
             node.name.clone().as_bytes().to_owned()
@@ -128,12 +127,12 @@ impl DtbGenerator<'_> {
         }
 
         for prop in node.properties.iter() {
-            let mut prop_bytes = self.generate_property(&prop.lock().unwrap());
+            let mut prop_bytes = self.generate_property(prop);
             bytes.append(&mut prop_bytes);
         }
 
-        for sub_node in node.sub_nodes.iter() {
-            let mut node_bytes = self.generate_node(&sub_node.lock().unwrap());
+        for sub_node in tree.children(id) {
+            let mut node_bytes = self.generate_node(*sub_node);
             bytes.append(&mut node_bytes);
         }
 
@@ -144,7 +143,7 @@ impl DtbGenerator<'_> {
     }
 
     fn generate_structure_block(&mut self) -> Vec<u8> {
-        let root = self.root_node;
+        let root = self.tree.root();
         let mut token = 9u32.to_be_bytes().to_vec();
 
         let mut bytes = self.generate_node(root);
@@ -155,11 +154,11 @@ impl DtbGenerator<'_> {
 
     fn generate_reservation_block(&self) -> Vec<u8> {
         let mut bytes: Vec<u8> = vec![];
-        let reservations = &self.reservations;
+        let reservations = &self.tree.reservations;
         for reservation in reservations {
-            let address = reservation.lock().unwrap().address;
+            let address = reservation.address;
             let mut address = address.to_be_bytes().to_vec();
-            let length = reservation.lock().unwrap().length;
+            let length = reservation.length;
             let mut length = length.to_be_bytes().to_vec();
             bytes.append(&mut address);
             bytes.append(&mut length);
@@ -205,6 +204,8 @@ mod tests {
     use crate::devicetree::DeviceTree;
     use crate::dtb_parser::DtbParser;
     use crate::dts_generator::DtsGenerator;
+    use crate::node::Node;
+    use crate::reservation::Reservation;
     use std::fs::File;
     use std::io::prelude::*;
 
@@ -216,26 +217,14 @@ mod tests {
         let tree = DeviceTree::new(vec![], root);
 
         // Generate the DTB
-        let x = tree.root.lock().unwrap();
-
-        let mut dtb_generator = DtbGenerator::from_tree(&x, vec![]);
+        let mut dtb_generator = DtbGenerator::from_tree(&tree);
         let dtb_bytes = dtb_generator.generate();
 
         // Parse the generated DTB and check
         let tree = DtbParser::from_bytes(&dtb_bytes).parse();
-        assert_eq!(tree.root.lock().unwrap().name, "");
-        assert_eq!(
-            tree.root.lock().unwrap().properties[0].lock().unwrap().name,
-            "compatible"
-        );
-        assert_eq!(
-            tree.root.lock().unwrap().properties[0]
-                .lock()
-                .unwrap()
-                .value
-                .len(),
-            17
-        );
+        assert_eq!(tree.node(tree.root()).name, "");
+        assert_eq!(tree.node(tree.root()).properties[0].name, "compatible");
+        assert_eq!(tree.node(tree.root()).properties[0].value.len(), 17);
         let s = DtsGenerator::generate_tree(&tree);
         assert_eq!(s, "/dts-v1/;\n\n/ {\n\tcompatible = <0x6c 0x69 0x6e 0x75 0x78 0x2c 0x64 0x75 0x6d 0x6d 0x79 0x2d 0x76 0x69 0x72 0x74 0x0>;\n};\n");
     }
@@ -251,58 +240,33 @@ mod tests {
 
         // Check the tree structure
         let tree = DeviceTree::new(vec![], root);
-        assert_eq!(
-            tree.root.lock().unwrap().properties[0].lock().unwrap().name,
-            "compatible"
-        );
-        assert_eq!(
-            tree.root.lock().unwrap().properties[0]
-                .lock()
-                .unwrap()
-                .value
-                .len(),
-            17
-        );
-        assert_eq!(
-            tree.root.lock().unwrap().properties[1].lock().unwrap().name,
-            "#address-cells"
-        );
+        assert_eq!(tree.node(tree.root()).properties[0].name, "compatible");
+        assert_eq!(tree.node(tree.root()).properties[0].value.len(), 17);
+        assert_eq!(tree.node(tree.root()).properties[1].name, "#address-cells");
         assert_eq!(
             u32::from_be_bytes(
-                tree.root.lock().unwrap().properties[1]
-                    .lock()
-                    .unwrap()
-                    .value[0..4]
+                tree.node(tree.root()).properties[1].value[0..4]
                     .try_into()
                     .unwrap()
             ),
             2u32
         );
-        assert_eq!(
-            tree.root.lock().unwrap().properties[2].lock().unwrap().name,
-            "#size-cells"
-        );
+        assert_eq!(tree.node(tree.root()).properties[2].name, "#size-cells");
         assert_eq!(
             u32::from_be_bytes(
-                tree.root.lock().unwrap().properties[2]
-                    .lock()
-                    .unwrap()
-                    .value[0..4]
+                tree.node(tree.root()).properties[2].value[0..4]
                     .try_into()
                     .unwrap()
             ),
             2u32
         );
         assert_eq!(
-            tree.root.lock().unwrap().properties[3].lock().unwrap().name,
+            tree.node(tree.root()).properties[3].name,
             "interrupt-parent"
         );
         assert_eq!(
             u32::from_be_bytes(
-                tree.root.lock().unwrap().properties[3]
-                    .lock()
-                    .unwrap()
-                    .value[0..4]
+                tree.node(tree.root()).properties[3].value[0..4]
                     .try_into()
                     .unwrap()
             ),
@@ -320,29 +284,20 @@ mod tests {
         let mut root = Node::new("");
         root.add_property(Property::new_strs("compatible", vec!["linux,dummy-virt"]));
         let mut reservations = vec![];
-        reservations.push(Arc::new(Mutex::new(Reservation::new(0x0, 0x100000))));
-        reservations.push(Arc::new(Mutex::new(Reservation::new(0x100000, 0x100000))));
-        reservations.push(Arc::new(Mutex::new(Reservation::new(0x200000, 0x100000))));
+        reservations.push(Reservation::new(0x0, 0x100000));
+        reservations.push(Reservation::new(0x100000, 0x100000));
+        reservations.push(Reservation::new(0x200000, 0x100000));
+        let tree = DeviceTree::new(reservations, root);
 
         // Generate the DTB
-        let mut dtb_generator = DtbGenerator::from_tree(&root, reservations);
+        let mut dtb_generator = DtbGenerator::from_tree(&tree);
         let dtb_bytes = dtb_generator.generate();
 
         // Parse the generated DTB and check
         let tree = DtbParser::from_bytes(&dtb_bytes).parse();
-        assert_eq!(tree.root.lock().unwrap().name, "");
-        assert_eq!(
-            tree.root.lock().unwrap().properties[0].lock().unwrap().name,
-            "compatible"
-        );
-        assert_eq!(
-            tree.root.lock().unwrap().properties[0]
-                .lock()
-                .unwrap()
-                .value
-                .len(),
-            17
-        );
+        assert_eq!(tree.node(tree.root()).name, "");
+        assert_eq!(tree.node(tree.root()).properties[0].name, "compatible");
+        assert_eq!(tree.node(tree.root()).properties[0].value.len(), 17);
         let s = DtsGenerator::generate_tree(&tree);
         assert_eq!(s, "/dts-v1/;\n\n/memreserve/ 0x0000000000000000 0x0000000000100000;\n/memreserve/ 0x0000000000100000 0x0000000000100000;\n/memreserve/ 0x0000000000200000 0x0000000000100000;\n\n/ {\n\tcompatible = <0x6c 0x69 0x6e 0x75 0x78 0x2c 0x64 0x75 0x6d 0x6d 0x79 0x2d 0x76 0x69 0x72 0x74 0x0>;\n};\n");
     }
@@ -357,8 +312,7 @@ mod tests {
 
         let tree = DtbParser::from_bytes(&buffer).parse();
 
-        let x = tree.root.lock().unwrap();
-        let mut dtb_generator = DtbGenerator::from_tree(&x, vec![]);
+        let mut dtb_generator = DtbGenerator::from_tree(&tree);
         let dtb_bytes = dtb_generator.generate();
 
         // parse the generated DTB
@@ -2,7 +2,11 @@
 // SPDX-License-Identifier: MIT
 
 use crate::{
-    devicetree::DeviceTree, node::Node, property::Property, reservation::Reservation, utils::Utils,
+    devicetree::DeviceTree,
+    node::{Node, NodeId},
+    property::Property,
+    reservation::Reservation,
+    utils::Utils,
 };
 
 pub struct DtsGenerator {}
@@ -27,6 +31,25 @@ impl DtsGenerator {
     }
 
     pub fn generate_node(node: &Node, indent_level: u32) -> String {
+        let sub_nodes = node
+            .sub_nodes
+            .iter()
+            .map(|sub_node| DtsGenerator::generate_node(sub_node, indent_level + 1))
+            .collect();
+        DtsGenerator::generate_node_text(node, sub_nodes, indent_level)
+    }
+
+    pub fn generate_tree_node(tree: &DeviceTree, id: NodeId, indent_level: u32) -> String {
+        let sub_nodes = tree
+            .children(id)
+            .iter()
+            .map(|sub_node| DtsGenerator::generate_tree_node(tree, *sub_node, indent_level + 1))
+            .collect();
+        DtsGenerator::generate_node_text(tree.node(id), sub_nodes, indent_level)
+    }
+
+    // Generate the text of a node with the generated text of its sub nodes.
+    fn generate_node_text(node: &Node, sub_nodes: Vec<String>, indent_level: u32) -> String {
         let mut s = String::new();
         let indents = Utils::indent(indent_level);
         s.push_str(&format!("{indents}"));
@@ -42,19 +65,13 @@ impl DtsGenerator {
         }
         s.push_str("{\n");
         for prop in &node.properties {
-            s.push_str(&DtsGenerator::generate_property(
-                &prop.clone().lock().unwrap(),
-                indent_level + 1,
-            ));
+            s.push_str(&DtsGenerator::generate_property(prop, indent_level + 1));
             s.push_str("\n");
         }
 
-        for sub_node in node.sub_nodes.iter() {
+        for sub_node in sub_nodes {
             s.push_str("\n");
-            s.push_str(&DtsGenerator::generate_node(
-                &sub_node.clone().lock().unwrap(),
-                indent_level + 1,
-            ));
+            s.push_str(&sub_node);
             s.push_str("\n");
         }
         s.push_str(&format!("{indents}}};"));
@@ -62,8 +79,12 @@ impl DtsGenerator {
     }
 
     pub fn generate_reservation(reservation: &Reservation, _indent_level: u32) -> String {
+        let label = match &reservation.label {
+            Some(label) => format!("{label}: "),
+            None => String::new(),
+        };
         String::from(format!(
-            "/memreserve/ {:#018x} {:#018x};",
+            "{label}/memreserve/ {:#018x} {:#018x};",
             reservation.address, reservation.length
         ))
     }
@@ -72,14 +93,13 @@ impl DtsGenerator {
         let mut dts = String::from("/dts-v1/;\n\n");
         if tree.reservations.len() > 0 {
             for reservation in &tree.reservations {
-                let reserv = reservation.lock().unwrap();
-                let reservation_dts = DtsGenerator::generate_reservation(&reserv, 0);
+                let reservation_dts = DtsGenerator::generate_reservation(reservation, 0);
                 dts.push_str(&reservation_dts);
                 dts.push_str("\n");
             }
             dts.push_str("\n");
         }
-        let root_dts = DtsGenerator::generate_node(&tree.root.clone().lock().unwrap(), 0);
+        let root_dts = DtsGenerator::generate_tree_node(tree, tree.root(), 0);
         dts.push_str(&root_dts);
         dts.push_str("\n");
         dts
@@ -1,12 +1,15 @@
 // Copyright (c) 2023, Michael Zhao
 // SPDX-License-Identifier: MIT
 
-use crate::{devicetree::DeviceTree, node::Node, property::Property, reservation::Reservation};
-use std::sync::{Arc, Mutex};
+use crate::{
+    devicetree::DeviceTree,
+    node::{Node, NodeId},
+    property::Property,
+    reservation::Reservation,
+};
 
 pub struct DtsParser {
     dts: Vec<u8>,
-    next_phandle: u32,
     tree: DeviceTree,
 }
 
@@ -14,7 +17,6 @@ impl DtsParser {
     pub fn from_bytes(dts: &[u8]) -> Self {
         DtsParser {
             dts: dts.clone().to_owned(),
-            next_phandle: 0,
             tree: DeviceTree::new(vec![], Node::new("/")),
         }
     }
@@ -28,21 +30,14 @@ impl DtsParser {
         self.parse_tree(dts, true);
         self.parse_tree(dts, false);
 
-        let mut reservations_clone = vec![];
-        for reservation in &self.tree.reservations {
-            reservations_clone.push(reservation.clone());
-        }
-        DeviceTree {
-            reservations: reservations_clone,
-            root: self.tree.root.clone(),
-        }
+        std::mem::replace(&mut self.tree, DeviceTree::new(vec![], Node::new("/")))
     }
 
     // Parse the DTS text that has been pre-processed and update the tree struct.
     // If `node_only` is true, only parse the node structure, and create nodes and subnodes
     // in the tree with names, all properties and indirectives will be ignored.
     fn parse_tree(&mut self, dts: &[u8], node_only: bool) {
-        let root_node = self.tree.root.clone();
+        let root_node = self.tree.root();
         let mut i: usize = 0;
         let mut text: Vec<u8> = vec![];
         while i < dts.len() {
@@ -59,10 +54,21 @@ impl DtsParser {
                     }
 
                     if statement == "/dts-v1/" {
-                        println!("detected /dts-v1/;");
-                    } else if statement.starts_with("/memreserve/") {
+                        // The version tag needs no further handling
+                    } else if statement.contains("/memreserve/") {
+                        // The entry may have a label, like "label: /memreserve/ ..."
+                        let (prefix, statement) = statement.split_once("/memreserve/").unwrap();
+                        let label = match prefix.trim() {
+                            "" => None,
+                            prefix => {
+                                let label = prefix.strip_suffix(':').map(str::trim);
+                                match label.filter(|label| DtsParser::is_valid_label(label)) {
+                                    Some(label) => Some(label),
+                                    None => panic!("invalid label of /memreserve/: {prefix}"),
+                                }
+                            }
+                        };
                         let mut reservation = statement.split_ascii_whitespace();
-                        let _ = reservation.next().unwrap();
                         let address = reservation.next().unwrap();
                         let address = if address.starts_with("0x") {
                             u64::from_str_radix(&address[2..], 16).unwrap()
@@ -75,13 +81,11 @@ impl DtsParser {
                         } else {
                             u64::from_str_radix(length, 10).unwrap()
                         };
-                        println!(
-                            "detected /memreserve/: address = {:#018x}, length = {:#018x}",
-                            address, length
-                        );
-                        self.tree
-                            .reservations
-                            .push(Arc::new(Mutex::new(Reservation::new(address, length))));
+                        let reservation = match label {
+                            Some(label) => Reservation::new_with_label(address, length, label),
+                            None => Reservation::new(address, length),
+                        };
+                        self.tree.reservations.push(reservation);
                     } else {
                         panic!("unknown top-level statement: {statement}");
                     }
@@ -98,7 +102,7 @@ impl DtsParser {
 
                     i = i + 1;
                     // Update the root node content
-                    let node_size = self.parse_node(&dts[i..], root_node.clone(), node_only);
+                    let node_size = self.parse_node(&dts[i..], root_node, node_only);
                     i = i + node_size;
                     text.clear();
                 }
@@ -110,7 +114,7 @@ impl DtsParser {
         }
     }
 
-    fn parse_node(&mut self, dts: &[u8], node: Arc<Mutex<Node>>, node_only: bool) -> usize {
+    fn parse_node(&mut self, dts: &[u8], node: NodeId, node_only: bool) -> usize {
         let mut i: usize = 0;
         let mut text: Vec<u8> = vec![];
         let mut at_end = false;
@@ -120,38 +124,30 @@ impl DtsParser {
                     // Found node
                     let sub_node_name =
                         &String::from(String::from_utf8_lossy(&text).to_string().trim());
-                    println!("found node {}", sub_node_name);
 
                     let (label, sub_node_name) = if sub_node_name.contains(":") {
                         let parts: Vec<&str> = sub_node_name.split(":").collect();
-                        (Some(String::from(parts[0])), String::from(parts[1]))
+                        (
+                            Some(String::from(parts[0].trim())),
+                            String::from(parts[1].trim()),
+                        )
                     } else {
                         (None, String::from(sub_node_name.trim()))
                     };
 
                     // If a sub_node with the name doesn't exist, create one
-                    if !node
-                        .lock()
-                        .unwrap()
-                        .sub_nodes
-                        .iter()
-                        .any(|x| &x.lock().unwrap().name == &sub_node_name)
-                    {
+                    if self.tree.find_child_by_name(node, &sub_node_name).is_none() {
                         let new_sub_node = if let Some(label) = label {
                             Node::new_with_label(&sub_node_name, &label)
                         } else {
                             Node::new(&sub_node_name)
                         };
 
-                        node.lock().unwrap().add_sub_node(new_sub_node);
+                        self.tree.add_sub_node(node, new_sub_node);
                     }
 
                     // Get the sub_node out and update
-                    let sub_node = node
-                        .lock()
-                        .unwrap()
-                        .find_subnode_by_name(&sub_node_name)
-                        .unwrap();
+                    let sub_node = self.tree.find_child_by_name(node, &sub_node_name).unwrap();
 
                     i = i + 1;
                     let node_size = self.parse_node(&dts[i..], sub_node, node_only);
@@ -167,7 +163,6 @@ impl DtsParser {
                     // Found a property with value
                     let prop_name =
                         &String::from(String::from_utf8_lossy(&text).to_string().trim());
-                    println!("found property {} with value:", prop_name);
                     i = i + 1;
                     let (property_value_size, property_value) =
                         self.parse_property_value(&dts[i..], node_only);
@@ -175,7 +170,7 @@ impl DtsParser {
                     text.clear();
                     if !node_only {
                         let prop = Property::new_u8s(prop_name, property_value);
-                        node.lock().unwrap().add_property(prop);
+                        self.tree.node_mut(node).add_property(prop);
                     }
                 }
                 ';' => {
@@ -199,38 +194,25 @@ impl DtsParser {
                         if prop_name.starts_with("/") {
                             // A compiler directive
                             let directive = prop_name;
-                            println!("found directive: {directive}");
                             let mut slices = directive.split_ascii_whitespace();
                             let instruction = slices.next().unwrap();
                             if instruction == "/delete-node/" {
                                 let sub_node_name = slices.next().unwrap();
-                                println!("delete node: {sub_node_name}");
-                                let sub_node_index = node
-                                    .lock()
-                                    .unwrap()
-                                    .sub_nodes
-                                    .iter()
-                                    .position(|x| x.lock().unwrap().name == sub_node_name)
-                                    .unwrap();
-                                node.lock().unwrap().sub_nodes.remove(sub_node_index);
+                                let sub_node =
+                                    self.tree.find_child_by_name(node, sub_node_name).unwrap();
+                                self.tree.take_node(sub_node).unwrap();
                             } else if instruction == "/delete-property/" {
                                 let property_name = slices.next().unwrap();
-                                println!("delete property: {property_name}");
-                                let property_index = node
-                                    .lock()
-                                    .unwrap()
-                                    .properties
-                                    .iter()
-                                    .position(|x| x.lock().unwrap().name == property_name)
+                                self.tree
+                                    .node_mut(node)
+                                    .remove_property(property_name)
                                     .unwrap();
-                                node.lock().unwrap().properties.remove(property_index);
                             } else {
                                 panic!("unknown comipler directive {directive}")
                             }
                         } else {
-                            println!("found property {} without value", prop_name);
                             let prop = Property::new_empty(&prop_name);
-                            node.lock().unwrap().add_property(prop);
+                            self.tree.node_mut(node).add_property(prop);
                         }
                     }
                 }
@@ -343,8 +325,6 @@ impl DtsParser {
 
     fn parse_property_value_cells(&mut self, text: &[u8]) -> Vec<u8> {
         let mut value: Vec<u8> = vec![];
-        println!("cells: {}", String::from_utf8_lossy(text));
-
         for num in String::from_utf8_lossy(text).split_whitespace() {
             // A value could be in format:
             //   * &LABEL
@@ -357,42 +337,13 @@ impl DtsParser {
                     // Get the full path
                     let ref_node_path = &num[2..(num.len() - 1)];
                     let node_to_ref = self.tree.find_node_by_path(ref_node_path).unwrap();
-                    let phandle_prop = node_to_ref.lock().unwrap().find_property("phandle");
-                    let phandle = if let Some(phandle_prop) = phandle_prop {
-                        u32::from_be_bytes(
-                            phandle_prop.lock().unwrap().value[0..4].try_into().unwrap(),
-                        )
-                    } else {
-                        let phandle = self.next_phandle;
-                        self.next_phandle = self.next_phandle + 1;
-                        node_to_ref
-                            .lock()
-                            .unwrap()
-                            .add_property(Property::new_u32("phandle", phandle));
-                        phandle
-                    };
-                    phandle
+                    self.tree.ensure_phandle(node_to_ref)
                 } else {
                     // It should be a label
                     let label = &num[1..];
                     let node_to_ref = self.tree.find_node_by_label(label).unwrap();
 
-                    let phandle_prop = node_to_ref.lock().unwrap().find_property("phandle");
-
-                    let phandle = if let Some(phandle_prop) = phandle_prop {
-                        u32::from_be_bytes(
-                            phandle_prop.lock().unwrap().value[0..4].try_into().unwrap(),
-                        )
-                    } else {
-                        let phandle = self.next_phandle;
-                        self.next_phandle = self.next_phandle + 1;
-                        node_to_ref
-                            .lock()
-                            .unwrap()
-                            .add_property(Property::new_u32("phandle", phandle));
-                        phandle
-                    };
-                    phandle
+                    self.tree.ensure_phandle(node_to_ref)
                 }
             } else if num.starts_with("0x") {
                 u32::from_str_radix(&num[2..], 16).unwrap()
@@ -403,15 +354,12 @@ impl DtsParser {
             for n in n_u8_vec {
                 value.push(n);
             }
-            println!("{:x}", n);
         }
         value
     }
 
     fn parse_property_value_bytes(text: &[u8]) -> Vec<u8> {
         let mut value: Vec<u8> = vec![];
-        println!("bytes: {}", String::from_utf8_lossy(text));
-        println!("bytes:");
         for num in String::from_utf8_lossy(text).split_whitespace() {
             let n = if num.starts_with("0x") {
                 u8::from_str_radix(&num[2..], 16).unwrap()
@@ -419,13 +367,11 @@ impl DtsParser {
                 u8::from_str_radix(num, 10).unwrap()
             };
             value.push(n);
-            println!("{:x}", n);
         }
         value
     }
 
     fn parse_property_value_string(text: &[u8]) -> Vec<u8> {
-        println!("string: {}", String::from_utf8_lossy(text));
         let mut bytes = text.to_vec();
         // Append the terminator
         bytes.push(0);
@@ -460,7 +406,6 @@ impl DtsParser {
                     panic!("included file path error: {path}")
                 }
                 let path = &path[1..(path.len() - 1)];
-                println!("path: {path}");
                 let included_dts = std::fs::read_to_string(path).unwrap();
                 let included_dts = DtsParser::pre_process(&included_dts, inclusion_depth - 1);
                 processed_dts.push_str(&included_dts);
@@ -473,6 +418,18 @@ impl DtsParser {
         processed_dts
     }
 
+    // Check if a label follows the syntax of DTS: a letter or "_", then
+    // letters, digits and "_".
+    fn is_valid_label(label: &str) -> bool {
+        let mut chars = label.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+            _ => false,
+        }
+    }
+
     // Return the space of a C-style comment: (start location, size)
     fn find_c_comment(text: &[u8]) -> Option<(usize, usize)> {
         if let Some(comment_start) = text
@@ -571,7 +528,7 @@ mod tests {
         // Read the DTS text from test data folder
         let dts = std::fs::read("test/dts_0.dts").unwrap();
         let tree = DtsParser::from_bytes(&dts).parse();
-        assert_eq!(tree.root.lock().unwrap().properties.len(), 4);
+        assert_eq!(tree.node(tree.root()).properties.len(), 4);
     }
 
     #[test]
@@ -579,17 +536,15 @@ mod tests {
         // Read the DTS text from test data folder
         let dts = std::fs::read("test/dts_2.dts").unwrap();
         let tree = DtsParser::from_bytes(&dts).parse();
-        assert_eq!(tree.root.lock().unwrap().sub_nodes.len(), 1);
-        let node_cpus = &tree.root.lock().unwrap().sub_nodes[0];
-        assert_eq!(node_cpus.lock().unwrap().sub_nodes.len(), 2);
-        assert_eq!(node_cpus.lock().unwrap().properties.len(), 2);
-        let node_cpu0 = &node_cpus.lock().unwrap().sub_nodes[0];
-        assert_eq!(node_cpu0.lock().unwrap().sub_nodes.len(), 0);
-        assert_eq!(node_cpu0.lock().unwrap().properties.len(), 4);
-        assert_eq!(
-            node_cpu0.lock().unwrap().properties[0].lock().unwrap().name,
-            "device_type"
-        );
+        let root = tree.to_node(tree.root());
+        assert_eq!(root.sub_nodes.len(), 1);
+        let node_cpus = &root.sub_nodes[0];
+        assert_eq!(node_cpus.sub_nodes.len(), 2);
+        assert_eq!(node_cpus.properties.len(), 2);
+        let node_cpu0 = &node_cpus.sub_nodes[0];
+        assert_eq!(node_cpu0.sub_nodes.len(), 0);
+        assert_eq!(node_cpu0.properties.len(), 4);
+        assert_eq!(node_cpu0.properties[0].name, "device_type");
     }
 
     #[test]
@@ -655,10 +610,33 @@ mod tests {
         let dts = std::fs::read("test/dts_4.dts").unwrap();
         let tree = DtsParser::from_bytes(&dts).parse();
         assert_eq!(tree.reservations.len(), 5);
-        assert_eq!(tree.reservations[0].lock().unwrap().address, 0x0);
-        assert_eq!(tree.reservations[0].lock().unwrap().length, 0x100000);
-        assert_eq!(tree.reservations[4].lock().unwrap().address, 0x400000);
-        assert_eq!(tree.reservations[4].lock().unwrap().length, 0x100000);
+        assert_eq!(tree.reservations[0].address, 0x0);
+        assert_eq!(tree.reservations[0].length, 0x100000);
+        assert_eq!(tree.reservations[4].address, 0x400000);
+        assert_eq!(tree.reservations[4].length, 0x100000);
+    }
+
+    #[test]
+    fn test_dts_parse_reservation_label() {
+        let dts = b"/dts-v1/;\n\nfw: /memreserve/ 0x1000 0x2000;\n/memreserve/ 0x4000 0x1000;\n\n/ {\n};\n";
+        let tree = DtsParser::from_bytes(dts).parse();
+        assert_eq!(
+            tree.reservations,
+            vec![
+                Reservation::new_with_label(0x1000, 0x2000, "fw"),
+                Reservation::new(0x4000, 0x1000),
+            ]
+        );
+        assert!(tree
+            .generate_dts()
+            .contains("fw: /memreserve/ 0x0000000000001000 0x0000000000002000;\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid label of /memreserve/: junk")]
+    fn test_dts_parse_reservation_invalid_label() {
+        let dts = b"/dts-v1/;\n\njunk /memreserve/ 0x1000 0x2000;\n\n/ {\n};\n";
+        DtsParser::from_bytes(dts).parse();
     }
 
     #[test]
@@ -667,37 +645,13 @@ mod tests {
         let dts = std::fs::read("test/dts_5.dts").unwrap();
         let tree = DtsParser::from_bytes(&dts).parse();
 
-        assert_eq!(tree.root.lock().unwrap().sub_nodes.len(), 1);
+        let root = tree.to_node(tree.root());
+        assert_eq!(root.sub_nodes.len(), 1);
+        assert_eq!(root.sub_nodes[0].name, "node_b");
+        assert_eq!(root.sub_nodes[0].properties.len(), 1);
+        assert_eq!(root.sub_nodes[0].properties[0].name, "property_key_0");
         assert_eq!(
-            tree.root.lock().unwrap().sub_nodes[0].lock().unwrap().name,
-            "node_b"
-        );
-        assert_eq!(
-            tree.root.lock().unwrap().sub_nodes[0]
-                .lock()
-                .unwrap()
-                .properties
-                .len(),
-            1
-        );
-        assert_eq!(
-            tree.root.lock().unwrap().sub_nodes[0]
-                .lock()
-                .unwrap()
-                .properties[0]
-                .lock()
-                .unwrap()
-                .name,
-            "property_key_0"
-        );
-        assert_eq!(
-            tree.root.lock().unwrap().sub_nodes[0]
-                .lock()
-                .unwrap()
-                .properties[0]
-                .lock()
-                .unwrap()
-                .value,
+            root.sub_nodes[0].properties[0].value,
             vec!['v' as u8, '_' as u8, '0' as u8, 0 as u8]
         );
     }
@@ -714,22 +668,14 @@ mod tests {
     fn test_dts_parse_label() {
         let dts = std::fs::read_to_string("test/dts_7.dts").unwrap();
         let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+        let root = tree.to_node(tree.root());
         assert_eq!(
-            tree.root.lock().unwrap().sub_nodes[2]
-                .lock()
-                .unwrap()
-                .label
-                .as_ref()
-                .unwrap(),
+            root.sub_nodes[2].label.as_ref().unwrap(),
             "interrupt_controller"
         );
-        let prop = tree.root.lock().unwrap().find_property("interrupt-parent");
+        let prop = root.find_property("interrupt-parent");
         assert_eq!(prop.is_some(), true);
-        let phandle = u32::from_be_bytes(
-            prop.unwrap().lock().unwrap().value[0..4]
-                .try_into()
-                .unwrap(),
-        );
-        assert_eq!(phandle, 0);
+        let phandle = u32::from_be_bytes(prop.unwrap().value[0..4].try_into().unwrap());
+        assert_eq!(phandle, 1);
     }
 }
@@ -0,0 +1,82 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+/// An error of a device tree operation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceTreeError {
+    /// No node is found at the path.
+    NodeNotFound(String),
+    /// The node has no property with the name.
+    PropertyNotFound(String),
+    /// The path can't be used for the operation.
+    InvalidPath(String),
+    /// The node name doesn't follow the node name syntax.
+    InvalidNodeName(String),
+    /// A node with the name already exists.
+    NodeExists(String),
+    /// The label is already used by another node.
+    DuplicateLabel(String),
+    /// The phandle is already used by another node.
+    DuplicatePhandle(u32),
+    /// The value of the property can't be read as requested.
+    InvalidPropertyValue(String),
+    /// No node has the phandle.
+    DanglingPhandle(u32),
+    /// The cells of a property don't fit the `#*-cells` of the nodes it
+    /// refers to, or no entry of a `*-map` property matches.
+    CellMismatch(String),
+    /// The address can't be translated through the "ranges" of the node.
+    UntranslatableAddress(String),
+    /// The nodes depend on each other, as described by the text.
+    DependencyCycle(String),
+    /// The selector doesn't follow the selector syntax.
+    InvalidSelector(String),
+    /// No interrupt parent is found for the node at the path.
+    NoInterruptParent(String),
+    /// No free memory is found for the dynamic reserved memory node at the
+    /// path.
+    AllocationFailed(String),
+    /// The CPU or NUMA topology is not valid, as described by the text.
+    InvalidTopology(String),
+    /// The description of a virtual machine is not valid, as described by
+    /// the text.
+    InvalidMachine(String),
+}
+
+impl std::fmt::Display for DeviceTreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceTreeError::NodeNotFound(path) => write!(f, "node not found: {path}"),
+            DeviceTreeError::PropertyNotFound(name) => write!(f, "property not found: {name}"),
+            DeviceTreeError::InvalidPath(path) => write!(f, "invalid path: {path}"),
+            DeviceTreeError::InvalidNodeName(name) => write!(f, "invalid node name: {name}"),
+            DeviceTreeError::NodeExists(path) => write!(f, "node already exists: {path}"),
+            DeviceTreeError::DuplicateLabel(label) => write!(f, "duplicate label: {label}"),
+            DeviceTreeError::DuplicatePhandle(phandle) => {
+                write!(f, "duplicate phandle: {phandle:#x}")
+            }
+            DeviceTreeError::InvalidPropertyValue(name) => {
+                write!(f, "invalid property value: {name}")
+            }
+            DeviceTreeError::DanglingPhandle(phandle) => {
+                write!(f, "dangling phandle: {phandle:#x}")
+            }
+            DeviceTreeError::CellMismatch(prop) => write!(f, "cell count mismatch: {prop}"),
+            DeviceTreeError::UntranslatableAddress(path) => {
+                write!(f, "untranslatable address: {path}")
+            }
+            DeviceTreeError::DependencyCycle(cycle) => write!(f, "dependency cycle: {cycle}"),
+            DeviceTreeError::InvalidSelector(selector) => {
+                write!(f, "invalid selector: {selector}")
+            }
+            DeviceTreeError::NoInterruptParent(path) => {
+                write!(f, "no interrupt parent: {path}")
+            }
+            DeviceTreeError::AllocationFailed(path) => write!(f, "allocation failed: {path}"),
+            DeviceTreeError::InvalidTopology(message) => write!(f, "invalid topology: {message}"),
+            DeviceTreeError::InvalidMachine(message) => write!(f, "invalid machine: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for DeviceTreeError {}
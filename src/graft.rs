@@ -0,0 +1,198 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::Node;
+use crate::phandle::PhandleReference;
+use crate::property::Property;
+use std::collections::HashMap;
+
+/// How to handle a label of a copied node that is already used in the
+/// destination tree.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum LabelConflict {
+    /// Fail the copy with `DeviceTreeError::DuplicateLabel`.
+    #[default]
+    Fail,
+    /// Prepend a prefix to the conflicting label.
+    Prefix(String),
+    /// Drop the conflicting label from the copied node.
+    Drop,
+}
+
+/// Options of copying a subtree from one device tree into another.
+///
+/// References from the copied subtree to nodes outside of it are kept as they
+/// are, the caller should make sure they are valid in the destination tree.
+#[derive(Clone, Debug)]
+pub struct GraftOptions {
+    /// Give the copied nodes new phandles if their phandles are already used
+    /// in the destination tree, fail with `DeviceTreeError::DuplicatePhandle`
+    /// otherwise.
+    pub renumber_phandles: bool,
+    /// Rewrite the references to the renumbered phandles inside the copied
+    /// subtree.
+    pub rewrite_references: bool,
+    /// How to handle the conflicting labels.
+    pub label_conflict: LabelConflict,
+}
+
+impl Default for GraftOptions {
+    fn default() -> Self {
+        GraftOptions {
+            renumber_phandles: false,
+            rewrite_references: true,
+            label_conflict: LabelConflict::Fail,
+        }
+    }
+}
+
+pub(crate) struct Graft {}
+
+impl Graft {
+    fn collect(node: &Node, phandles: &mut Vec<u32>, labels: &mut Vec<String>) {
+        if let Some(phandle) = node.phandle() {
+            phandles.push(phandle);
+        }
+        if let Some(label) = &node.label {
+            labels.push(label.clone());
+        }
+        for sub_node in &node.sub_nodes {
+            Graft::collect(sub_node, phandles, labels);
+        }
+    }
+
+    // Resolve the label and phandle conflicts of a copied subtree.
+    fn resolve_conflicts(
+        node: &mut Node,
+        dest_phandles: &[u32],
+        dest_labels: &[String],
+        next_phandle: &mut u32,
+        renumbered: &mut HashMap<u32, u32>,
+        options: &GraftOptions,
+    ) -> Result<(), DeviceTreeError> {
+        if let Some(label) = node.label.clone() {
+            if dest_labels.contains(&label) {
+                match &options.label_conflict {
+                    LabelConflict::Fail => return Err(DeviceTreeError::DuplicateLabel(label)),
+                    LabelConflict::Prefix(prefix) => node.label = Some(format!("{prefix}{label}")),
+                    LabelConflict::Drop => node.label = None,
+                }
+            }
+        }
+
+        if let Some(phandle) = node.phandle() {
+            if dest_phandles.contains(&phandle) {
+                if !options.renumber_phandles {
+                    return Err(DeviceTreeError::DuplicatePhandle(phandle));
+                }
+                let new_phandle = *next_phandle;
+                *next_phandle += 1;
+                renumbered.insert(phandle, new_phandle);
+                for name in ["phandle", "linux,phandle"] {
+                    if node.find_property(name).is_some() {
+                        node.set_property(Property::new_u32(name, new_phandle));
+                    }
+                }
+            }
+        }
+
+        for sub_node in &mut node.sub_nodes {
+            Graft::resolve_conflicts(
+                sub_node,
+                dest_phandles,
+                dest_labels,
+                next_phandle,
+                renumbered,
+                options,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Find the phandles held by the properties of a copied subtree before
+    // it is renumbered, as the index of the node in depth-first order, the
+    // property name and the cell. The nodes the phandles refer to are looked
+    // up in the copy first, then in the destination tree, so that only the
+    // phandle cells of lists with arguments and maps are found.
+    fn find_references(copy: &Node, dest: &DeviceTree) -> Vec<(usize, String, usize, u32)> {
+        let tree = DeviceTree::new(vec![], copy.clone());
+        let resolve = |phandle| match tree.find_node_by_phandle(phandle) {
+            Some(id) => Some(tree.node(id)),
+            None => dest.find_node_by_phandle(phandle).map(|id| dest.node(id)),
+        };
+        let mut references = vec![];
+        for (index, id) in tree.subtree(tree.root()).into_iter().enumerate() {
+            for prop in &tree.node(id).properties {
+                let cells =
+                    PhandleReference::find_in_property_with(&tree, id, &prop.name, &resolve);
+                for (cell, phandle) in cells {
+                    references.push((index, prop.name.clone(), cell, phandle));
+                }
+            }
+        }
+        references
+    }
+
+    fn rewrite_references(
+        node: &mut Node,
+        references: &[(usize, String, usize, u32)],
+        renumbered: &HashMap<u32, u32>,
+        index: &mut usize,
+    ) {
+        for (_, name, cell, phandle) in references.iter().filter(|r| r.0 == *index) {
+            let (Some(new_phandle), Some(prop)) =
+                (renumbered.get(phandle), node.find_property_mut(name))
+            else {
+                continue;
+            };
+            let offset = cell * 4;
+            prop.value[offset..(offset + 4)].copy_from_slice(&new_phandle.to_be_bytes());
+        }
+        *index += 1;
+        for sub_node in &mut node.sub_nodes {
+            Graft::rewrite_references(sub_node, references, renumbered, index);
+        }
+    }
+
+    // Copy a subtree so that it can be added into the destination tree.
+    pub fn copy_into(
+        mut copy: Node,
+        dest: &DeviceTree,
+        options: &GraftOptions,
+    ) -> Result<Node, DeviceTreeError> {
+        let mut dest_phandles = vec![];
+        let mut dest_labels = vec![];
+        for id in dest.subtree(dest.root()) {
+            let node = dest.node(id);
+            dest_phandles.extend(node.phandle());
+            dest_labels.extend(node.label.clone());
+        }
+
+        let mut copy_phandles = vec![];
+        Graft::collect(&copy, &mut copy_phandles, &mut vec![]);
+        let references = Graft::find_references(&copy, dest);
+
+        // New phandles must not conflict with the destination tree or the copy
+        let mut next_phandle = dest_phandles
+            .iter()
+            .chain(copy_phandles.iter())
+            .max()
+            .map_or(1, |max| max + 1);
+        let mut renumbered = HashMap::new();
+        Graft::resolve_conflicts(
+            &mut copy,
+            &dest_phandles,
+            &dest_labels,
+            &mut next_phandle,
+            &mut renumbered,
+            options,
+        )?;
+
+        if options.rewrite_references && !renumbered.is_empty() {
+            Graft::rewrite_references(&mut copy, &references, &renumbered, &mut 0);
+        }
+        Ok(copy)
+    }
+}
@@ -0,0 +1,269 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::NodeId;
+use crate::phandle::{CellCount, PhandleArgs};
+
+pub(crate) struct Iommu {}
+
+impl Iommu {
+    // Get the IOMMU specifiers in the "iommus" of a node, leaving out the
+    // empty entries.
+    pub(crate) fn iommus(
+        tree: &DeviceTree,
+        id: NodeId,
+    ) -> Result<Vec<PhandleArgs>, DeviceTreeError> {
+        if tree.node(id).find_property("iommus").is_none() {
+            return Ok(vec![]);
+        }
+        let list =
+            PhandleArgs::parse_list(tree, id, "iommus", &CellCount::Property("#iommu-cells"))?;
+        Ok(list.into_iter().flatten().collect())
+    }
+
+    // Map an ID through a "*-map" property of a node, like `of_map_id` of
+    // Linux. Every entry of the map is the base of the input IDs, the
+    // phandle of the target, the base of the output IDs and the length.
+    pub(crate) fn map_id(
+        tree: &DeviceTree,
+        id: NodeId,
+        input: u32,
+        map_name: &str,
+        mask_name: &str,
+        target: Option<NodeId>,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        let node = tree.node(id);
+        let Some(map) = node.find_property(map_name) else {
+            return Ok(None);
+        };
+        if map.value.is_empty() || !map.value.len().is_multiple_of(16) {
+            return Err(DeviceTreeError::CellMismatch(format!(
+                "{}:{}",
+                tree.path_of(id),
+                map_name
+            )));
+        }
+        let map = node.read_u32_array(map_name, map.value.len() / 4)?;
+        // All the bits are used by default
+        let mask = node.read_u32(mask_name).unwrap_or(u32::MAX);
+        let masked = input & mask;
+
+        for entry in map.chunks(4) {
+            let (id_base, phandle, out_base, len) = (entry[0], entry[1], entry[2], entry[3]);
+            if id_base & !mask != 0 {
+                // The mask would never let an ID reach the entry
+                return Err(DeviceTreeError::InvalidPropertyValue(format!(
+                    "{}:{}",
+                    tree.path_of(id),
+                    map_name
+                )));
+            }
+            if masked < id_base || (masked - id_base) as u64 >= len as u64 {
+                continue;
+            }
+            let node = tree
+                .find_node_by_phandle(phandle)
+                .ok_or(DeviceTreeError::DanglingPhandle(phandle))?;
+            if target.is_some_and(|target| target != node) {
+                continue;
+            }
+            return Ok(Some(PhandleArgs {
+                node,
+                args: vec![(masked - id_base).wrapping_add(out_base)],
+            }));
+        }
+        Ok(None)
+    }
+
+    // Map an ID through the "msi-map" of the closest node having it, from
+    // the node up to the root, like `of_msi_map_id` of Linux. The search
+    // stops at the first "msi-map" even if no entry matches, then the ID is
+    // passed through unchanged to the target.
+    pub(crate) fn msi_map(
+        tree: &DeviceTree,
+        id: NodeId,
+        input: u32,
+        target: Option<NodeId>,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        let mut node = Some(id);
+        while let Some(id) = node {
+            if tree.node(id).find_property("msi-map").is_some() {
+                let mapped = Iommu::map_id(tree, id, input, "msi-map", "msi-map-mask", target)?;
+                return Ok(mapped.or_else(|| {
+                    Some(PhandleArgs {
+                        node: target?,
+                        args: vec![input],
+                    })
+                }));
+            }
+            node = tree.parent(id);
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> DeviceTree {
+        DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	smmu: iommu@2b400000 {
+		phandle = <1>;
+		#iommu-cells = <1>;
+	};
+
+	its: msi-controller@8080000 {
+		phandle = <2>;
+		msi-controller;
+		#msi-cells = <1>;
+	};
+
+	its2: msi-controller@8100000 {
+		phandle = <3>;
+		msi-controller;
+		#msi-cells = <1>;
+	};
+
+	dma@7000000 {
+		iommus = <&smmu 0x10>, <0>, <&smmu 0x11>;
+	};
+
+	pcie@10000000 {
+		iommu-map = <0x0 &smmu 0x10000 0x100>, <0x100 &smmu 0x20000 0x100>;
+		iommu-map-mask = <0xfff8>;
+		msi-map = <0x0 &its 0x0 0x10000>, <0x0 &its2 0x100 0x10000>;
+
+		bridge@0 {
+			device@0 {
+			};
+		};
+
+		bridge@1 {
+			msi-map = <0x0 &its2 0x0 0x8>;
+
+			device@0 {
+			};
+		};
+	};
+
+	bad-mask {
+		iommu-map = <0x100 &smmu 0x0 0x10>;
+		iommu-map-mask = <0xff>;
+	};
+
+	bad-length {
+		iommu-map = <0x0 &smmu 0x0>;
+	};
+};
+"#,
+        )
+    }
+
+    #[test]
+    fn test_iommu_iommus() {
+        let tree = tree();
+        let smmu = tree.find_node_by_path("/iommu@2b400000").unwrap();
+        let dma = tree.find_node_by_path("/dma@7000000").unwrap();
+
+        assert_eq!(
+            tree.iommus(dma),
+            Ok(vec![
+                PhandleArgs {
+                    node: smmu,
+                    args: vec![0x10]
+                },
+                PhandleArgs {
+                    node: smmu,
+                    args: vec![0x11]
+                },
+            ])
+        );
+        assert_eq!(tree.iommus(smmu), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_iommu_map_id() {
+        let tree = tree();
+        let smmu = tree.find_node_by_path("/iommu@2b400000").unwrap();
+        let its = tree.find_node_by_path("/msi-controller@8080000").unwrap();
+        let its2 = tree.find_node_by_path("/msi-controller@8100000").unwrap();
+        let pcie = tree.find_node_by_path("/pcie@10000000").unwrap();
+        let device = tree
+            .find_node_by_path("/pcie@10000000/bridge@0/device@0")
+            .unwrap();
+
+        // The mask drops the function number
+        assert_eq!(
+            tree.iommu_map(pcie, 0x10a),
+            Ok(Some(PhandleArgs {
+                node: smmu,
+                args: vec![0x20008]
+            }))
+        );
+        assert_eq!(tree.iommu_map(pcie, 0x200), Ok(None));
+        assert_eq!(tree.iommu_map(smmu, 0x0), Ok(None));
+
+        // The first matching entry wins, unless the target is given
+        assert_eq!(
+            tree.msi_map(device, 0x10a, None),
+            Ok(Some(PhandleArgs {
+                node: its,
+                args: vec![0x10a]
+            }))
+        );
+        assert_eq!(
+            tree.msi_map(device, 0x10a, Some(its2)),
+            Ok(Some(PhandleArgs {
+                node: its2,
+                args: vec![0x20a]
+            }))
+        );
+        assert_eq!(
+            tree.msi_map(device, 0x10a, Some(smmu)),
+            Ok(Some(PhandleArgs {
+                node: smmu,
+                args: vec![0x10a]
+            }))
+        );
+
+        // The closest "msi-map" is used even if no entry matches
+        let device = tree
+            .find_node_by_path("/pcie@10000000/bridge@1/device@0")
+            .unwrap();
+        assert_eq!(
+            tree.msi_map(device, 0x4, None),
+            Ok(Some(PhandleArgs {
+                node: its2,
+                args: vec![0x4]
+            }))
+        );
+        assert_eq!(tree.msi_map(device, 0x10a, None), Ok(None));
+        assert_eq!(
+            tree.msi_map(device, 0x10a, Some(its2)),
+            Ok(Some(PhandleArgs {
+                node: its2,
+                args: vec![0x10a]
+            }))
+        );
+
+        let bad = tree.find_node_by_path("/bad-mask").unwrap();
+        assert_eq!(
+            tree.iommu_map(bad, 0x0),
+            Err(DeviceTreeError::InvalidPropertyValue(String::from(
+                "/bad-mask:iommu-map"
+            )))
+        );
+        let bad = tree.find_node_by_path("/bad-length").unwrap();
+        assert_eq!(
+            tree.iommu_map(bad, 0x0),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/bad-length:iommu-map"
+            )))
+        );
+    }
+}
@@ -0,0 +1,887 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::address::Address;
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::NodeId;
+use crate::phandle::PhandleArgs;
+
+/// The trigger type of an interrupt, encoded like the `IRQ_TYPE_*` flags of
+/// the device tree bindings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptTrigger {
+    EdgeRising = 1,
+    EdgeFalling = 2,
+    EdgeBoth = 3,
+    LevelHigh = 4,
+    LevelLow = 8,
+}
+
+impl InterruptTrigger {
+    fn from_flags(flags: u32) -> Option<Self> {
+        match flags {
+            1 => Some(InterruptTrigger::EdgeRising),
+            2 => Some(InterruptTrigger::EdgeFalling),
+            3 => Some(InterruptTrigger::EdgeBoth),
+            4 => Some(InterruptTrigger::LevelHigh),
+            8 => Some(InterruptTrigger::LevelLow),
+            _ => None,
+        }
+    }
+}
+
+/// An interrupt specifier of a known kind of interrupt controller.
+///
+/// It is encoded to the cells of "interrupts" with
+/// `DeviceTree::encode_interrupt`, and decoded from them with
+/// `DeviceTree::decode_interrupt`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterruptSpecifier {
+    /// A shared peripheral interrupt of an ARM GIC, numbered from 0 for
+    /// INTID 32. Only `EdgeRising` and `LevelHigh` are allowed.
+    GicSpi {
+        number: u32,
+        trigger: InterruptTrigger,
+    },
+    /// A private peripheral interrupt of an ARM GIC, numbered from 0 for
+    /// INTID 16. `cpus` is the mask of the CPUs it is wired to, which is
+    /// only used by GICv2.
+    GicPpi {
+        number: u32,
+        trigger: InterruptTrigger,
+        cpus: u8,
+    },
+    /// A source of a RISC-V PLIC, from 1. The trigger type is only present
+    /// if the PLIC has 2 "#interrupt-cells".
+    Plic {
+        source: u32,
+        trigger: Option<InterruptTrigger>,
+    },
+    /// A source of a RISC-V APLIC, from 1. `EdgeBoth` is not allowed.
+    Aplic {
+        source: u32,
+        trigger: InterruptTrigger,
+    },
+    /// The cells of a specifier of any other interrupt controller
+    Raw(Vec<u32>),
+}
+
+// The kinds of interrupt controllers with typed specifiers
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Controller {
+    Gic,
+    Plic,
+    Aplic,
+    Other,
+}
+
+impl Controller {
+    fn of(tree: &DeviceTree, id: NodeId) -> Self {
+        let node = tree.node(id);
+        let is = |compatibles: &[&str]| compatibles.iter().any(|c| node.is_compatible(c));
+        if is(&[
+            "arm,gic-v3",
+            "arm,gic-400",
+            "arm,cortex-a15-gic",
+            "arm,cortex-a9-gic",
+            "arm,cortex-a7-gic",
+            "arm,cortex-a5-gic",
+            "arm,arm11mp-gic",
+            "arm,pl390",
+        ]) {
+            Controller::Gic
+        } else if is(&[
+            "sifive,plic-1.0.0",
+            "riscv,plic0",
+            "andestech,nceplic100",
+            "thead,c900-plic",
+        ]) {
+            Controller::Plic
+        } else if is(&["riscv,aplic"]) {
+            Controller::Aplic
+        } else {
+            Controller::Other
+        }
+    }
+}
+
+pub(crate) struct Irq {}
+
+impl Irq {
+    fn mismatch(tree: &DeviceTree, id: NodeId, name: &str) -> DeviceTreeError {
+        DeviceTreeError::CellMismatch(format!("{}:{}", tree.path_of(id), name))
+    }
+
+    fn interrupt_parent(tree: &DeviceTree, id: NodeId) -> Result<NodeId, DeviceTreeError> {
+        tree.interrupt_parent(id)
+            .ok_or_else(|| DeviceTreeError::NoInterruptParent(tree.path_of(id)))
+    }
+
+    // Get the number of interrupts of a device.
+    pub(crate) fn count(tree: &DeviceTree, id: NodeId) -> Result<usize, DeviceTreeError> {
+        let node = tree.node(id);
+        if node.find_property("interrupts-extended").is_some() {
+            return tree.count_phandle_with_args(id, "interrupts-extended", "#interrupt-cells");
+        }
+        if node.find_property("interrupts").is_none() {
+            return Ok(0);
+        }
+        let parent = Irq::interrupt_parent(tree, id)?;
+        let intsize =
+            tree.node(parent)
+                .read_u32("#interrupt-cells")
+                .map_err(|_| Irq::mismatch(tree, parent, "#interrupt-cells"))? as usize;
+        let cells = node.count_elems_of_size("interrupts", 4)?;
+        if intsize == 0 || cells % intsize != 0 {
+            return Err(Irq::mismatch(tree, id, "interrupts"));
+        }
+        Ok(cells / intsize)
+    }
+
+    // Get an interrupt of a device as the interrupt parent and the specifier,
+    // and resolve it with `parse_raw`, like `of_irq_parse_one` of Linux.
+    pub(crate) fn parse_one(
+        tree: &DeviceTree,
+        id: NodeId,
+        index: usize,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        let node = tree.node(id);
+        // The unit address of the device, used to match "interrupt-map"
+        let addr = node
+            .find_property("reg")
+            .map(|reg| {
+                reg.value
+                    .chunks_exact(4)
+                    .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+            })
+            .map(|cells| cells.take(3).collect::<Vec<u32>>());
+
+        let irq = if node.find_property("interrupts-extended").is_some() {
+            tree.parse_phandle_with_args(id, "interrupts-extended", "#interrupt-cells", index)?
+        } else {
+            if index >= Irq::count(tree, id)? {
+                return Ok(None);
+            }
+            let parent = Irq::interrupt_parent(tree, id)?;
+            let intsize = tree.node(parent).read_u32("#interrupt-cells")? as usize;
+            let args = node.read_u32_array("interrupts", (index + 1) * intsize)?;
+            Some(PhandleArgs {
+                node: parent,
+                args: args[(index * intsize)..].to_vec(),
+            })
+        };
+        match irq {
+            Some(irq) => Ok(Some(Irq::parse_raw(tree, addr.as_deref(), irq)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Resolve an interrupt specifier of an interrupt parent to the interrupt
+    // controller, through the "interrupt-map" of the nexus nodes on the way,
+    // like `of_irq_parse_raw` of Linux. `addr` is the unit address of the
+    // device, and `None` if it has no "reg".
+    pub(crate) fn parse_raw(
+        tree: &DeviceTree,
+        addr: Option<&[u32]>,
+        irq: PhandleArgs,
+    ) -> Result<PhandleArgs, DeviceTreeError> {
+        // Find the node that tells how to interpret the specifier
+        let mut ipar = irq.node;
+        let mut parents = vec![];
+        let mut intsize = loop {
+            if let Ok(intsize) = tree.node(ipar).read_u32("#interrupt-cells") {
+                break intsize as usize;
+            }
+            // A loop of "interrupt-parent" never reaches the cells
+            if parents.contains(&ipar) {
+                return Err(DeviceTreeError::NoInterruptParent(tree.path_of(ipar)));
+            }
+            parents.push(ipar);
+            ipar = Irq::interrupt_parent(tree, ipar)?;
+        };
+        if irq.args.len() != intsize {
+            return Err(Irq::mismatch(tree, ipar, "#interrupt-cells"));
+        }
+
+        // The unit address and the specifier to match with the maps
+        let mut addrsize = Address::cells(tree, ipar, "#address-cells", 2);
+        let mut match_array: Vec<u32> = (0..addrsize)
+            .map(|i| addr.and_then(|addr| addr.get(i)).copied().unwrap_or(0))
+            .collect();
+        match_array.extend(&irq.args);
+
+        // A walk leading back to a node with the same specifier never ends
+        let mut visited: Vec<(NodeId, Vec<u32>)> = vec![];
+        loop {
+            let node = tree.node(ipar);
+            let revisited = visited.contains(&(ipar, match_array.clone()));
+            visited.push((ipar, match_array.clone()));
+            let Some(imap) = node.find_property("interrupt-map") else {
+                if node.find_property("interrupt-controller").is_some() {
+                    return Ok(PhandleArgs {
+                        node: ipar,
+                        args: match_array[addrsize..].to_vec(),
+                    });
+                }
+                if revisited {
+                    return Err(DeviceTreeError::NoInterruptParent(tree.path_of(ipar)));
+                }
+                // Neither a controller nor a nexus, go on with the parent
+                ipar = Irq::interrupt_parent(tree, ipar)?;
+                continue;
+            };
+            if revisited {
+                return Err(Irq::mismatch(tree, ipar, "interrupt-map"));
+            }
+
+            // A device without "reg" can only be mapped without unit address
+            if addr.is_none() && addrsize != 0 {
+                return Err(Irq::mismatch(tree, ipar, "interrupt-map"));
+            }
+            let size = addrsize + intsize;
+            let mask = match node.find_property("interrupt-map-mask") {
+                Some(_) => node
+                    .read_u32_array("interrupt-map-mask", size)
+                    .map_err(|_| Irq::mismatch(tree, ipar, "interrupt-map-mask"))?,
+                None => vec![u32::MAX; size],
+            };
+            if !imap.value.len().is_multiple_of(4) {
+                return Err(Irq::mismatch(tree, ipar, "interrupt-map"));
+            }
+            let imap = node.read_u32_array("interrupt-map", imap.value.len() / 4)?;
+
+            // Each entry is the child unit address and specifier, the phandle
+            // of the parent, and the parent unit address and specifier
+            let mut found = None;
+            let mut pos = 0;
+            while pos < imap.len() {
+                if pos + size + 1 > imap.len() {
+                    return Err(Irq::mismatch(tree, ipar, "interrupt-map"));
+                }
+                let matched = (0..size).all(|i| (match_array[i] ^ imap[pos + i]) & mask[i] == 0);
+                let phandle = imap[pos + size];
+                pos += size + 1;
+
+                let newpar = tree
+                    .find_node_by_phandle(phandle)
+                    .ok_or(DeviceTreeError::DanglingPhandle(phandle))?;
+                let newintsize = tree
+                    .node(newpar)
+                    .read_u32("#interrupt-cells")
+                    .map_err(|_| Irq::mismatch(tree, newpar, "#interrupt-cells"))?
+                    as usize;
+                let newaddrsize =
+                    tree.node(newpar).read_u32("#address-cells").unwrap_or(0) as usize;
+                if pos + newaddrsize + newintsize > imap.len() {
+                    return Err(Irq::mismatch(tree, ipar, "interrupt-map"));
+                }
+                let parent_spec = &imap[pos..(pos + newaddrsize + newintsize)];
+                pos += newaddrsize + newintsize;
+
+                if matched && tree.node(newpar).is_available() {
+                    found = Some((newpar, newaddrsize, newintsize, parent_spec.to_vec()));
+                    break;
+                }
+            }
+            let (newpar, newaddrsize, newintsize, parent_spec) =
+                found.ok_or_else(|| Irq::mismatch(tree, ipar, "interrupt-map"))?;
+
+            match_array = parent_spec;
+            addrsize = newaddrsize;
+            intsize = newintsize;
+            if newpar == ipar {
+                // An entry mapping to the nexus itself ends the walk
+                return Ok(PhandleArgs {
+                    node: newpar,
+                    args: match_array[addrsize..].to_vec(),
+                });
+            }
+            ipar = newpar;
+        }
+    }
+
+    // Resolve the INTx interrupt of a PCI function below a host bridge, like
+    // `of_irq_parse_pci` of Linux. `devices` are the bus, device and function
+    // numbers from the device on the root bus down to the function, and the
+    // bridges between them have no nodes, so the pin is swizzled at each of
+    // them.
+    pub(crate) fn parse_pci(
+        tree: &DeviceTree,
+        host: NodeId,
+        devices: &[(u8, u8, u8)],
+        pin: u8,
+    ) -> Result<Option<PhandleArgs>, DeviceTreeError> {
+        let Some((bus, device, function)) = devices.first() else {
+            return Ok(None);
+        };
+        if pin == 0 {
+            return Ok(None);
+        }
+        let mut pin = pin as u32;
+        for (_, device, _) in devices[1..].iter().rev() {
+            pin = ((pin - 1 + *device as u32) % 4) + 1;
+        }
+        let devfn = ((*device as u32) << 3) | *function as u32;
+        let addr = [((*bus as u32) << 16) | (devfn << 8), 0, 0];
+        let irq = PhandleArgs {
+            node: host,
+            args: vec![pin],
+        };
+        Ok(Some(Irq::parse_raw(tree, Some(&addr), irq)?))
+    }
+
+    // Encode an interrupt specifier to the cells of the interrupt
+    // controller, checking it against the kind and the "#interrupt-cells"
+    // of the controller.
+    pub(crate) fn encode(
+        tree: &DeviceTree,
+        controller: NodeId,
+        specifier: &InterruptSpecifier,
+    ) -> Result<Vec<u32>, DeviceTreeError> {
+        let mismatch = || Irq::mismatch(tree, controller, "#interrupt-cells");
+        let invalid = || DeviceTreeError::InvalidPropertyValue(String::from("interrupts"));
+        let intsize = tree
+            .node(controller)
+            .read_u32("#interrupt-cells")
+            .map_err(|_| mismatch())? as usize;
+        let kind = Controller::of(tree, controller);
+        let mut cells = match specifier {
+            InterruptSpecifier::GicSpi { number, trigger } => {
+                if kind != Controller::Gic || !(3..=4).contains(&intsize) {
+                    return Err(mismatch());
+                }
+                let edge_or_level = matches!(
+                    trigger,
+                    InterruptTrigger::EdgeRising | InterruptTrigger::LevelHigh
+                );
+                if *number >= 988 || !edge_or_level {
+                    return Err(invalid());
+                }
+                vec![0, *number, *trigger as u32]
+            }
+            InterruptSpecifier::GicPpi {
+                number,
+                trigger,
+                cpus,
+            } => {
+                if kind != Controller::Gic || !(3..=4).contains(&intsize) {
+                    return Err(mismatch());
+                }
+                if *number >= 16 {
+                    return Err(invalid());
+                }
+                vec![1, *number, ((*cpus as u32) << 8) | *trigger as u32]
+            }
+            InterruptSpecifier::Plic { source, trigger } => {
+                if kind != Controller::Plic || intsize != 1 + trigger.is_some() as usize {
+                    return Err(mismatch());
+                }
+                if *source == 0 || *source >= 1024 {
+                    return Err(invalid());
+                }
+                let mut cells = vec![*source];
+                cells.extend(trigger.map(|trigger| trigger as u32));
+                cells
+            }
+            InterruptSpecifier::Aplic { source, trigger } => {
+                if kind != Controller::Aplic || intsize != 2 {
+                    return Err(mismatch());
+                }
+                if *source == 0 || *source >= 1024 || *trigger == InterruptTrigger::EdgeBoth {
+                    return Err(invalid());
+                }
+                vec![*source, *trigger as u32]
+            }
+            InterruptSpecifier::Raw(cells) => {
+                if cells.len() != intsize {
+                    return Err(mismatch());
+                }
+                cells.clone()
+            }
+        };
+        // The fourth cell of a GIC is the phandle of a PPI partition
+        cells.resize(intsize, 0);
+        Ok(cells)
+    }
+
+    // Decode the specifier of an interrupt controller, or keep the cells if
+    // they are not of a known form.
+    pub(crate) fn decode(tree: &DeviceTree, irq: &PhandleArgs) -> InterruptSpecifier {
+        let raw = || InterruptSpecifier::Raw(irq.args.clone());
+        match (Controller::of(tree, irq.node), irq.args.as_slice()) {
+            (Controller::Gic, [kind, number, flags] | [kind, number, flags, 0]) => {
+                let Some(trigger) = InterruptTrigger::from_flags(flags & 0xf) else {
+                    return raw();
+                };
+                match kind {
+                    0 if flags >> 4 == 0 => InterruptSpecifier::GicSpi {
+                        number: *number,
+                        trigger,
+                    },
+                    1 if flags >> 16 == 0 && flags & 0xf0 == 0 => InterruptSpecifier::GicPpi {
+                        number: *number,
+                        trigger,
+                        cpus: (flags >> 8) as u8,
+                    },
+                    _ => raw(),
+                }
+            }
+            (Controller::Plic, [source]) => InterruptSpecifier::Plic {
+                source: *source,
+                trigger: None,
+            },
+            (Controller::Plic, [source, flags]) => match InterruptTrigger::from_flags(*flags) {
+                Some(trigger) => InterruptSpecifier::Plic {
+                    source: *source,
+                    trigger: Some(trigger),
+                },
+                None => raw(),
+            },
+            (Controller::Aplic, [source, flags]) => match InterruptTrigger::from_flags(*flags) {
+                Some(trigger) => InterruptSpecifier::Aplic {
+                    source: *source,
+                    trigger,
+                },
+                None => raw(),
+            },
+            _ => raw(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A tree like the "virt" machine of QEMU, where the PCI INTx of slot
+    // `s` pin `p` goes to SPI 3 + (s + p - 1) % 4 of the GIC
+    fn tree() -> DeviceTree {
+        let mut map = vec![];
+        for slot in 0..4 {
+            for pin in 1..=4 {
+                let spi = 3 + (slot + pin - 1) % 4;
+                map.push(format!(
+                    "<{:#x} 0 0 {} &gic 0 0 0 {} 4>",
+                    slot << 11,
+                    pin,
+                    spi
+                ));
+            }
+        }
+        let dts = format!(
+            r#"/dts-v1/;
+/ {{
+	#address-cells = <2>;
+	#size-cells = <2>;
+	interrupt-parent = <&gic>;
+
+	gic: intc@8000000 {{
+		phandle = <1>;
+		compatible = "arm,gic-v3";
+		#interrupt-cells = <3>;
+		#address-cells = <2>;
+		#size-cells = <2>;
+		interrupt-controller;
+		reg = <0 0x8000000 0 0x10000>;
+	}};
+
+	uart@9000000 {{
+		reg = <0 0x9000000 0 0x1000>;
+		interrupts = <0 1 4>, <0 2 4>;
+	}};
+
+	gpio: gpio@9030000 {{
+		phandle = <2>;
+		#interrupt-cells = <2>;
+		interrupt-controller;
+		reg = <0 0x9030000 0 0x1000>;
+	}};
+
+	keys {{
+		interrupts-extended = <&gpio 3 1>, <&gic 0 7 4>;
+	}};
+
+	bad-cells {{
+		interrupts = <0 1>;
+	}};
+
+	pcie@10000000 {{
+		phandle = <3>;
+		device_type = "pci";
+		#address-cells = <3>;
+		#size-cells = <2>;
+		#interrupt-cells = <1>;
+		interrupt-map-mask = <0x1800 0 0 7>;
+		interrupt-map = {};
+
+		ethernet@2,0 {{
+			reg = <0x1000 0 0 0 0>;
+			interrupts = <2>;
+		}};
+
+		no-reg {{
+			interrupts = <1>;
+		}};
+	}};
+
+	broken-pcie {{
+		#address-cells = <3>;
+		#interrupt-cells = <1>;
+		interrupt-map = <0 0 0 1 &gic 0 0>;
+
+		device@0 {{
+			reg = <0 0 0 0 0>;
+			interrupts = <1>;
+		}};
+	}};
+}};
+"#,
+            map.join(", ")
+        );
+        DeviceTree::from_dts_bytes(dts.as_bytes())
+    }
+
+    #[test]
+    fn test_irq_parse() {
+        let tree = tree();
+        let gic = tree.find_node_by_path("/intc@8000000").unwrap();
+        let gpio = tree.find_node_by_path("/gpio@9030000").unwrap();
+        let uart = tree.find_node_by_path("/uart@9000000").unwrap();
+        let keys = tree.find_node_by_path("/keys").unwrap();
+
+        assert_eq!(
+            tree.parse_interrupts(uart),
+            Ok(vec![
+                PhandleArgs {
+                    node: gic,
+                    args: vec![0, 1, 4]
+                },
+                PhandleArgs {
+                    node: gic,
+                    args: vec![0, 2, 4]
+                },
+            ])
+        );
+        assert_eq!(tree.parse_interrupt(uart, 2), Ok(None));
+        assert_eq!(
+            tree.parse_interrupts(keys),
+            Ok(vec![
+                PhandleArgs {
+                    node: gpio,
+                    args: vec![3, 1]
+                },
+                PhandleArgs {
+                    node: gic,
+                    args: vec![0, 7, 4]
+                },
+            ])
+        );
+        assert_eq!(tree.parse_interrupts(gpio), Ok(vec![]));
+
+        let bad = tree.find_node_by_path("/bad-cells").unwrap();
+        assert_eq!(
+            tree.parse_interrupt(bad, 0),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/bad-cells:interrupts"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_irq_parse_interrupt_map() {
+        let tree = tree();
+        let gic = tree.find_node_by_path("/intc@8000000").unwrap();
+        let pcie = tree.find_node_by_path("/pcie@10000000").unwrap();
+        let ethernet = tree
+            .find_node_by_path("/pcie@10000000/ethernet@2,0")
+            .unwrap();
+
+        // Slot 2 pin 2 goes to SPI 3 + (2 + 2 - 1) % 4
+        assert_eq!(
+            tree.parse_interrupt(ethernet, 0),
+            Ok(Some(PhandleArgs {
+                node: gic,
+                args: vec![0, 6, 4]
+            }))
+        );
+
+        // The pin of a function behind a bridge in slot 1 is swizzled by its
+        // slot 3 first: pin 1 becomes pin 4 at slot 1, SPI 3 + (1 + 4 - 1) % 4
+        assert_eq!(
+            tree.parse_pci_interrupt(pcie, &[(0, 1, 0), (1, 3, 0)], 1),
+            Ok(Some(PhandleArgs {
+                node: gic,
+                args: vec![0, 3, 4]
+            }))
+        );
+        assert_eq!(tree.parse_pci_interrupt(pcie, &[(0, 1, 0)], 0), Ok(None));
+
+        let no_reg = tree.find_node_by_path("/pcie@10000000/no-reg").unwrap();
+        assert_eq!(
+            tree.parse_interrupt(no_reg, 0),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/pcie@10000000:interrupt-map"
+            )))
+        );
+
+        // The GIC has 3 interrupt cells and 2 address cells, but the entry
+        // only has 2 parent cells
+        let device = tree.find_node_by_path("/broken-pcie/device@0").unwrap();
+        assert_eq!(
+            tree.parse_interrupt(device, 0),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/broken-pcie:interrupt-map"
+            )))
+        );
+
+        // Interrupt parents referring to each other, neither of them being a
+        // controller or a nexus
+        let tree = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	a {
+		phandle = <1>;
+		#interrupt-cells = <1>;
+		interrupt-parent = <2>;
+	};
+
+	b {
+		phandle = <2>;
+		#interrupt-cells = <1>;
+		interrupt-parent = <1>;
+	};
+
+	c {
+		phandle = <3>;
+		interrupt-parent = <4>;
+	};
+
+	d {
+		phandle = <4>;
+		interrupt-parent = <3>;
+	};
+
+	dev {
+		interrupt-parent = <1>;
+		interrupts = <5>;
+	};
+};
+"#,
+        );
+        let dev = tree.find_node_by_path("/dev").unwrap();
+        assert_eq!(
+            tree.parse_interrupt(dev, 0),
+            Err(DeviceTreeError::NoInterruptParent(String::from("/a")))
+        );
+        let c = tree.find_node_by_path("/c").unwrap();
+        assert_eq!(
+            tree.parse_pci_interrupt(c, &[(0, 1, 0)], 1),
+            Err(DeviceTreeError::NoInterruptParent(String::from("/c")))
+        );
+    }
+
+    #[test]
+    fn test_irq_specifiers() {
+        let mut tree = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	gic: intc@8000000 {
+		phandle = <1>;
+		compatible = "arm,gic-v3";
+		#interrupt-cells = <4>;
+		interrupt-controller;
+
+		its: msi-controller@8080000 {
+			phandle = <2>;
+			compatible = "arm,gic-v3-its";
+			msi-controller;
+			#msi-cells = <1>;
+		};
+	};
+
+	plic: interrupt-controller@c000000 {
+		phandle = <3>;
+		compatible = "thead,th1520-plic", "thead,c900-plic";
+		#interrupt-cells = <2>;
+		interrupt-controller;
+	};
+
+	aplic: interrupt-controller@d000000 {
+		phandle = <4>;
+		compatible = "riscv,aplic";
+		#interrupt-cells = <2>;
+		interrupt-controller;
+	};
+
+	gpio: gpio {
+		phandle = <5>;
+		#interrupt-cells = <2>;
+		interrupt-controller;
+	};
+
+	device {
+		interrupt-parent = <&gic>;
+		interrupts = <0 1 4 0>;
+	};
+};
+"#,
+        );
+        let gic = tree.find_node_by_path("/intc@8000000").unwrap();
+        let its = tree
+            .find_node_by_path("/intc@8000000/msi-controller@8080000")
+            .unwrap();
+        let plic = tree
+            .find_node_by_path("/interrupt-controller@c000000")
+            .unwrap();
+        let aplic = tree
+            .find_node_by_path("/interrupt-controller@d000000")
+            .unwrap();
+        let gpio = tree.find_node_by_path("/gpio").unwrap();
+        let device = tree.find_node_by_path("/device").unwrap();
+
+        let spi = InterruptSpecifier::GicSpi {
+            number: 1,
+            trigger: InterruptTrigger::LevelHigh,
+        };
+        let ppi = InterruptSpecifier::GicPpi {
+            number: 14,
+            trigger: InterruptTrigger::EdgeFalling,
+            cpus: 0,
+        };
+        assert_eq!(
+            tree.interrupt_specifiers(device),
+            Ok(vec![(gic, spi.clone())])
+        );
+        assert_eq!(tree.encode_interrupt(gic, &ppi), Ok(vec![1, 14, 2, 0]));
+
+        let errors = [
+            (
+                gic,
+                InterruptSpecifier::Plic {
+                    source: 1,
+                    trigger: None,
+                },
+                "cell",
+            ),
+            (
+                plic,
+                InterruptSpecifier::Plic {
+                    source: 1,
+                    trigger: None,
+                },
+                "cell",
+            ),
+            (gpio, InterruptSpecifier::Raw(vec![1]), "cell"),
+            (
+                gic,
+                InterruptSpecifier::GicSpi {
+                    number: 1,
+                    trigger: InterruptTrigger::LevelLow,
+                },
+                "value",
+            ),
+            (
+                gic,
+                InterruptSpecifier::GicPpi {
+                    number: 16,
+                    trigger: InterruptTrigger::LevelHigh,
+                    cpus: 0,
+                },
+                "value",
+            ),
+            (
+                aplic,
+                InterruptSpecifier::Aplic {
+                    source: 0,
+                    trigger: InterruptTrigger::LevelHigh,
+                },
+                "value",
+            ),
+            (
+                aplic,
+                InterruptSpecifier::Aplic {
+                    source: 1,
+                    trigger: InterruptTrigger::EdgeBoth,
+                },
+                "value",
+            ),
+        ];
+        for (controller, specifier, error) in errors {
+            let expected = match error {
+                "cell" => DeviceTreeError::CellMismatch(format!(
+                    "{}:#interrupt-cells",
+                    tree.path_of(controller)
+                )),
+                _ => DeviceTreeError::InvalidPropertyValue(String::from("interrupts")),
+            };
+            assert_eq!(tree.encode_interrupt(controller, &specifier), Err(expected));
+        }
+
+        // Moving the device to the PLIC writes its "interrupt-parent"
+        let plic_irq = InterruptSpecifier::Plic {
+            source: 5,
+            trigger: Some(InterruptTrigger::LevelHigh),
+        };
+        tree.set_interrupts(device, plic, std::slice::from_ref(&plic_irq))
+            .unwrap();
+        assert_eq!(tree.node(device).read_u32("interrupt-parent"), Ok(3));
+        assert_eq!(
+            tree.interrupt_specifiers(device),
+            Ok(vec![(plic, plic_irq.clone())])
+        );
+
+        let aplic_irq = InterruptSpecifier::Aplic {
+            source: 7,
+            trigger: InterruptTrigger::EdgeRising,
+        };
+        let gpio_irq = InterruptSpecifier::Raw(vec![3, 1]);
+        let specifiers = vec![
+            (gic, ppi.clone()),
+            (aplic, aplic_irq),
+            (gpio, gpio_irq),
+            (plic, plic_irq),
+        ];
+        tree.set_interrupts_extended(device, &specifiers).unwrap();
+        assert!(tree.node(device).find_property("interrupts").is_none());
+        assert_eq!(tree.interrupt_specifiers(device), Ok(specifiers));
+
+        // A GICv2 PPI with a CPU mask, and a flag GICv3 doesn't define
+        let args = PhandleArgs {
+            node: gic,
+            args: vec![1, 13, 0xf04],
+        };
+        assert_eq!(
+            tree.decode_interrupt(&args),
+            InterruptSpecifier::GicPpi {
+                number: 13,
+                trigger: InterruptTrigger::LevelHigh,
+                cpus: 0xf,
+            }
+        );
+        let args = PhandleArgs {
+            node: gic,
+            args: vec![0, 13, 0x104],
+        };
+        assert_eq!(
+            tree.decode_interrupt(&args),
+            InterruptSpecifier::Raw(vec![0, 13, 0x104])
+        );
+
+        assert_eq!(tree.msi_parent(device), Ok(None));
+        tree.set_msi_parent(device, its, &[0x10]).unwrap();
+        assert_eq!(
+            tree.msi_parent(device),
+            Ok(Some(PhandleArgs {
+                node: its,
+                args: vec![0x10]
+            }))
+        );
+        assert_eq!(
+            tree.set_msi_parent(device, its, &[]),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/intc@8000000/msi-controller@8080000:#msi-cells"
+            )))
+        );
+    }
+}
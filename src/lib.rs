@@ -1,17 +1,48 @@
 // Copyright (c) 2023, Michael Zhao
 // SPDX-License-Identifier: MIT
 
+mod address;
+pub use address::DmaRange;
+mod cpu;
+pub use cpu::{Cache, CacheGeometry, Cpu, CpuLocation, CpuTopology};
+mod dependency;
+pub use dependency::{Dependency, DependencyGraph};
 mod devicetree;
 pub use devicetree::DeviceTree;
+mod diff;
+pub use diff::{DiffEntry, DiffOptions, TreeDiff};
 mod dtb;
 mod dtb_generator;
 mod dtb_parser;
 mod dts_generator;
 mod dts_parser;
+mod error;
+pub use error::DeviceTreeError;
+mod graft;
+pub use graft::{GraftOptions, LabelConflict};
+mod iommu;
+mod irq;
+pub use irq::{InterruptSpecifier, InterruptTrigger};
+mod memory;
+pub use memory::{MemoryRegion, MemoryRegionKind};
 mod node;
-pub use node::Node;
+pub use node::{Node, NodeId, NodeMut, NodeRef};
+mod numa;
+pub use numa::{NumaNode, NumaTopology};
+mod of;
+pub use of::OfDeviceId;
+mod phandle;
+pub use phandle::{PhandleArgs, PhandleNumbering, PhandleReference};
 mod property;
-pub use property::Property;
+pub use property::{Property, PropertyValue};
+mod reference;
+pub use reference::{Reference, ReferenceGraph, ReferenceKind};
 mod reservation;
 pub use reservation::Reservation;
+mod selector;
+pub use selector::Selector;
 mod utils;
+mod virt;
+pub use virt::{PciHost, VirtDevice, VirtMachine};
+mod visit;
+pub use visit::{BreadthFirstIter, DepthFirstIter, PropertyIter, VisitAction, Visitor, VisitorMut};
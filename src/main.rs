@@ -2,52 +2,232 @@
 // SPDX-License-Identifier: MIT
 
 use argh::FromArgs;
-use devicetree_tool::DeviceTree;
+use devicetree_tool::{DeviceTree, DiffOptions, Selector};
 
 #[derive(FromArgs)]
 /// Device tree tool
 struct Args {
     /// input type
     #[argh(option)]
-    in_type: String,
+    in_type: Option<String>,
 
     /// input filename
     #[argh(option)]
-    in_file: String,
+    in_file: Option<String>,
 
     /// output type
     #[argh(option)]
-    out_type: String,
+    out_type: Option<String>,
 
     /// output filename
     #[argh(option)]
-    out_file: String,
+    out_file: Option<String>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
-    let args: Args = argh::from_env();
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Diff(DiffArgs),
+    Query(QueryArgs),
+    Refs(RefsArgs),
+}
+
+#[derive(FromArgs)]
+/// Compare two device trees
+#[argh(subcommand, name = "diff")]
+struct DiffArgs {
+    /// the old DTS or DTB file
+    #[argh(positional)]
+    old_file: String,
+
+    /// the new DTS or DTB file
+    #[argh(positional)]
+    new_file: String,
+
+    /// print the differences in JSON
+    #[argh(switch)]
+    json: bool,
+
+    /// ignore the numbering of phandles
+    #[argh(switch)]
+    ignore_phandles: bool,
+
+    /// ignore the order of properties and sub nodes
+    #[argh(switch)]
+    ignore_order: bool,
+}
+
+#[derive(FromArgs)]
+/// Find nodes or properties matching a selector
+#[argh(subcommand, name = "query")]
+struct QueryArgs {
+    /// the DTS or DTB file
+    #[argh(positional)]
+    file: String,
+
+    /// the selector, like "/soc/**/serial@*[status=okay]::reg"
+    #[argh(positional)]
+    selector: String,
+
+    /// print the matches in JSON
+    #[argh(switch)]
+    json: bool,
+}
+
+#[derive(FromArgs)]
+/// List the references to and from a node
+#[argh(subcommand, name = "refs")]
+struct RefsArgs {
+    /// the DTS or DTB file
+    #[argh(positional)]
+    file: String,
+
+    /// the path or alias of the node
+    #[argh(positional)]
+    path: String,
+
+    /// print the references in JSON
+    #[argh(switch)]
+    json: bool,
+}
+
+// Read a device tree from a DTB file, or a DTS file if the DTB magic is missing.
+fn read_tree(file: &str) -> DeviceTree {
+    let bytes = std::fs::read(file).expect("Unable to read input file");
+    if bytes.len() >= 4 && bytes[0..4] == 0xd00dfeedu32.to_be_bytes() {
+        DeviceTree::from_dtb_bytes(&bytes)
+    } else {
+        DeviceTree::from_dts_bytes(&bytes)
+    }
+}
 
-    if &args.in_type != "dts" && &args.in_type != "dtb" {
+fn diff(args: &DiffArgs) {
+    let old_tree = read_tree(&args.old_file);
+    let new_tree = read_tree(&args.new_file);
+    let options = DiffOptions {
+        ignore_phandles: args.ignore_phandles,
+        ignore_order: args.ignore_order,
+    };
+    let diff = old_tree.diff(&new_tree, options);
+
+    if args.json {
+        println!("{}", diff.to_json());
+    } else {
+        print!("{}", diff);
+    }
+
+    // Exit like diff(1): 0 if the trees are the same, 1 otherwise
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn query(args: &QueryArgs) {
+    let tree = read_tree(&args.file);
+    let selector = match Selector::parse(&args.selector) {
+        Ok(selector) => selector,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    // Print the nodes, or the properties if the selector selects them
+    let found = !selector.select_nodes(&tree).is_empty();
+    if args.json {
+        println!("{}", selector.to_json(&tree));
+    } else if selector.selects_properties() {
+        for (id, prop) in selector.select_properties(&tree) {
+            let path = tree.path_of(id);
+            if prop.value.is_empty() {
+                println!("{}: {};", path, prop.name);
+            } else {
+                println!("{}: {} = {};", path, prop.name, prop.typed_value());
+            }
+        }
+    } else {
+        for id in selector.select_nodes(&tree) {
+            println!("{}", tree.path_of(id));
+        }
+    }
+
+    // Exit like grep(1): 0 if anything matches, 1 otherwise
+    if !found {
+        std::process::exit(1);
+    }
+}
+
+fn refs(args: &RefsArgs) {
+    let tree = read_tree(&args.file);
+    let Some(id) = tree.find_node_by_path(&args.path) else {
+        eprintln!("node not found: {}", args.path);
+        std::process::exit(2);
+    };
+    let graph = tree.reference_graph();
+
+    if args.json {
+        println!("{}", graph.to_json(&tree, id));
+    } else {
+        println!("referrers:");
+        for reference in graph.referrers(id) {
+            println!(
+                "    {}: {}",
+                tree.path_of(reference.from),
+                reference.location()
+            );
+        }
+        println!("references:");
+        for reference in graph.references_from(id) {
+            println!(
+                "    {} -> {}",
+                reference.location(),
+                tree.path_of(reference.to)
+            );
+        }
+    }
+}
+
+fn convert(in_type: &str, in_file: &str, out_type: &str, out_file: &str) {
+    if in_type != "dts" && in_type != "dtb" {
         println!("Invalid input type");
-    } else if &args.out_type != "dts" && &args.out_type != "dtb" {
+    } else if out_type != "dts" && out_type != "dtb" {
         println!("Invalid output type");
-    } else if &args.in_type == &args.out_type {
+    } else if in_type == out_type {
         println!("Input type and output type cannot be same");
-    } else if &args.in_type == "dts" && &args.out_type == "dtb" {
-        println!("Encode DTS ({}) to DTB ({})", args.in_file, args.out_file);
+    } else if in_type == "dts" && out_type == "dtb" {
+        println!("Encode DTS ({}) to DTB ({})", in_file, out_file);
 
-        let dts = std::fs::read_to_string(&args.in_file).expect("Unable to read input file");
+        let dts = std::fs::read_to_string(in_file).expect("Unable to read input file");
         let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
         let dtb = tree.generate_dtb();
-        std::fs::write(&args.out_file, dtb).expect("Unable to write output file");
-    } else if &args.in_type == "dtb" && &args.out_type == "dts" {
-        println!("Decode DTB ({}) to DTS ({})", args.in_file, args.out_file);
+        std::fs::write(out_file, dtb).expect("Unable to write output file");
+    } else if in_type == "dtb" && out_type == "dts" {
+        println!("Decode DTB ({}) to DTS ({})", in_file, out_file);
 
-        let dtb = std::fs::read(&args.in_file).expect("Unable to read input file");
+        let dtb = std::fs::read(in_file).expect("Unable to read input file");
         let tree = DeviceTree::from_dtb_bytes(&dtb);
         let dts = tree.generate_dts();
-        std::fs::write(&args.out_file, dts).expect("Unable to write output file");
+        std::fs::write(out_file, dts).expect("Unable to write output file");
     } else {
         println!("Invalid input or output type");
     }
 }
+
+fn main() {
+    let args: Args = argh::from_env();
+
+    match &args.command {
+        Some(Command::Diff(diff_args)) => diff(diff_args),
+        Some(Command::Query(query_args)) => query(query_args),
+        Some(Command::Refs(refs_args)) => refs(refs_args),
+        None => match (&args.in_type, &args.in_file, &args.out_type, &args.out_file) {
+            (Some(in_type), Some(in_file), Some(out_type), Some(out_file)) => {
+                convert(in_type, in_file, out_type, out_file)
+            }
+            _ => println!("Input and output type and filename are required"),
+        },
+    }
+}
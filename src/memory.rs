@@ -0,0 +1,555 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::address::Address;
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::Node;
+use crate::node::NodeId;
+use crate::property::Property;
+use crate::reservation::Reservation;
+
+/// Where a region of a `DeviceTree::memory_map` comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegionKind {
+    /// RAM of the memory nodes that is not reserved
+    Usable,
+    /// RAM reserved by the `/memreserve/` entry at the index in
+    /// `DeviceTree::reservations`
+    MemReserve(usize),
+    /// RAM reserved by a child node of "/reserved-memory"
+    Reserved {
+        node: NodeId,
+        /// The region must not be mapped by the OS
+        no_map: bool,
+        /// The OS may use the region until the driver of the node claims it
+        reusable: bool,
+    },
+}
+
+/// A region of physical memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub address: u64,
+    pub size: u64,
+    pub kind: MemoryRegionKind,
+}
+
+pub(crate) struct MemoryMap {}
+
+impl MemoryMap {
+    // Get the ranges of RAM, from the "linux,usable-memory" or "reg" of the
+    // available nodes with device_type "memory".
+    pub(crate) fn ram(tree: &DeviceTree) -> Result<Vec<(u64, u64)>, DeviceTreeError> {
+        let mut ram = vec![];
+        for id in tree.children(tree.root()).iter() {
+            let node = tree.node(*id);
+            if node.read_string("device_type") != Ok("memory") || !node.is_available() {
+                continue;
+            }
+            let name = match node.find_property("linux,usable-memory") {
+                Some(_) => "linux,usable-memory",
+                None => "reg",
+            };
+            ram.extend(Address::reg(tree, *id, name)?);
+        }
+        Ok(ram)
+    }
+
+    // Get the reserved ranges in the order of precedence: the "no-map"
+    // children of "/reserved-memory", the other children having "reg", and
+    // the `/memreserve/` entries.
+    fn reserved(tree: &DeviceTree) -> Result<Vec<(u64, u64, MemoryRegionKind)>, DeviceTreeError> {
+        let mut no_map = vec![];
+        let mut reserved = vec![];
+        if let Some(parent) = tree.find_child_by_name(tree.root(), "reserved-memory") {
+            for id in tree.children(parent).iter() {
+                let node = tree.node(*id);
+                // The regions without "reg" are allocated dynamically
+                if node.find_property("reg").is_none() || !node.is_available() {
+                    continue;
+                }
+                let kind = MemoryRegionKind::Reserved {
+                    node: *id,
+                    no_map: node.find_property("no-map").is_some(),
+                    reusable: node.find_property("reusable").is_some(),
+                };
+                let list = if node.find_property("no-map").is_some() {
+                    &mut no_map
+                } else {
+                    &mut reserved
+                };
+                for (address, size) in Address::reg(tree, *id, "reg")? {
+                    list.push((address, size, kind));
+                }
+            }
+        }
+        no_map.extend(reserved);
+        for (i, reservation) in tree.reservations.iter().enumerate() {
+            no_map.push((
+                reservation.address,
+                reservation.length,
+                MemoryRegionKind::MemReserve(i),
+            ));
+        }
+        Ok(no_map)
+    }
+
+    // Split the RAM by the reservations into regions, like the "memory" and
+    // "reserved" memblocks of Linux.
+    pub(crate) fn build(tree: &DeviceTree) -> Result<Vec<MemoryRegion>, DeviceTreeError> {
+        let end = |address: u64, size: u64| address.saturating_add(size);
+        let ram: Vec<(u64, u64)> = MemoryMap::ram(tree)?
+            .into_iter()
+            .filter(|(_, size)| *size > 0)
+            .map(|(address, size)| (address, end(address, size)))
+            .collect();
+        let reserved: Vec<(u64, u64, MemoryRegionKind)> = MemoryMap::reserved(tree)?
+            .into_iter()
+            .filter(|(_, size, _)| *size > 0)
+            .map(|(address, size, kind)| (address, end(address, size), kind))
+            .collect();
+
+        // Every piece between two boundaries has a single kind
+        let mut boundaries: Vec<u64> = ram.iter().flat_map(|(start, end)| [*start, *end]).collect();
+        boundaries.extend(reserved.iter().flat_map(|(start, end, _)| [*start, *end]));
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut regions: Vec<MemoryRegion> = vec![];
+        for piece in boundaries.windows(2) {
+            let (start, end) = (piece[0], piece[1]);
+            if !ram.iter().any(|(s, e)| *s <= start && end <= *e) {
+                continue;
+            }
+            let kind = reserved
+                .iter()
+                .find(|(s, e, _)| *s <= start && end <= *e)
+                .map_or(MemoryRegionKind::Usable, |(_, _, kind)| *kind);
+            match regions.last_mut() {
+                Some(last) if last.kind == kind && last.address + last.size == start => {
+                    last.size += end - start;
+                }
+                _ => regions.push(MemoryRegion {
+                    address: start,
+                    size: end - start,
+                    kind,
+                }),
+            }
+        }
+        Ok(regions)
+    }
+
+    // Find the highest address for a region in the usable RAM within a
+    // range, like the top-down allocation of memblock.
+    fn find_free(
+        usable: &[(u64, u64)],
+        size: u64,
+        align: u64,
+        (start, end): (u64, u64),
+    ) -> Option<u64> {
+        usable.iter().rev().find_map(|(address, length)| {
+            let low = (*address).max(start);
+            let high = address.saturating_add(*length).min(end);
+            let found = high.checked_sub(size)? / align * align;
+            (found >= low).then_some(found)
+        })
+    }
+
+    // Place the children of "/reserved-memory" that have "size" but no
+    // "reg", like `__reserved_mem_alloc_size` of Linux, and write the "reg"
+    // of them.
+    pub(crate) fn allocate_reserved(tree: &mut DeviceTree) -> Result<Vec<NodeId>, DeviceTreeError> {
+        let Some(parent) = tree.find_child_by_name(tree.root(), "reserved-memory") else {
+            return Ok(vec![]);
+        };
+        let na = Address::cells(tree, parent, "#address-cells", 2);
+        let ns = Address::cells(tree, parent, "#size-cells", 1);
+
+        let mut allocated = vec![];
+        for id in tree.children(parent).to_vec() {
+            let node = tree.node(id);
+            if node.find_property("reg").is_some()
+                || node.find_property("size").is_none()
+                || !node.is_available()
+            {
+                continue;
+            }
+            let size = Address::read_entries(tree, id, "size", &[ns])?[0][0];
+            let align = match node.find_property("alignment") {
+                Some(_) => Address::read_entries(tree, id, "alignment", &[na])?[0][0].max(1),
+                None => 0x1000,
+            };
+            let ranges = match node.find_property("alloc-ranges") {
+                Some(_) => Address::read_entries(tree, id, "alloc-ranges", &[na, ns])?
+                    .iter()
+                    .map(|range| (range[0], range[0].saturating_add(range[1])))
+                    .collect(),
+                None => vec![(0, u64::MAX)],
+            };
+
+            // The regions placed before are in the memory map as well
+            let usable: Vec<(u64, u64)> = MemoryMap::build(tree)?
+                .iter()
+                .filter(|region| region.kind == MemoryRegionKind::Usable)
+                .map(|region| (region.address, region.size))
+                .collect();
+            let address = ranges
+                .into_iter()
+                .find_map(|range| MemoryMap::find_free(&usable, size, align, range))
+                .ok_or_else(|| DeviceTreeError::AllocationFailed(tree.path_of(id)))?;
+
+            Address::write_entries(tree, id, "reg", &[na, ns], &[vec![address, size]])?;
+            allocated.push(id);
+        }
+        Ok(allocated)
+    }
+
+    // Move the `/memreserve/` entries into children of "/reserved-memory",
+    // which is created if it is missing.
+    pub(crate) fn from_memreserve(tree: &mut DeviceTree) -> Result<Vec<NodeId>, DeviceTreeError> {
+        let parent = tree.find_child_by_name(tree.root(), "reserved-memory");
+        let (na, ns) = match parent {
+            Some(parent) => (
+                Address::cells(tree, parent, "#address-cells", 2),
+                Address::cells(tree, parent, "#size-cells", 1),
+            ),
+            None => (
+                Address::cells(tree, tree.root(), "#address-cells", 2),
+                Address::cells(tree, tree.root(), "#size-cells", 1),
+            ),
+        };
+
+        // Check all the entries before changing the tree
+        let mut nodes = vec![];
+        for reservation in &tree.reservations {
+            let name = format!("memreserve@{:x}", reservation.address);
+            let exists =
+                parent.is_some_and(|parent| tree.find_child_by_name(parent, &name).is_some());
+            if exists || nodes.iter().any(|node: &Node| node.name == name) {
+                return Err(DeviceTreeError::NodeExists(format!(
+                    "/reserved-memory/{name}"
+                )));
+            }
+            if let Some(label) = &reservation.label {
+                if tree.find_node_by_label(label).is_some() {
+                    return Err(DeviceTreeError::DuplicateLabel(label.clone()));
+                }
+            }
+            let reg = Address::encode(&[reservation.address, reservation.length], &[na, ns])
+                .ok_or_else(|| {
+                    DeviceTreeError::CellMismatch(format!(
+                        "{}:#address-cells",
+                        tree.path_of(parent.unwrap_or(tree.root()))
+                    ))
+                })?;
+            let mut node = match &reservation.label {
+                Some(label) => Node::new_with_label(&name, label),
+                None => Node::new(&name),
+            };
+            node.add_property(Property::new_u32s("reg", reg));
+            nodes.push(node);
+        }
+
+        let parent = match parent {
+            Some(parent) => parent,
+            None => {
+                let mut node = Node::new("reserved-memory");
+                node.add_property(Property::new_u32("#address-cells", na as u32));
+                node.add_property(Property::new_u32("#size-cells", ns as u32));
+                node.add_property(Property::new_empty("ranges"));
+                tree.add_sub_node(tree.root(), node)
+            }
+        };
+        tree.reservations.clear();
+        Ok(nodes
+            .into_iter()
+            .map(|node| tree.add_sub_node(parent, node))
+            .collect())
+    }
+
+    // Move the children of "/reserved-memory" that have nothing but "reg"
+    // into `/memreserve/` entries. The other children have properties that
+    // `/memreserve/` can't express, like "no-map" and "compatible".
+    pub(crate) fn to_memreserve(
+        tree: &mut DeviceTree,
+    ) -> Result<Vec<Reservation>, DeviceTreeError> {
+        let Some(parent) = tree.find_child_by_name(tree.root(), "reserved-memory") else {
+            return Ok(vec![]);
+        };
+        let mut ids = vec![];
+        let mut moved = vec![];
+        for id in tree.children(parent).iter() {
+            let node = tree.node(*id);
+            if node.properties.len() != 1
+                || node.find_property("reg").is_none()
+                || !tree.children(*id).is_empty()
+            {
+                continue;
+            }
+            for (i, (address, length)) in Address::reg(tree, *id, "reg")?.into_iter().enumerate() {
+                moved.push(Reservation {
+                    address,
+                    length,
+                    // Only the first entry can keep the label
+                    label: node.label.clone().filter(|_| i == 0),
+                });
+            }
+            ids.push(*id);
+        }
+        for id in ids {
+            tree.take_node(id)?;
+        }
+        tree.reservations.extend(moved.iter().cloned());
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_map() {
+        let tree = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/memreserve/ 0x40000000 0x1000;
+/memreserve/ 0x40300000 0x200000;
+/ {
+	#address-cells = <2>;
+	#size-cells = <2>;
+
+	memory@40000000 {
+		device_type = "memory";
+		reg = <0x0 0x40000000 0x0 0x20000000>;
+	};
+
+	memory@60000000 {
+		device_type = "memory";
+		reg = <0x0 0x60000000 0x0 0x10000000>, <0x0 0x68000000 0x0 0x10000000>;
+	};
+
+	memory@100000000 {
+		device_type = "memory";
+		reg = <0x1 0x0 0x0 0x10000000>;
+		status = "disabled";
+	};
+
+	reserved-memory {
+		#address-cells = <2>;
+		#size-cells = <2>;
+		ranges;
+
+		secmon@40200000 {
+			reg = <0x0 0x40200000 0x0 0x200000>;
+			no-map;
+		};
+
+		cma@50000000 {
+			reg = <0x0 0x50000000 0x0 0x1000000>;
+			reusable;
+		};
+
+		dynamic {
+			size = <0x0 0x100000>;
+		};
+
+		outside@10000000 {
+			reg = <0x0 0x10000000 0x0 0x1000>;
+		};
+	};
+};
+"#,
+        );
+        let secmon = tree
+            .find_node_by_path("/reserved-memory/secmon@40200000")
+            .unwrap();
+        let cma = tree
+            .find_node_by_path("/reserved-memory/cma@50000000")
+            .unwrap();
+        let region = |address: u64, size: u64, kind| MemoryRegion {
+            address,
+            size,
+            kind,
+        };
+        assert_eq!(
+            tree.memory_map(),
+            Ok(vec![
+                region(0x40000000, 0x1000, MemoryRegionKind::MemReserve(0)),
+                region(0x40001000, 0x1ff000, MemoryRegionKind::Usable),
+                // The no-map region wins over the overlapping /memreserve/
+                region(
+                    0x40200000,
+                    0x200000,
+                    MemoryRegionKind::Reserved {
+                        node: secmon,
+                        no_map: true,
+                        reusable: false
+                    }
+                ),
+                region(0x40400000, 0x100000, MemoryRegionKind::MemReserve(1)),
+                region(0x40500000, 0xfb00000, MemoryRegionKind::Usable),
+                region(
+                    0x50000000,
+                    0x1000000,
+                    MemoryRegionKind::Reserved {
+                        node: cma,
+                        no_map: false,
+                        reusable: true
+                    }
+                ),
+                // The overlapping and adjacent memory ranges are merged
+                region(0x51000000, 0x27000000, MemoryRegionKind::Usable),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_memory_allocate_reserved() {
+        let dts = br#"/dts-v1/;
+/ {
+	#address-cells = <1>;
+	#size-cells = <1>;
+
+	memory@40000000 {
+		device_type = "memory";
+		reg = <0x40000000 0x10000000>;
+	};
+
+	reserved-memory {
+		#address-cells = <1>;
+		#size-cells = <1>;
+		ranges;
+
+		secmon@4ff00000 {
+			reg = <0x4ff00000 0x100000>;
+			no-map;
+		};
+
+		aligned {
+			size = <0x100000>;
+			alignment = <0x400000>;
+		};
+
+		ranged {
+			size = <0x1000>;
+			alloc-ranges = <0x30000000 0x1000000>, <0x40000000 0x1000000>;
+		};
+
+		disabled {
+			size = <0x1000>;
+			status = "disabled";
+		};
+
+		placed@48000000 {
+			size = <0x1000>;
+			reg = <0x48000000 0x1000>;
+		};
+	};
+};
+"#;
+        let mut tree = DeviceTree::from_dts_bytes(dts);
+        let aligned = tree.find_node_by_path("/reserved-memory/aligned").unwrap();
+        let ranged = tree.find_node_by_path("/reserved-memory/ranged").unwrap();
+        let disabled = tree.find_node_by_path("/reserved-memory/disabled").unwrap();
+
+        assert_eq!(tree.allocate_reserved_memory(), Ok(vec![aligned, ranged]));
+        // The highest place below the static region is aligned down
+        assert_eq!(tree.reg(aligned), Ok(vec![(0x4fc00000, 0x100000)]));
+        // The first range has no RAM
+        assert_eq!(tree.reg(ranged), Ok(vec![(0x40fff000, 0x1000)]));
+        assert!(tree.node(disabled).find_property("reg").is_none());
+        // Everything is placed already
+        assert_eq!(tree.allocate_reserved_memory(), Ok(vec![]));
+
+        let mut tree = DeviceTree::from_dts_bytes(dts);
+        let aligned = tree.find_node_by_path("/reserved-memory/aligned").unwrap();
+        tree.node_mut(aligned)
+            .set_property(Property::new_u32("size", 0x10000000));
+        assert_eq!(
+            tree.allocate_reserved_memory(),
+            Err(DeviceTreeError::AllocationFailed(String::from(
+                "/reserved-memory/aligned"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_memory_reservations() {
+        let dts = br#"/dts-v1/;
+fw: /memreserve/ 0x40000000 0x1000;
+/memreserve/ 0x80000000 0x1000;
+/ {
+	#address-cells = <1>;
+	#size-cells = <1>;
+
+	memory@40000000 {
+		device_type = "memory";
+		reg = <0x40000000 0x10000000>;
+	};
+
+	reserved-memory {
+		#address-cells = <1>;
+		#size-cells = <1>;
+		ranges;
+
+		secmon: secmon@4ff00000 {
+			reg = <0x4ff00000 0x100000>;
+			no-map;
+		};
+
+		ramoops: ramoops@4fe00000 {
+			reg = <0x4fe00000 0x10000>, <0x4fe80000 0x10000>;
+		};
+	};
+};
+"#;
+        let mut tree = DeviceTree::from_dts_bytes(dts);
+        assert_eq!(tree.check_reservations(), Ok(vec![1]));
+
+        // The labels must stay unique
+        assert_eq!(
+            tree.add_reservation(Reservation::new_with_label(0x0, 0x1000, "secmon")),
+            Err(DeviceTreeError::DuplicateLabel(String::from("secmon")))
+        );
+
+        let nodes = tree.memreserve_to_reserved_memory().unwrap();
+        let paths: Vec<String> = nodes.iter().map(|id| tree.path_of(*id)).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/reserved-memory/memreserve@40000000",
+                "/reserved-memory/memreserve@80000000"
+            ]
+        );
+        assert_eq!(tree.find_node_by_label("fw"), Some(nodes[0]));
+        assert_eq!(tree.reg(nodes[1]), Ok(vec![(0x80000000, 0x1000)]));
+        assert!(tree.reservations.is_empty());
+
+        // The no-map region stays a node
+        let reservations = tree.reserved_memory_to_memreserve().unwrap();
+        assert_eq!(
+            reservations,
+            vec![
+                Reservation::new_with_label(0x4fe00000, 0x10000, "ramoops"),
+                Reservation::new(0x4fe80000, 0x10000),
+                Reservation::new_with_label(0x40000000, 0x1000, "fw"),
+                Reservation::new(0x80000000, 0x1000),
+            ]
+        );
+        let reserved = tree.find_node_by_path("/reserved-memory").unwrap();
+        assert_eq!(tree.children(reserved).len(), 1);
+
+        // An address that doesn't fit the cells
+        let mut tree = DeviceTree::from_dts_bytes(dts);
+        tree.add_reservation(Reservation::new(0x1_0000_0000, 0x1000))
+            .unwrap();
+        assert_eq!(
+            tree.memreserve_to_reserved_memory(),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/reserved-memory:#address-cells"
+            )))
+        );
+        assert_eq!(tree.reservations.len(), 3);
+    }
+}
@@ -1,20 +1,35 @@
 // Copyright (c) 2023, Michael Zhao
 // SPDX-License-Identifier: MIT
 
+use crate::devicetree::DeviceTree;
 use crate::dts_generator::DtsGenerator;
+use crate::error::DeviceTreeError;
 use crate::property::Property;
-use std::sync::{Arc, Mutex};
+
+/// The identifier of a node stored in a `DeviceTree`.
+///
+/// A `NodeId` stays valid until the node is removed from the tree, no matter
+/// how other nodes are added, removed or moved. The `NodeId` of a removed
+/// node may be given to a node added later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub(crate) usize);
 
 /// A node that is used to describe a device.
 ///
 /// A node has a list of properties that are represented with a vector of `Property`.
 ///
-/// `Node` can also contain other nodes.
+/// `Node` can also contain other nodes. The sub nodes are kept in `sub_nodes`
+/// while the node is built on its own. Once the node is added to a
+/// `DeviceTree`, the tree takes over the sub nodes and `sub_nodes` is left
+/// empty, the sub nodes are then accessed with `DeviceTree::children()`. The
+/// sub nodes added to a node of a tree through `DeviceTree::node_mut()` are
+/// taken over by the tree as well.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Node {
     pub name: String,
     pub label: Option<String>,
-    pub properties: Vec<Arc<Mutex<Property>>>,
-    pub sub_nodes: Vec<Arc<Mutex<Node>>>,
+    pub properties: Vec<Property>,
+    pub sub_nodes: Vec<Node>,
 }
 
 impl Node {
@@ -58,6 +73,36 @@ impl Node {
         }
     }
 
+    /// Check if a name follows the node name syntax `node-name@unit-address`.
+    ///
+    /// The node name must have 1 to 31 characters, the node name and the
+    /// optional unit address can only use digits, letters and `,._+-`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    ///
+    /// assert_eq!(Node::is_valid_name("pcie@10000000"), true);
+    /// assert_eq!(Node::is_valid_name("cpu-map"), true);
+    /// assert_eq!(Node::is_valid_name("serial@"), false);
+    /// assert_eq!(Node::is_valid_name("a/b"), false);
+    /// ```
+    pub fn is_valid_name(name: &str) -> bool {
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || ",._+-".contains(c);
+        let (node_name, unit_address) = match name.split_once('@') {
+            Some((node_name, unit_address)) => (node_name, Some(unit_address)),
+            None => (name, None),
+        };
+        if node_name.is_empty() || node_name.len() > 31 || !node_name.chars().all(valid_char) {
+            return false;
+        }
+        match unit_address {
+            Some(unit_address) => !unit_address.is_empty() && unit_address.chars().all(valid_char),
+            None => true,
+        }
+    }
+
     /// Add a `Property` to the `Node`.
     ///
     /// # Example
@@ -78,7 +123,7 @@ impl Node {
     ///            prop2 = <0x68 0x65 0x6c 0x6c 0x6f 0x0>;\n};\n");
     /// ```
     pub fn add_property(&mut self, prop: Property) {
-        self.properties.push(Arc::new(Mutex::new(prop)));
+        self.properties.push(prop);
     }
 
     /// Add a sub node to the `Node`.
@@ -103,7 +148,126 @@ impl Node {
     ///            "node {\n\n\tsub_node {\n\t\tprop = <0x0 0x0 0x0 0x2a>;\n\t};\n};\n");
     /// ```
     pub fn add_sub_node(&mut self, sub_node: Node) {
-        self.sub_nodes.push(Arc::new(Mutex::new(sub_node)));
+        self.sub_nodes.push(sub_node);
+    }
+
+    /// Set a `Property` of the `Node`.
+    ///
+    /// If a property with the same name exists, it is replaced in place and
+    /// returned, otherwise the property is added to the end.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    ///
+    /// let mut node = Node::new("node");
+    ///
+    /// node.set_property(Property::new_str("status", "disabled"));
+    /// let old = node.set_property(Property::new_str("status", "okay")).unwrap();
+    ///
+    /// assert_eq!(old.value, b"disabled\0".to_vec());
+    /// assert_eq!(node.properties.len(), 1);
+    /// assert_eq!(node.find_property("status").unwrap().value, b"okay\0".to_vec());
+    /// ```
+    pub fn set_property(&mut self, prop: Property) -> Option<Property> {
+        match self.properties.iter().position(|p| p.name == prop.name) {
+            Some(index) => Some(std::mem::replace(&mut self.properties[index], prop)),
+            None => {
+                self.properties.push(prop);
+                None
+            }
+        }
+    }
+
+    /// Remove a `Property` from the `Node` by name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_u32("prop", 42));
+    ///
+    /// let prop = node.remove_property("prop").unwrap();
+    ///
+    /// assert_eq!(prop.name, "prop");
+    /// assert_eq!(node.properties.len(), 0);
+    /// assert_eq!(node.remove_property("prop").is_none(), true);
+    /// ```
+    pub fn remove_property(&mut self, name: &str) -> Option<Property> {
+        let index = self.properties.iter().position(|p| p.name == name)?;
+        Some(self.properties.remove(index))
+    }
+
+    /// Insert a sub node to the `Node` at a position.
+    ///
+    /// The sub nodes after the position are shifted, a position beyond the
+    /// end appends the sub node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_sub_node(Node::new("sub_node_0"));
+    /// node.add_sub_node(Node::new("sub_node_2"));
+    ///
+    /// node.insert_sub_node_at(1, Node::new("sub_node_1"));
+    ///
+    /// assert_eq!(node.sub_nodes[1].name, "sub_node_1");
+    /// ```
+    pub fn insert_sub_node_at(&mut self, index: usize, sub_node: Node) {
+        let index = index.min(self.sub_nodes.len());
+        self.sub_nodes.insert(index, sub_node);
+    }
+
+    /// Take a sub node out of the `Node` by name.
+    ///
+    /// The sub node is detached from the `Node` and returned with its
+    /// properties and sub nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    ///
+    /// let mut node = Node::new("node");
+    /// let mut sub_node = Node::new("sub_node");
+    /// sub_node.add_sub_node(Node::new("sub_sub_node"));
+    /// node.add_sub_node(sub_node);
+    ///
+    /// let sub_node = node.take_sub_node("sub_node").unwrap();
+    ///
+    /// assert_eq!(node.sub_nodes.len(), 0);
+    /// assert_eq!(sub_node.sub_nodes.len(), 1);
+    /// ```
+    pub fn take_sub_node(&mut self, name: &str) -> Option<Node> {
+        let index = self.sub_nodes.iter().position(|n| n.name == name)?;
+        Some(self.sub_nodes.remove(index))
+    }
+
+    /// Remove a sub node from the `Node` by name.
+    ///
+    /// Return `true` if the sub node existed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_sub_node(Node::new("sub_node"));
+    ///
+    /// assert_eq!(node.remove_sub_node("sub_node"), true);
+    /// assert_eq!(node.remove_sub_node("sub_node"), false);
+    /// ```
+    pub fn remove_sub_node(&mut self, name: &str) -> bool {
+        self.take_sub_node(name).is_some()
     }
 
     /// Find `Property` from a `Node` by name.
@@ -125,15 +289,29 @@ impl Node {
     /// // Find the property from the node
     /// let prop = node.find_property("prop").unwrap();
     ///
-    /// assert_eq!(prop.lock().unwrap().value, vec![0u8, 0u8, 0u8, 42u8]);
+    /// assert_eq!(prop.value, vec![0u8, 0u8, 0u8, 42u8]);
     /// ```
-    pub fn find_property(&self, name: &str) -> Option<Arc<Mutex<Property>>> {
-        for prop in &self.properties {
-            if name == prop.lock().unwrap().name {
-                return Some(prop.clone());
-            }
-        }
-        None
+    pub fn find_property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|prop| prop.name == name)
+    }
+
+    /// Find `Property` from a `Node` by name for modification.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_u32("prop", 42));
+    ///
+    /// node.find_property_mut("prop").unwrap().value = vec![0u8, 0u8, 0u8, 1u8];
+    ///
+    /// assert_eq!(node.find_property("prop").unwrap().value, vec![0u8, 0u8, 0u8, 1u8]);
+    /// ```
+    pub fn find_property_mut(&mut self, name: &str) -> Option<&mut Property> {
+        self.properties.iter_mut().find(|prop| prop.name == name)
     }
 
     /// Find sub node from a `Node` by name.
@@ -155,15 +333,32 @@ impl Node {
     /// // Find the sub node from the node
     /// let sub_node = node.find_subnode_by_name("subnode").unwrap();
     ///
-    /// assert_eq!(sub_node.lock().unwrap().name, "subnode");
+    /// assert_eq!(sub_node.name, "subnode");
     /// ```
-    pub fn find_subnode_by_name(&self, name: &str) -> Option<Arc<Mutex<Node>>> {
-        for sub_node in &self.sub_nodes {
-            if sub_node.lock().unwrap().name == name {
-                return Some(sub_node.clone());
-            }
-        }
-        None
+    pub fn find_subnode_by_name(&self, name: &str) -> Option<&Node> {
+        self.sub_nodes.iter().find(|sub_node| sub_node.name == name)
+    }
+
+    /// Find sub node from a `Node` by name for modification.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    /// use devicetree_tool::Property;
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_sub_node(Node::new("subnode"));
+    ///
+    /// let sub_node = node.find_subnode_by_name_mut("subnode").unwrap();
+    /// sub_node.add_property(Property::new_empty("prop"));
+    ///
+    /// assert_eq!(node.sub_nodes[0].properties.len(), 1);
+    /// ```
+    pub fn find_subnode_by_name_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.sub_nodes
+            .iter_mut()
+            .find(|sub_node| sub_node.name == name)
     }
 
     /// Find sub node from a `Node` by label.
@@ -185,16 +380,14 @@ impl Node {
     /// // Find the sub node from the node
     /// let sub_node = node.find_subnode_by_label("label").unwrap();
     ///
-    /// assert_eq!(sub_node.lock().unwrap().name, "subnode");
+    /// assert_eq!(sub_node.name, "subnode");
     /// ```
-    pub fn find_subnode_by_label(&self, label: &str) -> Option<Arc<Mutex<Node>>> {
+    pub fn find_subnode_by_label(&self, label: &str) -> Option<&Node> {
         for sub_node in &self.sub_nodes {
-            if let Some(sub_node_label) = &sub_node.lock().unwrap().label {
-                if sub_node_label == label {
-                    return Some(sub_node.clone());
-                }
+            if sub_node.label.as_deref() == Some(label) {
+                return Some(sub_node);
             }
-            let sub_node_with_label = sub_node.lock().unwrap().find_subnode_by_label(label);
+            let sub_node_with_label = sub_node.find_subnode_by_label(label);
             if sub_node_with_label.is_some() {
                 return sub_node_with_label;
             }
@@ -226,20 +419,17 @@ impl Node {
     /// // Find the layer-3 sub node
     /// let sub_node = node_layer_1.find_subnode_by_path(vec!["node_layer_2", "node_layer_3"]).unwrap();
     ///
-    /// assert_eq!(sub_node.lock().unwrap().name, "node_layer_3");
+    /// assert_eq!(sub_node.name, "node_layer_3");
     /// ```
-    pub fn find_subnode_by_path(&self, path: Vec<&str>) -> Option<Arc<Mutex<Node>>> {
+    pub fn find_subnode_by_path(&self, path: Vec<&str>) -> Option<&Node> {
         for sub_node in &self.sub_nodes {
-            if sub_node.lock().unwrap().name == path[0] {
+            if sub_node.name == path[0] {
                 if path.len() == 1 {
                     // Found the matching node
-                    return Some(sub_node.clone());
+                    return Some(sub_node);
                 } else {
                     // There are more to match
-                    let sub_node_with_path = sub_node
-                        .lock()
-                        .unwrap()
-                        .find_subnode_by_path(path[1..].to_vec());
+                    let sub_node_with_path = sub_node.find_subnode_by_path(path[1..].to_vec());
                     if sub_node_with_path.is_some() {
                         return sub_node_with_path;
                     }
@@ -248,6 +438,217 @@ impl Node {
         }
         None
     }
+
+    /// Get the name of the `Node` without the unit address.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Node;
+    ///
+    /// assert_eq!(Node::new("serial@9000000").base_name(), "serial");
+    /// assert_eq!(Node::new("chosen").base_name(), "chosen");
+    /// ```
+    pub fn base_name(&self) -> &str {
+        self.name
+            .split_once('@')
+            .map_or(self.name.as_str(), |(base, _)| base)
+    }
+
+    /// Get the phandle of the `Node` from the "phandle" property, or the
+    /// legacy "linux,phandle" property.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("intc");
+    /// assert_eq!(node.phandle(), None);
+    ///
+    /// node.add_property(Property::new_u32("phandle", 1));
+    /// assert_eq!(node.phandle(), Some(1));
+    /// ```
+    pub fn phandle(&self) -> Option<u32> {
+        let prop = self
+            .find_property("phandle")
+            .or_else(|| self.find_property("linux,phandle"))?;
+        Some(u32::from_be_bytes(
+            prop.value.get(0..4)?.try_into().unwrap(),
+        ))
+    }
+
+    // Find a property that must exist, like the property readers of Linux.
+    fn read_property(&self, name: &str) -> Result<&Property, DeviceTreeError> {
+        self.find_property(name)
+            .ok_or_else(|| DeviceTreeError::PropertyNotFound(String::from(name)))
+    }
+
+    /// Count the elements of a given size in a property, like
+    /// `of_property_count_elems_of_size` of Linux.
+    ///
+    /// It fails if the property doesn't exist or the length of the value isn't
+    /// a multiple of the size.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_u32s("reg", vec![0, 0x9000000, 0, 0x1000]));
+    ///
+    /// assert_eq!(node.count_elems_of_size("reg", 4), Ok(4));
+    /// assert_eq!(node.count_elems_of_size("reg", 16), Ok(1));
+    /// assert!(node.count_elems_of_size("reg", 3).is_err());
+    /// ```
+    pub fn count_elems_of_size(&self, name: &str, size: usize) -> Result<usize, DeviceTreeError> {
+        let prop = self.read_property(name)?;
+        if size == 0 || !prop.value.len().is_multiple_of(size) {
+            return Err(DeviceTreeError::InvalidPropertyValue(String::from(name)));
+        }
+        Ok(prop.value.len() / size)
+    }
+
+    /// Read the first `count` cells of a property, like
+    /// `of_property_read_u32_array` of Linux.
+    ///
+    /// It fails if the property doesn't exist or has less than `count` cells.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_u32s("reg", vec![0, 0x9000000, 0, 0x1000]));
+    ///
+    /// assert_eq!(node.read_u32_array("reg", 2), Ok(vec![0, 0x9000000]));
+    /// assert!(node.read_u32_array("reg", 5).is_err());
+    /// ```
+    pub fn read_u32_array(&self, name: &str, count: usize) -> Result<Vec<u32>, DeviceTreeError> {
+        let prop = self.read_property(name)?;
+        if prop.value.is_empty() || prop.value.len() < count * 4 {
+            return Err(DeviceTreeError::InvalidPropertyValue(String::from(name)));
+        }
+        Ok(prop.value[..(count * 4)]
+            .chunks(4)
+            .map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+            .collect())
+    }
+
+    /// Read the first cell of a property, like `of_property_read_u32` of
+    /// Linux.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_u32("#address-cells", 2));
+    ///
+    /// assert_eq!(node.read_u32("#address-cells"), Ok(2));
+    /// assert!(node.read_u32("#size-cells").is_err());
+    /// ```
+    pub fn read_u32(&self, name: &str) -> Result<u32, DeviceTreeError> {
+        Ok(self.read_u32_array(name, 1)?[0])
+    }
+
+    /// Read a string from a string list property by index, like
+    /// `of_property_read_string_index` of Linux.
+    ///
+    /// It fails if the property doesn't exist, isn't a string list or has no
+    /// string at the index.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_strs("clock-names", vec!["uartclk", "apb_pclk"]));
+    ///
+    /// assert_eq!(node.read_string_index("clock-names", 1), Ok("apb_pclk"));
+    /// assert!(node.read_string_index("clock-names", 2).is_err());
+    /// ```
+    pub fn read_string_index(&self, name: &str, index: usize) -> Result<&str, DeviceTreeError> {
+        let invalid = || DeviceTreeError::InvalidPropertyValue(String::from(name));
+        let prop = self.read_property(name)?;
+        let strings = match prop.value.split_last() {
+            Some((0, strings)) => strings,
+            _ => return Err(invalid()),
+        };
+        let string = strings.split(|c| *c == 0).nth(index).ok_or_else(invalid)?;
+        std::str::from_utf8(string).map_err(|_| invalid())
+    }
+
+    /// Read the first string of a property, like `of_property_read_string` of
+    /// Linux.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_str("device_type", "memory"));
+    ///
+    /// assert_eq!(node.read_string("device_type"), Ok("memory"));
+    /// ```
+    pub fn read_string(&self, name: &str) -> Result<&str, DeviceTreeError> {
+        self.read_string_index(name, 0)
+    }
+
+    /// Check if a string is in the "compatible" property, like
+    /// `of_device_is_compatible` of Linux. The strings are compared ignoring
+    /// the ASCII case.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("node");
+    /// node.add_property(Property::new_strs("compatible", vec!["arm,pl011", "arm,primecell"]));
+    ///
+    /// assert!(node.is_compatible("arm,primecell"));
+    /// assert!(!node.is_compatible("arm,pl061"));
+    /// ```
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible_index(compatible).is_some()
+    }
+
+    // Get the index of a string in the "compatible" property.
+    pub(crate) fn compatible_index(&self, compatible: &str) -> Option<usize> {
+        (0..)
+            .map_while(|index| self.read_string_index("compatible", index).ok())
+            .position(|s| s.eq_ignore_ascii_case(compatible))
+    }
+
+    /// Check if the device of the `Node` is available, like
+    /// `of_device_is_available` of Linux.
+    ///
+    /// A device is available if the "status" property is "okay" or "ok", or
+    /// doesn't exist.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{Node, Property};
+    ///
+    /// let mut node = Node::new("node");
+    /// assert!(node.is_available());
+    ///
+    /// node.set_property(Property::new_str("status", "disabled"));
+    /// assert!(!node.is_available());
+    /// ```
+    pub fn is_available(&self) -> bool {
+        match self.find_property("status") {
+            Some(_) => matches!(self.read_string("status"), Ok("okay") | Ok("ok")),
+            None => true,
+        }
+    }
 }
 
 impl std::fmt::Display for Node {
@@ -258,10 +659,158 @@ impl std::fmt::Display for Node {
     }
 }
 
+/// A node of a `DeviceTree` together with the tree it lives in.
+///
+/// Unlike a bare `Node`, a `NodeRef` knows its position in the tree, so the
+/// parent, the sub nodes and the full path of the node can be queried. The
+/// fields of the node are accessed through `Deref`.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::DeviceTree;
+/// use devicetree_tool::Node;
+///
+/// let mut root = Node::new("");
+/// let mut soc = Node::new("soc");
+/// soc.add_sub_node(Node::new_with_label("serial@9000000", "uart0"));
+/// root.add_sub_node(soc);
+/// let tree = DeviceTree::new(vec![], root);
+///
+/// let uart = tree.node_ref(tree.find_node_by_label("uart0").unwrap());
+///
+/// assert_eq!(uart.name, "serial@9000000");
+/// assert_eq!(uart.path(), "/soc/serial@9000000");
+/// assert_eq!(uart.parent().unwrap().path(), "/soc");
+/// ```
+#[derive(Clone, Copy)]
+pub struct NodeRef<'a> {
+    tree: &'a DeviceTree,
+    id: NodeId,
+}
+
+impl<'a> NodeRef<'a> {
+    pub(crate) fn new(tree: &'a DeviceTree, id: NodeId) -> Self {
+        NodeRef { tree, id }
+    }
+
+    /// Get the `NodeId` of the node.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Get the tree that the node lives in.
+    pub fn tree(&self) -> &'a DeviceTree {
+        self.tree
+    }
+
+    /// Get the parent node, the root node has no parent.
+    pub fn parent(&self) -> Option<NodeRef<'a>> {
+        let parent = self.tree.parent(self.id)?;
+        Some(NodeRef::new(self.tree, parent))
+    }
+
+    /// Get the sub nodes in order.
+    pub fn children(&self) -> impl Iterator<Item = NodeRef<'a>> + 'a {
+        let tree = self.tree;
+        tree.children(self.id)
+            .iter()
+            .map(move |id| NodeRef::new(tree, *id))
+    }
+
+    /// Get the full path of the node, like `/soc/serial@9000000`.
+    pub fn path(&self) -> String {
+        self.tree.path_of(self.id)
+    }
+}
+
+impl std::ops::Deref for NodeRef<'_> {
+    type Target = Node;
+
+    fn deref(&self) -> &Node {
+        self.tree.node(self.id)
+    }
+}
+
+impl std::fmt::Debug for NodeRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("NodeRef")
+            .field("id", &self.id)
+            .field("path", &self.path())
+            .finish()
+    }
+}
+
+/// A node of a `DeviceTree` borrowed for modification, returned by
+/// `DeviceTree::node_mut()`.
+///
+/// The fields of the node are accessed through `Deref` and `DerefMut`. The
+/// sub nodes put in `sub_nodes` are moved into the tree after the other sub
+/// nodes of the node when the `NodeMut` is dropped, so they are not lost.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::DeviceTree;
+/// use devicetree_tool::Node;
+///
+/// let mut tree = DeviceTree::new(vec![], Node::new(""));
+///
+/// let mut root = tree.node_mut(tree.root());
+/// root.name = String::from("/");
+/// root.add_sub_node(Node::new("chosen"));
+/// drop(root);
+///
+/// assert!(tree.find_node_by_path("/chosen").is_some());
+/// ```
+pub struct NodeMut<'a> {
+    tree: &'a mut DeviceTree,
+    id: NodeId,
+}
+
+impl<'a> NodeMut<'a> {
+    pub(crate) fn new(tree: &'a mut DeviceTree, id: NodeId) -> Self {
+        NodeMut { tree, id }
+    }
+
+    /// Get the `NodeId` of the node.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+}
+
+impl std::ops::Deref for NodeMut<'_> {
+    type Target = Node;
+
+    fn deref(&self) -> &Node {
+        self.tree.node(self.id)
+    }
+}
+
+impl std::ops::DerefMut for NodeMut<'_> {
+    fn deref_mut(&mut self) -> &mut Node {
+        self.tree.node_mut_unchecked(self.id)
+    }
+}
+
+impl Drop for NodeMut<'_> {
+    fn drop(&mut self) {
+        self.tree.adopt_sub_nodes(self.id);
+    }
+}
+
+impl std::fmt::Debug for NodeMut<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("NodeMut")
+            .field("id", &self.id)
+            .field("path", &self.tree.path_of(self.id))
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::property::Property;
 
     #[test]
     fn test_node_empty() {
@@ -295,6 +844,53 @@ mod tests {
         assert_eq!(node.properties.len(), 2);
     }
 
+    #[test]
+    fn test_node_set_property() {
+        let mut node = Node::new("node");
+        node.add_property(Property::new_u32("prop0", 0));
+        node.add_property(Property::new_u32("prop1", 1));
+
+        // Replacing keeps the position of the property
+        let old = node.set_property(Property::new_u32("prop0", 42)).unwrap();
+        assert_eq!(old.value, vec![0u8, 0u8, 0u8, 0u8]);
+        assert_eq!(node.properties.len(), 2);
+        assert_eq!(node.properties[0].value, vec![0u8, 0u8, 0u8, 42u8]);
+
+        assert!(node.set_property(Property::new_empty("prop2")).is_none());
+        assert_eq!(node.properties.len(), 3);
+        assert_eq!(node.properties[2].name, "prop2");
+    }
+
+    #[test]
+    fn test_node_remove_sub_nodes() {
+        let mut node = Node::new("node");
+        node.add_sub_node(Node::new("sub_node_1"));
+        node.insert_sub_node_at(0, Node::new("sub_node_0"));
+        node.insert_sub_node_at(10, Node::new("sub_node_2"));
+        assert_eq!(node.sub_nodes[0].name, "sub_node_0");
+        assert_eq!(node.sub_nodes[2].name, "sub_node_2");
+
+        assert!(node.remove_sub_node("sub_node_1"));
+        assert!(!node.remove_sub_node("sub_node_1"));
+        let sub_node = node.take_sub_node("sub_node_0").unwrap();
+        assert_eq!(sub_node.name, "sub_node_0");
+        assert_eq!(node.sub_nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_node_name_syntax() {
+        assert!(Node::is_valid_name("node"));
+        assert!(Node::is_valid_name("memory@40000000"));
+        assert!(Node::is_valid_name("pci@1,0"));
+        assert!(Node::is_valid_name("regulator-3v3_main+x.y"));
+        assert!(!Node::is_valid_name(""));
+        assert!(!Node::is_valid_name("@0"));
+        assert!(!Node::is_valid_name("node@"));
+        assert!(!Node::is_valid_name("node@0@1"));
+        assert!(!Node::is_valid_name("no de"));
+        assert!(!Node::is_valid_name("a_very_long_node_name_over_31_chr"));
+    }
+
     #[test]
     fn test_property_print() {
         let mut node = Node::new("node");
@@ -325,6 +921,6 @@ mod tests {
         let sub_node = node_layer_1
             .find_subnode_by_path(vec!["node_layer_2", "node_layer_3"])
             .unwrap();
-        assert_eq!(sub_node.lock().unwrap().name, "node_layer_3");
+        assert_eq!(sub_node.name, "node_layer_3");
     }
 }
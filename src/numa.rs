@@ -0,0 +1,506 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::{Node, NodeId};
+use crate::property::Property;
+
+// The distance of a NUMA node to itself, and the default distance to the
+// others, like `LOCAL_DISTANCE` and `REMOTE_DISTANCE` of Linux
+const LOCAL_DISTANCE: u32 = 10;
+const REMOTE_DISTANCE: u32 = 20;
+
+/// A NUMA node and the device tree nodes with its "numa-node-id".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NumaNode {
+    /// The "numa-node-id"
+    pub id: u32,
+    /// The nodes with device_type "cpu"
+    pub cpus: Vec<NodeId>,
+    /// The nodes with device_type "memory"
+    pub memory: Vec<NodeId>,
+    /// The other nodes, like PCI host bridges
+    pub devices: Vec<NodeId>,
+}
+
+impl NumaNode {
+    /// Create a NUMA node without members.
+    pub fn new(id: u32) -> Self {
+        NumaNode {
+            id,
+            ..Default::default()
+        }
+    }
+
+    fn members(&self) -> impl Iterator<Item = &NodeId> {
+        self.cpus.iter().chain(&self.memory).chain(&self.devices)
+    }
+}
+
+/// The NUMA description of a device tree: the "numa-node-id" of the nodes
+/// and the "distance-matrix" of "/distance-map".
+///
+/// It is read with `DeviceTree::numa_topology`, and written with
+/// `DeviceTree::set_numa_topology`.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, NumaNode, NumaTopology};
+///
+/// let dts = r#"/dts-v1/;
+/// / {
+///     #address-cells = <1>;
+///     #size-cells = <1>;
+///     cpus {
+///         #address-cells = <1>;
+///         #size-cells = <0>;
+///         cpu@0 {
+///             device_type = "cpu";
+///             reg = <0>;
+///         };
+///         cpu@1 {
+///             device_type = "cpu";
+///             reg = <1>;
+///         };
+///     };
+///     memory@40000000 {
+///         device_type = "memory";
+///         reg = <0x40000000 0x10000000>;
+///     };
+///     memory@50000000 {
+///         device_type = "memory";
+///         reg = <0x50000000 0x10000000>;
+///     };
+/// };"#;
+/// let mut tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+/// let node = |path| tree.find_node_by_path(path).unwrap();
+///
+/// let mut node0 = NumaNode::new(0);
+/// node0.cpus.push(node("/cpus/cpu@0"));
+/// node0.memory.push(node("/memory@40000000"));
+/// let mut node1 = NumaNode::new(1);
+/// node1.cpus.push(node("/cpus/cpu@1"));
+/// node1.memory.push(node("/memory@50000000"));
+/// let topology = NumaTopology {
+///     nodes: vec![node0, node1],
+///     distances: vec![vec![10, 20], vec![20, 10]],
+/// };
+///
+/// tree.set_numa_topology(&topology).unwrap();
+/// assert_eq!(tree.numa_topology(), Ok(topology));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NumaTopology {
+    /// The NUMA nodes, in the order of the IDs
+    pub nodes: Vec<NumaNode>,
+    /// The distances between the NUMA nodes, in the order of `nodes`
+    pub distances: Vec<Vec<u32>>,
+}
+
+impl NumaTopology {
+    /// Get the distance between two NUMA nodes by their IDs.
+    pub fn distance(&self, from: u32, to: u32) -> Option<u32> {
+        let from = self.nodes.iter().position(|node| node.id == from)?;
+        let to = self.nodes.iter().position(|node| node.id == to)?;
+        self.distances.get(from)?.get(to).copied()
+    }
+
+    fn invalid(message: String) -> DeviceTreeError {
+        DeviceTreeError::InvalidTopology(message)
+    }
+
+    // Check a distance, like `of_numa_parse_distance_map_v1` of Linux.
+    fn check_distance(from: u32, to: u32, distance: u32) -> Result<(), DeviceTreeError> {
+        if (from == to && distance != LOCAL_DISTANCE) || (from != to && distance <= LOCAL_DISTANCE)
+        {
+            return Err(NumaTopology::invalid(format!(
+                "distance {distance} from node {from} to node {to}"
+            )));
+        }
+        Ok(())
+    }
+
+    // Check that the topology can be written and read back: the members are
+    // in the lists of their device_type, every CPU and memory node is a
+    // member, and the distance matrix is symmetric.
+    fn validate(&self, tree: &DeviceTree) -> Result<(), DeviceTreeError> {
+        let mut members = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if self.nodes[..i].iter().any(|other| other.id == node.id) {
+                return Err(NumaTopology::invalid(format!("duplicate node {}", node.id)));
+            }
+            for id in node.members() {
+                if !tree.contains(*id) || members.contains(id) {
+                    return Err(NumaTopology::invalid(format!(
+                        "node {} has an invalid member",
+                        node.id
+                    )));
+                }
+                members.push(*id);
+            }
+            let lists = [
+                (&node.cpus, Some("cpu")),
+                (&node.memory, Some("memory")),
+                (&node.devices, None),
+            ];
+            for (list, expected) in lists {
+                for id in list {
+                    let device_type = NumaTopology::device_type(tree, *id);
+                    if device_type != expected {
+                        return Err(NumaTopology::invalid(format!(
+                            "{} in the wrong list of node {}",
+                            tree.path_of(*id),
+                            node.id
+                        )));
+                    }
+                }
+            }
+        }
+        if self.distances.len() != self.nodes.len()
+            || self
+                .distances
+                .iter()
+                .any(|row| row.len() != self.nodes.len())
+        {
+            return Err(NumaTopology::invalid(String::from(
+                "distance matrix size mismatch",
+            )));
+        }
+        for (i, from) in self.nodes.iter().enumerate() {
+            for (j, to) in self.nodes.iter().enumerate() {
+                NumaTopology::check_distance(from.id, to.id, self.distances[i][j])?;
+                if self.distances[i][j] != self.distances[j][i] {
+                    return Err(NumaTopology::invalid(format!(
+                        "asymmetric distance between node {} and node {}",
+                        from.id, to.id
+                    )));
+                }
+            }
+        }
+        if !self.nodes.is_empty() {
+            for id in tree.subtree(tree.root()) {
+                if NumaTopology::device_type(tree, id).is_some() && !members.contains(&id) {
+                    return Err(NumaTopology::invalid(format!(
+                        "no node for {}",
+                        tree.path_of(id)
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Get the device_type of a node if it is "cpu" or "memory".
+    fn device_type(tree: &DeviceTree, id: NodeId) -> Option<&'static str> {
+        match tree.node(id).read_string("device_type") {
+            Ok("cpu") => Some("cpu"),
+            Ok("memory") => Some("memory"),
+            _ => None,
+        }
+    }
+
+    // Read the NUMA description of a tree. Every CPU and memory node must
+    // have "numa-node-id" if any node has it. The distances missing from
+    // "/distance-map" are taken from the reverse direction, or are the
+    // default remote distance.
+    pub(crate) fn read(tree: &DeviceTree) -> Result<NumaTopology, DeviceTreeError> {
+        let mut topology = NumaTopology::default();
+        let mut missing = vec![];
+        for id in tree.subtree(tree.root()) {
+            let node = tree.node(id);
+            let device_type = node.read_string("device_type").ok();
+            let Ok(numa_id) = node.read_u32("numa-node-id") else {
+                if matches!(device_type, Some("cpu") | Some("memory")) {
+                    missing.push(id);
+                }
+                continue;
+            };
+            let index = match topology.nodes.iter().position(|n| n.id == numa_id) {
+                Some(index) => index,
+                None => {
+                    topology.nodes.push(NumaNode::new(numa_id));
+                    topology.nodes.len() - 1
+                }
+            };
+            let numa_node = &mut topology.nodes[index];
+            match device_type {
+                Some("cpu") => numa_node.cpus.push(id),
+                Some("memory") => numa_node.memory.push(id),
+                _ => numa_node.devices.push(id),
+            }
+        }
+        let map = tree.find_child_by_name(tree.root(), "distance-map");
+        if topology.nodes.is_empty() && map.is_none() {
+            return Ok(topology);
+        }
+        if let Some(id) = missing.first() {
+            return Err(NumaTopology::invalid(format!(
+                "no numa-node-id in {}",
+                tree.path_of(*id)
+            )));
+        }
+        topology.nodes.sort_by_key(|node| node.id);
+
+        // The given distances, and then the defaults
+        let n = topology.nodes.len();
+        let mut distances = vec![vec![None; n]; n];
+        if let Some(map) = map {
+            if !tree.node(map).is_compatible("numa-distance-map-v1") {
+                return Err(NumaTopology::invalid(String::from(
+                    "/distance-map is not numa-distance-map-v1",
+                )));
+            }
+            let count = tree.node(map).count_elems_of_size("distance-matrix", 4)?;
+            if count % 3 != 0 {
+                return Err(DeviceTreeError::CellMismatch(String::from(
+                    "/distance-map:distance-matrix",
+                )));
+            }
+            let matrix = tree.node(map).read_u32_array("distance-matrix", count)?;
+            for entry in matrix.chunks(3) {
+                let (from, to, distance) = (entry[0], entry[1], entry[2]);
+                NumaTopology::check_distance(from, to, distance)?;
+                let index = |id: u32| {
+                    topology
+                        .nodes
+                        .iter()
+                        .position(|node| node.id == id)
+                        .ok_or_else(|| NumaTopology::invalid(format!("no node {id}")))
+                };
+                let (i, j) = (index(from)?, index(to)?);
+                distances[i][j] = Some(distance);
+            }
+        }
+        let mut filled = vec![vec![0; n]; n];
+        for (i, row) in filled.iter_mut().enumerate() {
+            for (j, distance) in row.iter_mut().enumerate() {
+                *distance = match (distances[i][j], distances[j][i]) {
+                    (Some(a), Some(b)) if a != b => {
+                        return Err(NumaTopology::invalid(format!(
+                            "asymmetric distance between node {} and node {}",
+                            topology.nodes[i].id, topology.nodes[j].id
+                        )));
+                    }
+                    (Some(a), _) | (None, Some(a)) => a,
+                    (None, None) if i == j => LOCAL_DISTANCE,
+                    (None, None) => REMOTE_DISTANCE,
+                };
+            }
+        }
+        topology.distances = filled;
+        Ok(topology)
+    }
+
+    // Write "numa-node-id" to the members of the NUMA nodes, removing it
+    // from the other nodes, and replace "/distance-map".
+    pub(crate) fn write(&self, tree: &mut DeviceTree) -> Result<(), DeviceTreeError> {
+        self.validate(tree)?;
+        for id in tree.subtree(tree.root()) {
+            if tree.node(id).find_property("numa-node-id").is_some() {
+                tree.node_mut(id).remove_property("numa-node-id");
+            }
+        }
+        for node in &self.nodes {
+            for id in node.members() {
+                tree.node_mut(*id)
+                    .set_property(Property::new_u32("numa-node-id", node.id));
+            }
+        }
+
+        if let Some(old) = tree.find_child_by_name(tree.root(), "distance-map") {
+            tree.take_node(old)?;
+        }
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+        let mut matrix = vec![];
+        for (i, from) in self.nodes.iter().enumerate() {
+            for (j, to) in self.nodes.iter().enumerate() {
+                matrix.extend([from.id, to.id, self.distances[i][j]]);
+            }
+        }
+        let mut map = Node::new("distance-map");
+        map.add_property(Property::new_str("compatible", "numa-distance-map-v1"));
+        map.add_property(Property::new_u32s("distance-matrix", matrix));
+        tree.add_sub_node(tree.root(), map);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numa_tree(distance_matrix: &str) -> DeviceTree {
+        let dts = format!(
+            r#"/dts-v1/;
+/ {{
+	#address-cells = <2>;
+	#size-cells = <2>;
+
+	cpus {{
+		#address-cells = <1>;
+		#size-cells = <0>;
+
+		cpu@0 {{
+			device_type = "cpu";
+			reg = <0>;
+			numa-node-id = <0>;
+		}};
+
+		cpu@1 {{
+			device_type = "cpu";
+			reg = <1>;
+			numa-node-id = <1>;
+		}};
+
+		cpu@2 {{
+			device_type = "cpu";
+			reg = <2>;
+			numa-node-id = <2>;
+		}};
+	}};
+
+	memory@40000000 {{
+		device_type = "memory";
+		reg = <0x0 0x40000000 0x0 0x40000000>;
+		numa-node-id = <0>;
+	}};
+
+	memory@80000000 {{
+		device_type = "memory";
+		reg = <0x0 0x80000000 0x0 0x40000000>;
+		numa-node-id = <1>;
+	}};
+
+	memory@c0000000 {{
+		device_type = "memory";
+		reg = <0x0 0xc0000000 0x0 0x40000000>;
+		numa-node-id = <2>;
+	}};
+
+	pcie@10000000 {{
+		device_type = "pci";
+		numa-node-id = <1>;
+	}};
+
+	distance-map {{
+		compatible = "numa-distance-map-v1";
+		distance-matrix = {distance_matrix};
+	}};
+}};
+"#
+        );
+        DeviceTree::from_dts_bytes(dts.as_bytes())
+    }
+
+    #[test]
+    fn test_numa_read() {
+        let tree = numa_tree("<0 0 10>, <0 1 20>, <1 0 20>, <1 1 10>, <0 2 40>, <1 2 30>");
+        let topology = tree.numa_topology().unwrap();
+        let pcie = tree.find_node_by_path("/pcie@10000000").unwrap();
+
+        let ids: Vec<u32> = topology.nodes.iter().map(|node| node.id).collect();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(
+            topology.nodes[1].memory,
+            vec![tree.find_node_by_path("/memory@80000000").unwrap()]
+        );
+        assert_eq!(topology.nodes[1].devices, vec![pcie]);
+        // The reverse distances and the local distance of node 2 are filled
+        assert_eq!(
+            topology.distances,
+            vec![vec![10, 20, 40], vec![20, 10, 30], vec![40, 30, 10]]
+        );
+        assert_eq!(topology.distance(2, 0), Some(40));
+
+        // Writing and reading back keeps the topology
+        let mut tree = tree;
+        tree.set_numa_topology(&topology).unwrap();
+        assert_eq!(tree.numa_topology(), Ok(topology));
+    }
+
+    #[test]
+    fn test_numa_validate() {
+        let tree = numa_tree("<0 1 20>, <1 0 30>");
+        assert_eq!(
+            tree.numa_topology(),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "asymmetric distance between node 0 and node 1"
+            )))
+        );
+        let tree = numa_tree("<0 1 10>");
+        assert_eq!(
+            tree.numa_topology(),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "distance 10 from node 0 to node 1"
+            )))
+        );
+        let tree = numa_tree("<0 3 20>");
+        assert_eq!(
+            tree.numa_topology(),
+            Err(DeviceTreeError::InvalidTopology(String::from("no node 3")))
+        );
+
+        let mut tree = numa_tree("<0 1 20>");
+        let cpu = tree.find_node_by_path("/cpus/cpu@2").unwrap();
+        tree.node_mut(cpu).remove_property("numa-node-id");
+        assert_eq!(
+            tree.numa_topology(),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "no numa-node-id in /cpus/cpu@2"
+            )))
+        );
+
+        let mut topology = NumaTopology {
+            nodes: vec![NumaNode::new(0), NumaNode::new(1)],
+            distances: vec![vec![10, 20], vec![30, 10]],
+        };
+        assert_eq!(
+            tree.set_numa_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "asymmetric distance between node 0 and node 1"
+            )))
+        );
+        topology.distances[1][0] = 20;
+        topology.nodes[0].cpus.push(cpu);
+        topology.nodes[1].cpus.push(cpu);
+        assert_eq!(
+            tree.set_numa_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "node 1 has an invalid member"
+            )))
+        );
+
+        // Every CPU and memory node must be in the list of its device_type
+        let cpu1 = tree.find_node_by_path("/cpus/cpu@1").unwrap();
+        let memory = tree.find_node_by_path("/memory@40000000").unwrap();
+        let mut topology = NumaTopology {
+            nodes: vec![NumaNode::new(0)],
+            distances: vec![vec![10]],
+        };
+        topology.nodes[0]
+            .cpus
+            .push(tree.find_node_by_path("/cpus/cpu@0").unwrap());
+        assert_eq!(
+            tree.set_numa_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "no node for /cpus/cpu@1"
+            )))
+        );
+        topology.nodes[0].cpus.push(memory);
+        assert_eq!(
+            tree.set_numa_topology(&topology),
+            Err(DeviceTreeError::InvalidTopology(String::from(
+                "/memory@40000000 in the wrong list of node 0"
+            )))
+        );
+        assert_eq!(tree.node(cpu1).read_u32("numa-node-id"), Ok(1));
+
+        // Without NUMA nodes, the NUMA description is removed
+        tree.set_numa_topology(&NumaTopology::default()).unwrap();
+        assert_eq!(tree.numa_topology(), Ok(NumaTopology::default()));
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::node::Node;
+
+/// An entry of a device match table, like `struct of_device_id` of Linux.
+///
+/// Each of `name`, `device_type` and `compatible` that is set must match the
+/// node. `data` is the driver data of the entry.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, Node, OfDeviceId, Property};
+///
+/// let mut root = Node::new("");
+/// let mut uart = Node::new("serial@9000000");
+/// uart.add_property(Property::new_strs("compatible", vec!["arm,pl011", "arm,primecell"]));
+/// root.add_sub_node(uart);
+/// let tree = DeviceTree::new(vec![], root);
+///
+/// let matches = [
+///     OfDeviceId::new("arm,primecell", "amba"),
+///     OfDeviceId::new("arm,pl011", "pl011"),
+/// ];
+///
+/// // The earlier compatible string of the node is the better match
+/// let uart = tree.find_compatible_node(None, None, "arm,pl011").unwrap();
+/// assert_eq!(tree.match_node(&matches, uart).unwrap().data, "pl011");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OfDeviceId<'a, T> {
+    pub name: Option<&'a str>,
+    pub device_type: Option<&'a str>,
+    pub compatible: Option<&'a str>,
+    pub data: T,
+}
+
+impl<'a, T> OfDeviceId<'a, T> {
+    /// Create an entry matching a compatible string.
+    pub fn new(compatible: &'a str, data: T) -> Self {
+        OfDeviceId {
+            name: None,
+            device_type: None,
+            compatible: Some(compatible),
+            data,
+        }
+    }
+
+    // Score how well the entry matches a node, 0 if it doesn't match.
+    pub(crate) fn score(&self, node: &Node) -> u32 {
+        match_score(node, self.compatible, self.device_type, self.name)
+    }
+}
+
+// Score how well a node matches, like `__of_device_is_compatible` of Linux.
+// Matching an earlier compatible string of the node scores higher, and
+// matching the device type or name adds to the score. The score is 0 if any
+// given condition doesn't match, or no condition is given.
+pub(crate) fn match_score(
+    node: &Node,
+    compatible: Option<&str>,
+    device_type: Option<&str>,
+    name: Option<&str>,
+) -> u32 {
+    let mut score = 0;
+    if let Some(compatible) = compatible.filter(|c| !c.is_empty()) {
+        match node.compatible_index(compatible) {
+            Some(index) => score = (i32::MAX as u32 / 2).saturating_sub((index as u32) << 2),
+            None => return 0,
+        }
+    }
+    if let Some(device_type) = device_type.filter(|t| !t.is_empty()) {
+        if node.read_string("device_type") != Ok(device_type) {
+            return 0;
+        }
+        score += 2;
+    }
+    if let Some(name) = name.filter(|n| !n.is_empty()) {
+        if node.base_name() != name {
+            return 0;
+        }
+        score += 1;
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devicetree::DeviceTree;
+    use crate::error::DeviceTreeError;
+    use crate::property::Property;
+
+    fn tree() -> DeviceTree {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        DeviceTree::from_dts_bytes(&dts)
+    }
+
+    #[test]
+    fn test_of_property_read() {
+        let tree = tree();
+        let uart = tree.node(tree.find_node_by_path("/pl011@9000000").unwrap());
+
+        assert_eq!(
+            uart.read_u32_array("reg", 4),
+            Ok(vec![0, 0x9000000, 0, 0x1000])
+        );
+        assert_eq!(uart.read_u32_array("reg", 0), Ok(vec![]));
+        assert_eq!(
+            uart.read_u32_array("reg", 5),
+            Err(DeviceTreeError::InvalidPropertyValue(String::from("reg")))
+        );
+        assert_eq!(
+            uart.read_u32("interrupts-extended"),
+            Err(DeviceTreeError::PropertyNotFound(String::from(
+                "interrupts-extended"
+            )))
+        );
+        assert_eq!(uart.count_elems_of_size("reg", 8), Ok(2));
+        assert_eq!(uart.count_elems_of_size("clock-names", 1), Ok(9));
+        assert!(uart.count_elems_of_size("reg", 0).is_err());
+
+        assert_eq!(uart.read_string("compatible"), Ok("arm,pl011"));
+        assert_eq!(uart.read_string_index("compatible", 1), Ok("arm,primecell"));
+        assert!(uart.read_string_index("compatible", 2).is_err());
+        assert!(uart.read_string("interrupts").is_err());
+
+        assert!(uart.is_compatible("ARM,PrimeCell"));
+        assert!(uart.is_available());
+    }
+
+    #[test]
+    fn test_of_find_compatible_node() {
+        let tree = tree();
+
+        let mut found = vec![];
+        let mut from = None;
+        while let Some(id) = tree.find_compatible_node(from, None, "arm,primecell") {
+            found.push(tree.path_of(id));
+            from = Some(id);
+        }
+        assert_eq!(
+            found,
+            vec!["/rtc@9010000", "/pl061@9020000", "/pl011@9000000"]
+        );
+
+        let cpu = tree.find_compatible_node(None, Some("cpu"), "arm,arm-v8");
+        assert_eq!(tree.path_of(cpu.unwrap()), "/cpus/cpu@0");
+        assert_eq!(
+            tree.find_compatible_node(None, Some("memory"), "arm,arm-v8"),
+            None
+        );
+
+        // The search goes back up to the next sibling of an ancestor
+        let mut root = Node::new("");
+        let mut a = Node::new("a");
+        let mut b = Node::new("b");
+        let mut c = Node::new("c");
+        c.add_property(Property::new_str("compatible", "test"));
+        b.add_sub_node(c);
+        a.add_sub_node(b);
+        a.add_property(Property::new_str("compatible", "test"));
+        root.add_sub_node(a);
+        let mut d = Node::new("d");
+        d.add_property(Property::new_str("compatible", "test"));
+        root.add_sub_node(d);
+        let tree = DeviceTree::new(vec![], root);
+
+        let mut found = vec![];
+        let mut from = None;
+        while let Some(id) = tree.find_compatible_node(from, None, "test") {
+            found.push(tree.path_of(id));
+            from = Some(id);
+        }
+        assert_eq!(found, vec!["/a", "/a/b/c", "/d"]);
+    }
+
+    #[test]
+    fn test_of_children() {
+        let mut tree = tree();
+        let cpus = tree.find_node_by_path("/cpus").unwrap();
+
+        let cpu0 = tree.get_child_by_name(cpus, "cpu").unwrap();
+        assert_eq!(tree.path_of(cpu0), "/cpus/cpu@0");
+        assert_eq!(tree.get_child_by_name(cpus, "cpu@0"), None);
+
+        let cpu1 = tree.find_node_by_path("/cpus/cpu@1").unwrap();
+        tree.node_mut(cpu1)
+            .set_property(crate::property::Property::new_str("status", "disabled"));
+
+        let mut available = vec![];
+        let mut prev = None;
+        while let Some(id) = tree.get_next_available_child(cpus, prev) {
+            available.push(tree.node(id).name.clone());
+            prev = Some(id);
+        }
+        assert_eq!(available, vec!["cpu@0", "cpu@2", "cpu@3"]);
+    }
+
+    #[test]
+    fn test_of_match_node() {
+        let tree = tree();
+        let cpu = tree.find_node_by_path("/cpus/cpu@2").unwrap();
+        let gic = tree.find_node_by_path("/intc").unwrap();
+
+        let matches = [
+            OfDeviceId {
+                name: Some("cpu"),
+                ..Default::default()
+            },
+            OfDeviceId {
+                device_type: Some("cpu"),
+                data: 1,
+                ..OfDeviceId::new("arm,arm-v8", 0)
+            },
+            OfDeviceId::new("arm,gic-v3", 2),
+            OfDeviceId {
+                name: Some("msic"),
+                data: 3,
+                ..OfDeviceId::new("arm,gic-v3", 0)
+            },
+        ];
+        assert_eq!(tree.match_node(&matches, cpu).unwrap().data, 1);
+        assert_eq!(tree.match_node(&matches, gic).unwrap().data, 2);
+        assert_eq!(tree.match_node(&matches[..1], gic), None);
+        assert_eq!(tree.match_node(&matches[..1], cpu).unwrap().data, 0);
+    }
+}
@@ -0,0 +1,875 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::{Node, NodeId};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A phandle reference with arguments, like `struct of_phandle_args` of
+/// Linux.
+///
+/// It is an entry of a property like `clocks = <&clk 1>, <&pll 0 2>;`, where
+/// `node` is the node the phandle refers to and `args` are the cells after
+/// the phandle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhandleArgs {
+    pub node: NodeId,
+    pub args: Vec<u32>,
+}
+
+// The number of argument cells following each phandle.
+pub(crate) enum CellCount<'a> {
+    // Read from a property of the node the phandle refers to
+    Property(&'a str),
+    // The same for all the phandles
+    Fixed(usize),
+}
+
+impl PhandleArgs {
+    fn mismatch(tree: &DeviceTree, id: NodeId, name: &str) -> DeviceTreeError {
+        DeviceTreeError::CellMismatch(format!("{}:{}", tree.path_of(id), name))
+    }
+
+    // Get the cells of a property.
+    fn cells(tree: &DeviceTree, id: NodeId, name: &str) -> Result<Vec<u32>, DeviceTreeError> {
+        let prop = tree
+            .node(id)
+            .find_property(name)
+            .ok_or_else(|| DeviceTreeError::PropertyNotFound(String::from(name)))?;
+        if !prop.value.len().is_multiple_of(4) {
+            return Err(PhandleArgs::mismatch(tree, id, name));
+        }
+        Ok(prop
+            .value
+            .chunks(4)
+            .map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+            .collect())
+    }
+
+    // Get the value of a `#*-cells` property of a node.
+    fn cell_count(tree: &DeviceTree, id: NodeId, name: &str) -> Result<usize, DeviceTreeError> {
+        tree.node(id)
+            .read_u32(name)
+            .map(|count| count as usize)
+            .map_err(|_| PhandleArgs::mismatch(tree, id, name))
+    }
+
+    // Parse all the entries of a phandle list property, like
+    // `of_phandle_iterator` of Linux. An entry with phandle 0 is a
+    // placeholder without arguments, and is returned as `None`.
+    pub(crate) fn parse_list(
+        tree: &DeviceTree,
+        id: NodeId,
+        list_name: &str,
+        cell_count: &CellCount,
+    ) -> Result<Vec<Option<PhandleArgs>>, DeviceTreeError> {
+        let cells = PhandleArgs::cells(tree, id, list_name)?;
+        let mut list = vec![];
+        let mut pos = 0;
+        while pos < cells.len() {
+            let phandle = cells[pos];
+            pos += 1;
+            if phandle == 0 {
+                list.push(None);
+                continue;
+            }
+            let node = tree
+                .find_node_by_phandle(phandle)
+                .ok_or(DeviceTreeError::DanglingPhandle(phandle))?;
+            let count = match cell_count {
+                CellCount::Property(name) => PhandleArgs::cell_count(tree, node, name)?,
+                CellCount::Fixed(count) => *count,
+            };
+            if pos + count > cells.len() {
+                return Err(PhandleArgs::mismatch(tree, id, list_name));
+            }
+            list.push(Some(PhandleArgs {
+                node,
+                args: cells[pos..(pos + count)].to_vec(),
+            }));
+            pos += count;
+        }
+        Ok(list)
+    }
+
+    // Translate the reference through the `<stem>-map` properties of the nodes
+    // it refers to, like `of_parse_phandle_with_args_map` of Linux, until a
+    // node without the map is reached.
+    pub(crate) fn map(self, tree: &DeviceTree, stem_name: &str) -> Result<Self, DeviceTreeError> {
+        let cells_name = format!("#{stem_name}-cells");
+        let map_name = format!("{stem_name}-map");
+        let mask_name = format!("{stem_name}-map-mask");
+        let pass_name = format!("{stem_name}-map-pass-thru");
+
+        let mut current = self;
+        let mut visited: Vec<PhandleArgs> = vec![];
+        while tree.node(current.node).find_property(&map_name).is_some() {
+            // A map referring back to an entry already seen never ends
+            if visited.contains(&current) {
+                return Err(PhandleArgs::mismatch(tree, current.node, &map_name));
+            }
+
+            let node = current.node;
+            let count = PhandleArgs::cell_count(tree, node, &cells_name)?;
+            let read_or = |name: &str, default: u32| match tree.node(node).find_property(name) {
+                Some(_) => tree
+                    .node(node)
+                    .read_u32_array(name, count)
+                    .map_err(|_| PhandleArgs::mismatch(tree, node, name)),
+                None => Ok(vec![default; count]),
+            };
+            let mask = read_or(&mask_name, u32::MAX)?;
+            let pass = read_or(&pass_name, 0)?;
+            let map = PhandleArgs::cells(tree, node, &map_name)?;
+
+            // Each entry is a child specifier, a phandle and a parent specifier
+            let mut found = None;
+            let mut pos = 0;
+            while pos < map.len() {
+                if pos + count + 1 > map.len() {
+                    return Err(PhandleArgs::mismatch(tree, node, &map_name));
+                }
+                let child = &map[pos..(pos + count)];
+                let phandle = map[pos + count];
+                pos += count + 1;
+                let parent = tree
+                    .find_node_by_phandle(phandle)
+                    .ok_or(DeviceTreeError::DanglingPhandle(phandle))?;
+                let parent_count = PhandleArgs::cell_count(tree, parent, &cells_name)?;
+                if pos + parent_count > map.len() {
+                    return Err(PhandleArgs::mismatch(tree, node, &map_name));
+                }
+                let parent_spec = &map[pos..(pos + parent_count)];
+                pos += parent_count;
+
+                let matched = (0..count).all(|i| {
+                    let arg = current.args.get(i).copied().unwrap_or(0);
+                    (child[i] ^ arg) & mask[i] == 0
+                });
+                if matched && tree.node(parent).is_available() {
+                    // The pass-thru bits are copied from the child specifier
+                    let args = parent_spec
+                        .iter()
+                        .enumerate()
+                        .map(|(i, cell)| {
+                            let pass = pass.get(i).copied().unwrap_or(0);
+                            let arg = current.args.get(i).copied().unwrap_or(0);
+                            (cell & !pass) | (arg & pass)
+                        })
+                        .collect();
+                    found = Some(PhandleArgs { node: parent, args });
+                    break;
+                }
+            }
+
+            let next = found.ok_or_else(|| PhandleArgs::mismatch(tree, node, &map_name))?;
+            visited.push(std::mem::replace(&mut current, next));
+        }
+        Ok(current)
+    }
+}
+
+/// A phandle held by a property, found by `DeviceTree::phandle_references`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhandleReference {
+    /// The node having the property
+    pub node: NodeId,
+    /// The name of the property
+    pub property: String,
+    /// The index of the cell holding the phandle in the property
+    pub cell: usize,
+    /// The phandle
+    pub phandle: u32,
+}
+
+/// How `DeviceTree::renumber_phandles` numbers the phandles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhandleNumbering {
+    /// Number the nodes with phandles 1, 2, 3... in depth-first order.
+    Sequential,
+    /// Derive the phandle of each node from its path, so that a node keeps
+    /// its phandle when other nodes are added, removed or reordered.
+    ///
+    /// The phandle is a hash of the path. If the hashes of two paths
+    /// collide, the path sorting first keeps its hash and the other path is
+    /// hashed again with a salt, so the phandle of a node only changes when
+    /// a node with a colliding path is added or removed.
+    StableByPath,
+}
+
+// The index of the phandles of a tree, kept up to date by the tree edits.
+//
+// A node borrowed with `node_mut` may change its phandle at any time, so it is
+// marked stale and checked directly until the index is refreshed. The lookups
+// check the phandles of the nodes found in the index, so the index may keep
+// outdated entries.
+#[derive(Default)]
+pub(crate) struct PhandleIndex {
+    nodes: BTreeMap<u32, Vec<NodeId>>,
+    stale: BTreeSet<NodeId>,
+}
+
+impl PhandleIndex {
+    pub(crate) fn insert(&mut self, phandle: u32, id: NodeId) {
+        let ids = self.nodes.entry(phandle).or_default();
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    pub(crate) fn mark_stale(&mut self, id: NodeId) {
+        self.stale.insert(id);
+    }
+
+    pub(crate) fn take_stale(&mut self) -> BTreeSet<NodeId> {
+        std::mem::take(&mut self.stale)
+    }
+
+    // Get the nodes that may have the phandle.
+    pub(crate) fn candidates(&self, phandle: u32) -> impl Iterator<Item = NodeId> + '_ {
+        let indexed = self.nodes.get(&phandle).into_iter().flatten();
+        indexed.chain(self.stale.iter()).copied()
+    }
+}
+
+// How the phandles are laid out in the cells of a property.
+enum Layout {
+    // Phandles, each followed by the number of cells given by a property of
+    // the node it refers to. Without the property, there is no cell after
+    // each phandle. If the property is optional, it counts 0 when missing.
+    List(Option<&'static str>, bool),
+    // A nexus map like "gpio-map", see `PhandleArgs::map`
+    Nexus(String),
+    // "interrupt-map": child unit address, child interrupt specifier,
+    // phandle, parent unit address and parent interrupt specifier
+    InterruptMap,
+    // "iommu-map" and "msi-map": id base, phandle, output id base and length,
+    // always 4 cells whatever "#iommu-cells" or "#msi-cells" of the node it
+    // refers to is, like `of_map_id` of Linux
+    IdMap,
+}
+
+// Count the cells after a phandle from the node it refers to
+type CellCounter<'a> = Box<dyn Fn(&Node) -> Option<usize> + 'a>;
+
+impl PhandleReference {
+    // Get the layout of a property by its name.
+    fn layout(tree: &DeviceTree, id: NodeId, name: &str) -> Option<Layout> {
+        let layout = match name {
+            "interrupt-parent"
+            | "memory-region"
+            | "cpu-idle-states"
+            | "next-level-cache"
+            | "operating-points-v2"
+            | "remote-endpoint"
+            | "phy-handle"
+            | "cpu" => Layout::List(None, false),
+            "clocks" | "assigned-clocks" | "assigned-clock-parents" => {
+                Layout::List(Some("#clock-cells"), false)
+            }
+            "resets" => Layout::List(Some("#reset-cells"), false),
+            "power-domains" => Layout::List(Some("#power-domain-cells"), false),
+            "dmas" => Layout::List(Some("#dma-cells"), false),
+            "pwms" => Layout::List(Some("#pwm-cells"), false),
+            "iommus" => Layout::List(Some("#iommu-cells"), false),
+            "phys" => Layout::List(Some("#phy-cells"), false),
+            "mboxes" => Layout::List(Some("#mbox-cells"), false),
+            "io-channels" => Layout::List(Some("#io-channel-cells"), false),
+            "thermal-sensors" => Layout::List(Some("#thermal-sensor-cells"), false),
+            "sound-dai" => Layout::List(Some("#sound-dai-cells"), false),
+            "interconnects" => Layout::List(Some("#interconnect-cells"), false),
+            "hwlocks" => Layout::List(Some("#hwlock-cells"), false),
+            "interrupts-extended" => Layout::List(Some("#interrupt-cells"), false),
+            "msi-parent" => Layout::List(Some("#msi-cells"), true),
+            "nvmem-cells" => Layout::List(Some("#nvmem-cell-cells"), true),
+            "interrupt-map" => Layout::InterruptMap,
+            "iommu-map" | "msi-map" => Layout::IdMap,
+            _ if name == "gpios" || name.ends_with("-gpios") || name.ends_with("-gpio") => {
+                Layout::List(Some("#gpio-cells"), false)
+            }
+            _ if name.ends_with("-supply") => Layout::List(None, false),
+            _ if name.starts_with("pinctrl-")
+                && name[8..].chars().all(|c| c.is_ascii_digit())
+                && name.len() > 8 =>
+            {
+                Layout::List(None, false)
+            }
+            _ => {
+                let stem = name.strip_suffix("-map")?;
+                let cells_name = format!("#{stem}-cells");
+                tree.node(id).find_property(&cells_name)?;
+                Layout::Nexus(String::from(stem))
+            }
+        };
+        Some(layout)
+    }
+
+    // Read a `#*-cells` property of a node.
+    fn cell_count(node: &Node, name: &str, optional: bool) -> Option<usize> {
+        match node.read_u32(name) {
+            Ok(count) => Some(count as usize),
+            Err(_) if optional => Some(0),
+            Err(_) => None,
+        }
+    }
+
+    // Get the "#address-cells" of a node for "interrupt-map", looking up the
+    // ancestors like Linux does.
+    fn address_cells(tree: &DeviceTree, id: NodeId) -> usize {
+        let mut node = Some(id);
+        while let Some(id) = node {
+            if let Ok(count) = tree.node(id).read_u32("#address-cells") {
+                return count as usize;
+            }
+            node = tree.parent(id);
+        }
+        2
+    }
+
+    // Find the phandles held by a property of a node, as pairs of the cell
+    // index and the phandle. The decoding stops at the first phandle that
+    // refers to no node or whose cells can't be counted, which is still
+    // returned.
+    pub(crate) fn find_in_property(tree: &DeviceTree, id: NodeId, name: &str) -> Vec<(usize, u32)> {
+        let resolve = |phandle| tree.find_node_by_phandle(phandle).map(|id| tree.node(id));
+        PhandleReference::find_in_property_with(tree, id, name, &resolve)
+    }
+
+    // Find the phandles held by a property like `find_in_property`, where
+    // the nodes the phandles refer to are found by `resolve`, which may look
+    // them up outside of the tree.
+    pub(crate) fn find_in_property_with<'a>(
+        tree: &DeviceTree,
+        id: NodeId,
+        name: &str,
+        resolve: &dyn Fn(u32) -> Option<&'a Node>,
+    ) -> Vec<(usize, u32)> {
+        let mut found = vec![];
+        let Some(layout) = PhandleReference::layout(tree, id, name) else {
+            return found;
+        };
+        let Ok(cells) = PhandleArgs::cells(tree, id, name) else {
+            return found;
+        };
+
+        // The cells before the phandle of each entry, and the cells of the
+        // node the phandle refers to after it
+        let (before, after): (usize, CellCounter) = match &layout {
+            Layout::List(None, _) => (0, Box::new(|_| Some(0))),
+            Layout::List(Some(cells_name), optional) => (
+                0,
+                Box::new(|target| PhandleReference::cell_count(target, cells_name, *optional)),
+            ),
+            Layout::Nexus(stem) => {
+                let cells_name = format!("#{stem}-cells");
+                let Some(count) = PhandleReference::cell_count(tree.node(id), &cells_name, false)
+                else {
+                    return found;
+                };
+                (
+                    count,
+                    Box::new(move |target| {
+                        PhandleReference::cell_count(target, &cells_name, false)
+                    }),
+                )
+            }
+            Layout::InterruptMap => {
+                let Some(count) =
+                    PhandleReference::cell_count(tree.node(id), "#interrupt-cells", false)
+                else {
+                    return found;
+                };
+                (
+                    PhandleReference::address_cells(tree, id) + count,
+                    Box::new(|target| {
+                        let address = target.read_u32("#address-cells").unwrap_or(0);
+                        let count =
+                            PhandleReference::cell_count(target, "#interrupt-cells", false)?;
+                        Some(address as usize + count)
+                    }),
+                )
+            }
+            Layout::IdMap => (1, Box::new(|_| Some(2))),
+        };
+
+        let mut pos = 0;
+        while pos + before < cells.len() {
+            let cell = pos + before;
+            let phandle = cells[cell];
+            pos = cell + 1;
+            // Phandle 0 is an empty entry of a list
+            if phandle == 0 && matches!(layout, Layout::List(..)) {
+                continue;
+            }
+            found.push((cell, phandle));
+            // The entries of an ID map don't depend on the node referred to
+            let count = match layout {
+                Layout::IdMap => Some(2),
+                _ => resolve(phandle).and_then(&after),
+            };
+            match count {
+                Some(count) => pos += count,
+                None => break,
+            }
+        }
+        found
+    }
+
+    // Find all the phandles held by the properties of the tree.
+    pub(crate) fn find_all(tree: &DeviceTree) -> Vec<PhandleReference> {
+        let mut references = vec![];
+        for id in tree.subtree(tree.root()) {
+            for prop in &tree.node(id).properties {
+                for (cell, phandle) in PhandleReference::find_in_property(tree, id, &prop.name) {
+                    references.push(PhandleReference {
+                        node: id,
+                        property: prop.name.clone(),
+                        cell,
+                        phandle,
+                    });
+                }
+            }
+        }
+        references
+    }
+}
+
+impl PhandleNumbering {
+    // Choose the new phandles of the nodes having phandles, in depth-first
+    // order.
+    pub(crate) fn assign(&self, tree: &DeviceTree, ids: &[NodeId]) -> Vec<u32> {
+        match self {
+            PhandleNumbering::Sequential => (1..=ids.len() as u32).collect(),
+            PhandleNumbering::StableByPath => {
+                let paths: Vec<String> = ids.iter().map(|id| tree.path_of(*id)).collect();
+                // Resolve the collisions in the order of the paths, not the
+                // order of the nodes
+                let mut order: Vec<usize> = (0..ids.len()).collect();
+                order.sort_by(|a, b| paths[*a].cmp(&paths[*b]));
+                let mut used = BTreeSet::new();
+                let mut phandles = vec![0; ids.len()];
+                for i in order {
+                    let phandle = (0..)
+                        .map(|salt| PhandleNumbering::hash(&paths[i], salt))
+                        // 0 and 0xffffffff are not valid phandles
+                        .find(|p| *p != 0 && *p != u32::MAX && !used.contains(p))
+                        .unwrap();
+                    used.insert(phandle);
+                    phandles[i] = phandle;
+                }
+                phandles
+            }
+        }
+    }
+
+    // Hash a path with 32-bit FNV-1a, followed by the salt if it is not 0.
+    fn hash(path: &str, salt: u32) -> u32 {
+        let salt = match salt {
+            0 => vec![],
+            _ => salt.to_le_bytes().to_vec(),
+        };
+        path.bytes().chain(salt).fold(0x811c9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use crate::property::Property;
+
+    fn tree() -> DeviceTree {
+        DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	clk: clock {
+		phandle = <1>;
+		#clock-cells = <1>;
+	};
+
+	osc: oscillator {
+		phandle = <2>;
+		#clock-cells = <0>;
+	};
+
+	gpio0: gpio@0 {
+		phandle = <3>;
+		#gpio-cells = <2>;
+	};
+
+	gpio1: gpio@1 {
+		phandle = <4>;
+		#gpio-cells = <2>;
+		status = "disabled";
+	};
+
+	gpio2: gpio@2 {
+		phandle = <5>;
+		#gpio-cells = <2>;
+	};
+
+	connector: connector {
+		phandle = <6>;
+		#gpio-cells = <2>;
+		gpio-map = <0 0 &gpio1 3 0>, <0 0 &gpio0 3 0>, <1 0 &gpio2 7 0>;
+		gpio-map-mask = <0xf 0>;
+		gpio-map-pass-thru = <0 0x1>;
+	};
+
+	loop: loop {
+		phandle = <7>;
+		#gpio-cells = <1>;
+		gpio-map = <0 &loop 0>;
+	};
+
+	device {
+		clocks = <&clk 3>, <&osc>, <0>, <&clk 5>;
+		gpios = <&gpio0 1 0>, <&connector 0x10 1>, <&connector 1 0>;
+		loop-gpios = <&loop 0>;
+		broken-gpios = <&gpio0 1>;
+		dangling-gpios = <0x100 1 0>;
+	};
+};
+"#,
+        )
+    }
+
+    #[test]
+    fn test_phandle_with_args() {
+        let tree = tree();
+        let device = tree.find_node_by_path("/device").unwrap();
+        let clk = tree.find_node_by_path("/clock").unwrap();
+        let osc = tree.find_node_by_path("/oscillator").unwrap();
+
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "clocks", "#clock-cells", 0),
+            Ok(Some(PhandleArgs {
+                node: clk,
+                args: vec![3]
+            }))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "clocks", "#clock-cells", 1),
+            Ok(Some(PhandleArgs {
+                node: osc,
+                args: vec![]
+            }))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "clocks", "#clock-cells", 2),
+            Ok(None)
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "clocks", "#clock-cells", 4),
+            Ok(None)
+        );
+        assert_eq!(
+            tree.count_phandle_with_args(device, "clocks", "#clock-cells"),
+            Ok(4)
+        );
+        assert_eq!(
+            tree.parse_phandle_with_fixed_args(device, "clocks", 1, 0)
+                .unwrap()
+                .unwrap()
+                .args,
+            vec![3]
+        );
+
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "pwms", "#pwm-cells", 0),
+            Err(DeviceTreeError::PropertyNotFound(String::from("pwms")))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "broken-gpios", "#gpio-cells", 0),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/device:broken-gpios"
+            )))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "dangling-gpios", "#gpio-cells", 0),
+            Err(DeviceTreeError::DanglingPhandle(0x100))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "clocks", "#gpio-cells", 0),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/clock:#gpio-cells"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_phandle_with_args_map() {
+        let tree = tree();
+        let device = tree.find_node_by_path("/device").unwrap();
+        let gpio0 = tree.find_node_by_path("/gpio@0").unwrap();
+        let gpio2 = tree.find_node_by_path("/gpio@2").unwrap();
+
+        // A provider without the map is returned as it is
+        assert_eq!(
+            tree.parse_phandle_with_args_map(device, "gpios", "gpio", 0),
+            Ok(Some(PhandleArgs {
+                node: gpio0,
+                args: vec![1, 0]
+            }))
+        );
+        // The mask ignores 0x10, the disabled gpio@1 is skipped, and the flag
+        // passes through
+        assert_eq!(
+            tree.parse_phandle_with_args_map(device, "gpios", "gpio", 1),
+            Ok(Some(PhandleArgs {
+                node: gpio0,
+                args: vec![3, 1]
+            }))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args_map(device, "gpios", "gpio", 2),
+            Ok(Some(PhandleArgs {
+                node: gpio2,
+                args: vec![7, 0]
+            }))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args_map(device, "loop-gpios", "gpio", 0),
+            Err(DeviceTreeError::CellMismatch(String::from(
+                "/loop:gpio-map"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_phandle_index() {
+        let mut tree = tree();
+        let clk = tree.find_node_by_path("/clock").unwrap();
+        assert_eq!(tree.find_node_by_phandle(1), Some(clk));
+
+        // The index follows the edits of the nodes
+        tree.node_mut(clk)
+            .set_property(Property::new_u32("phandle", 0x20));
+        assert_eq!(tree.find_node_by_phandle(1), None);
+        assert_eq!(tree.find_node_by_phandle(0x20), Some(clk));
+        tree.set_property_at("/oscillator", Property::new_u32("linux,phandle", 0x30))
+            .unwrap();
+        tree.remove_property_at("/oscillator", "phandle").unwrap();
+        let osc = tree.find_node_by_path("/oscillator").unwrap();
+        assert_eq!(tree.find_node_by_phandle(0x30), Some(osc));
+        assert_eq!(tree.find_node_by_phandle(2), None);
+
+        let mut node = Node::new("new");
+        node.add_property(Property::new_u32("phandle", 0x40));
+        let new = tree.add_sub_node(tree.root(), node);
+        assert_eq!(tree.find_node_by_phandle(0x40), Some(new));
+        tree.remove_node("/new").unwrap();
+        assert_eq!(tree.find_node_by_phandle(0x40), None);
+
+        let device = tree.find_node_by_path("/device").unwrap();
+        assert_eq!(tree.ensure_phandle(device), 0x31);
+        assert_eq!(tree.find_node_by_phandle(0x31), Some(device));
+    }
+
+    #[test]
+    fn test_phandle_references() {
+        let tree = tree();
+        let device = tree.find_node_by_path("/device").unwrap();
+        let connector = tree.find_node_by_path("/connector").unwrap();
+
+        let references = tree.phandle_references();
+        let cells = |id: NodeId, name: &str| -> Vec<(usize, u32)> {
+            references
+                .iter()
+                .filter(|r| r.node == id && r.property == name)
+                .map(|r| (r.cell, r.phandle))
+                .collect()
+        };
+        assert_eq!(cells(device, "clocks"), vec![(0, 1), (2, 2), (4, 1)]);
+        assert_eq!(cells(device, "gpios"), vec![(0, 3), (3, 6), (6, 6)]);
+        assert_eq!(cells(connector, "gpio-map"), vec![(2, 4), (7, 3), (12, 5)]);
+        // The decoding stops at the first dangling phandle
+        assert_eq!(cells(device, "dangling-gpios"), vec![(0, 0x100)]);
+
+        let dangling: Vec<u32> = tree.dangling_phandles().iter().map(|r| r.phandle).collect();
+        assert_eq!(dangling, vec![0x100]);
+        assert_eq!(
+            tree.check_phandles(),
+            Err(DeviceTreeError::DanglingPhandle(0x100))
+        );
+        assert!(tree.duplicate_phandles().is_empty());
+    }
+
+    #[test]
+    fn test_phandle_interrupt_map() {
+        let tree = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	intc: intc {
+		phandle = <1>;
+		#address-cells = <2>;
+		#interrupt-cells = <3>;
+	};
+
+	pci {
+		#address-cells = <3>;
+		#interrupt-cells = <1>;
+		interrupt-map-mask = <0x1800 0 0 7>;
+		interrupt-map = <0 0 0 1 &intc 0 0 0 3 4>, <0x800 0 0 1 &intc 0 0 0 4 4>;
+	};
+};
+"#,
+        );
+        let pci = tree.find_node_by_path("/pci").unwrap();
+        // Each entry has 3 + 1 cells of the child, the phandle and 2 + 3 cells
+        // of the parent
+        assert_eq!(
+            PhandleReference::find_in_property(&tree, pci, "interrupt-map"),
+            vec![(4, 1), (14, 1)]
+        );
+        assert_eq!(tree.check_phandles(), Ok(()));
+    }
+
+    #[test]
+    fn test_phandle_renumber() {
+        let mut tree = tree();
+        let mut node = Node::new("duplicate");
+        node.add_property(Property::new_u32("phandle", 1));
+        tree.add_sub_node(tree.root(), node);
+        assert_eq!(
+            tree.duplicate_phandles(),
+            vec![(
+                1,
+                vec![
+                    tree.find_node_by_path("/clock").unwrap(),
+                    tree.find_node_by_path("/duplicate").unwrap()
+                ]
+            )]
+        );
+        tree.remove_node("/duplicate").unwrap();
+
+        tree.renumber_phandles(PhandleNumbering::StableByPath);
+        let device = tree.find_node_by_path("/device").unwrap();
+        let clk = tree.find_node_by_path("/clock").unwrap();
+        let connector = tree.find_node_by_path("/connector").unwrap();
+        let clk_phandle = tree.node(clk).phandle().unwrap();
+        assert_eq!(clk_phandle, PhandleNumbering::hash("/clock", 0));
+
+        // The references follow the new phandles
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "clocks", "#clock-cells", 3),
+            Ok(Some(PhandleArgs {
+                node: clk,
+                args: vec![5]
+            }))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args(device, "gpios", "#gpio-cells", 1),
+            Ok(Some(PhandleArgs {
+                node: connector,
+                args: vec![0x10, 1]
+            }))
+        );
+        assert_eq!(
+            tree.parse_phandle_with_args_map(device, "gpios", "gpio", 2)
+                .unwrap()
+                .unwrap()
+                .args,
+            vec![7, 0]
+        );
+
+        // The stable phandles don't depend on the other nodes
+        let osc_phandle = PhandleNumbering::hash("/oscillator", 0);
+        tree.remove_node("/oscillator").unwrap();
+        tree.renumber_phandles(PhandleNumbering::StableByPath);
+        assert_eq!(tree.node(clk).phandle(), Some(clk_phandle));
+
+        tree.renumber_phandles(PhandleNumbering::Sequential);
+        let phandles: Vec<Option<u32>> = ["/clock", "/gpio@0", "/loop"]
+            .iter()
+            .map(|path| tree.node(tree.find_node_by_path(path).unwrap()).phandle())
+            .collect();
+        assert_eq!(phandles, vec![Some(1), Some(2), Some(6)]);
+        assert_eq!(
+            tree.dangling_phandles()
+                .iter()
+                .map(|r| r.phandle)
+                .collect::<Vec<u32>>(),
+            vec![osc_phandle, 0x100]
+        );
+    }
+
+    #[test]
+    fn test_phandle_renumber_collision() {
+        // The FNV-1a hashes of the two paths collide
+        let names = ["node674385", "node1000020"];
+        assert_eq!(
+            PhandleNumbering::hash("/node674385", 0),
+            PhandleNumbering::hash("/node1000020", 0)
+        );
+        let phandles = |names: &[&str]| {
+            let mut root = Node::new("");
+            for name in names {
+                let mut node = Node::new(name);
+                node.add_property(Property::new_u32("phandle", 1));
+                root.add_sub_node(node);
+            }
+            let mut tree = DeviceTree::new(vec![], root);
+            tree.renumber_phandles(PhandleNumbering::StableByPath);
+            let phandle = |path| tree.node(tree.find_node_by_path(path).unwrap()).phandle();
+            (phandle("/node674385"), phandle("/node1000020"))
+        };
+
+        // The path sorting first keeps the hash, whatever the node order is
+        let expected = (
+            Some(PhandleNumbering::hash("/node674385", 1)),
+            Some(PhandleNumbering::hash("/node1000020", 0)),
+        );
+        assert_eq!(phandles(&names), expected);
+        assert_eq!(phandles(&[names[1], names[0]]), expected);
+    }
+
+    #[test]
+    fn test_phandle_renumber_id_map() {
+        let mut tree = DeviceTree::from_dts_bytes(
+            br#"/dts-v1/;
+/ {
+	imsic: imsic {
+		phandle = <0x100>;
+		msi-controller;
+		#msi-cells = <0>;
+	};
+
+	smmu: iommu {
+		phandle = <0x200>;
+		#iommu-cells = <2>;
+	};
+
+	pcie {
+		msi-map = <0x0 0x100 0x0 0x100>, <0x100 0x100 0x100 0x100>;
+		iommu-map = <0x0 0x200 0x0 0x100>, <0x100 0x200 0x100 0x100>;
+	};
+};
+"#,
+        );
+        let pcie = tree.find_node_by_path("/pcie").unwrap();
+        // Each entry has 4 cells, whatever the cells of the target are
+        for name in ["msi-map", "iommu-map"] {
+            let found = PhandleReference::find_in_property(&tree, pcie, name);
+            assert_eq!(
+                found.iter().map(|(cell, _)| *cell).collect::<Vec<_>>(),
+                vec![1, 5]
+            );
+        }
+
+        tree.renumber_phandles(PhandleNumbering::Sequential);
+        let node = tree.node(pcie);
+        assert_eq!(
+            node.read_u32_array("msi-map", 8),
+            Ok(vec![0, 1, 0, 0x100, 0x100, 1, 0x100, 0x100])
+        );
+        assert_eq!(
+            node.read_u32_array("iommu-map", 8),
+            Ok(vec![0, 2, 0, 0x100, 0x100, 2, 0x100, 0x100])
+        );
+        assert_eq!(tree.check_phandles(), Ok(()));
+    }
+}
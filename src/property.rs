@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use crate::dts_generator::DtsGenerator;
+use crate::utils::Utils;
 
 /// A property that describes a characteristic of node.
 ///
@@ -26,11 +27,26 @@ use crate::dts_generator::DtsGenerator;
 ///
 /// assert_eq!(format!("{}", prop), "prop = <0x0 0x0 0x0 0x2a>;\n");
 /// ```
+#[derive(Clone, Debug, PartialEq)]
 pub struct Property {
     pub name: String,
     pub value: Vec<u8>,
 }
 
+/// The value of a `Property` interpreted as the most likely DTS type.
+///
+/// The binary value of a property carries no type information, the
+/// interpretation follows the same rules `dtc` uses when decompiling a DTB:
+/// a list of printable zero-terminated strings, otherwise a list of 32-bit
+/// cells if the length is a multiple of 4, otherwise a byte string.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    Empty,
+    Strings(Vec<String>),
+    Cells(Vec<u32>),
+    Bytes(Vec<u8>),
+}
+
 impl Property {
     /// Create a `Property` with a name, but without any value.
     ///
@@ -179,6 +195,108 @@ impl Property {
     }
 }
 
+impl Property {
+    /// Interpret the value of the `Property` as a `PropertyValue`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::Property;
+    /// use devicetree_tool::PropertyValue;
+    ///
+    /// let prop = Property::new_strs("compatible", vec!["arm,pl011", "arm,primecell"]);
+    /// assert_eq!(
+    ///     prop.typed_value(),
+    ///     PropertyValue::Strings(vec![String::from("arm,pl011"), String::from("arm,primecell")])
+    /// );
+    ///
+    /// let prop = Property::new_u32s("reg", vec![0x9000000, 0x1000]);
+    /// assert_eq!(prop.typed_value(), PropertyValue::Cells(vec![0x9000000, 0x1000]));
+    /// ```
+    pub fn typed_value(&self) -> PropertyValue {
+        let value = &self.value;
+        if value.is_empty() {
+            return PropertyValue::Empty;
+        }
+
+        // Every string must be non-empty and consist of printable characters
+        let is_strings = value[value.len() - 1] == 0
+            && value[0] != 0
+            && !value.windows(2).any(|w| w[0] == 0 && w[1] == 0)
+            && value
+                .iter()
+                .all(|&c| c == 0 || c.is_ascii_graphic() || c == b' ' || c == b'\t');
+        if is_strings {
+            let strings = value[..value.len() - 1]
+                .split(|&c| c == 0)
+                .map(|s| String::from_utf8_lossy(s).to_string())
+                .collect();
+            PropertyValue::Strings(strings)
+        } else if value.len().is_multiple_of(4) {
+            let cells = value
+                .chunks(4)
+                .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+            PropertyValue::Cells(cells)
+        } else {
+            PropertyValue::Bytes(value.clone())
+        }
+    }
+}
+
+impl PropertyValue {
+    /// Generate the JSON object of the value, with a `type` (`empty`,
+    /// `strings`, `cells` or `bytes`) and a `value`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::PropertyValue;
+    ///
+    /// let value = PropertyValue::Cells(vec![0x9000000, 0x1000]);
+    ///
+    /// assert_eq!(value.to_json(), "{\"type\":\"cells\",\"value\":[150994944,4096]}");
+    /// ```
+    pub fn to_json(&self) -> String {
+        match self {
+            PropertyValue::Empty => String::from("{\"type\":\"empty\"}"),
+            PropertyValue::Strings(strings) => {
+                let strings: Vec<String> = strings.iter().map(|s| Utils::json_string(s)).collect();
+                format!("{{\"type\":\"strings\",\"value\":[{}]}}", strings.join(","))
+            }
+            PropertyValue::Cells(cells) => {
+                let cells: Vec<String> = cells.iter().map(|c| c.to_string()).collect();
+                format!("{{\"type\":\"cells\",\"value\":[{}]}}", cells.join(","))
+            }
+            PropertyValue::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+                format!("{{\"type\":\"bytes\",\"value\":[{}]}}", bytes.join(","))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PropertyValue {
+    /// Print a `PropertyValue` in the format of DTS
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PropertyValue::Empty => Ok(()),
+            PropertyValue::Strings(strings) => {
+                let strings: Vec<String> = strings.iter().map(|s| format!("{:?}", s)).collect();
+                write!(f, "{}", strings.join(", "))
+            }
+            PropertyValue::Cells(cells) => {
+                let cells: Vec<String> = cells.iter().map(|c| format!("{:#x}", c)).collect();
+                write!(f, "<{}>", cells.join(" "))
+            }
+            PropertyValue::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "[{}]", bytes.join(" "))
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for Property {
     /// Print a `Property` in the format of DTS
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -252,6 +370,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_property_typed_value() {
+        assert_eq!(
+            Property::new_empty("name").typed_value(),
+            PropertyValue::Empty
+        );
+        assert_eq!(
+            Property::new_str("name", "okay").typed_value(),
+            PropertyValue::Strings(vec![String::from("okay")])
+        );
+        assert_eq!(
+            Property::new_u32("name", 0x6f6b6179).typed_value(),
+            PropertyValue::Cells(vec![0x6f6b6179])
+        );
+        assert_eq!(
+            Property::new_u8s("name", vec![0x61, 0x0, 0x0, 0x62, 0x0]).typed_value(),
+            PropertyValue::Bytes(vec![0x61, 0x0, 0x0, 0x62, 0x0])
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Property::new_strs("name", vec!["a", "b"]).typed_value()
+            ),
+            "\"a\", \"b\""
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Property::new_u32s("name", vec![1, 0x20]).typed_value()
+            ),
+            "<0x1 0x20>"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Property::new_u8s("name", vec![1, 0x20, 3]).typed_value()
+            ),
+            "[01 20 03]"
+        );
+    }
+
     #[test]
     fn test_property_print() {
         let prop = Property::new_u32("name", 42);
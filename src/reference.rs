@@ -0,0 +1,291 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::node::NodeId;
+use crate::phandle::PhandleReference;
+use crate::utils::Utils;
+use std::collections::BTreeMap;
+
+/// How a property refers to another node.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReferenceKind {
+    /// A phandle at the index of the cell in the property
+    Phandle(usize),
+    /// A path string, like the properties of "/aliases" and the
+    /// "stdout-path" of "/chosen"
+    Path,
+}
+
+/// A reference from a property of a node to another node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    /// The node having the property
+    pub from: NodeId,
+    /// The name of the property
+    pub property: String,
+    /// The node referred to
+    pub to: NodeId,
+    pub kind: ReferenceKind,
+}
+
+impl Reference {
+    /// Get the name of the property, followed by the index of the cell for a
+    /// phandle, like `interrupts-extended[3]`.
+    pub fn location(&self) -> String {
+        match self.kind {
+            ReferenceKind::Phandle(cell) => format!("{}[{}]", self.property, cell),
+            ReferenceKind::Path => self.property.clone(),
+        }
+    }
+}
+
+/// The references between the nodes of a `DeviceTree`.
+///
+/// The phandles are found like `DeviceTree::phandle_references`, and the
+/// paths are found in "/aliases", "/__symbols__" and the "stdout-path" and
+/// "stdin-path" of "/chosen". The references that can't be resolved are left
+/// out.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, ReferenceKind};
+///
+/// let dts = r#"/dts-v1/;
+/// / {
+///     aliases {
+///         serial0 = "/pl011@9000000";
+///     };
+///     chosen {
+///         stdout-path = "serial0:115200n8";
+///     };
+///     gic: intc {
+///         phandle = <1>;
+///         #interrupt-cells = <3>;
+///     };
+///     pl011@9000000 {
+///         interrupt-parent = <&gic>;
+///     };
+/// };"#;
+/// let tree = DeviceTree::from_dts_bytes(dts.as_bytes());
+/// let graph = tree.reference_graph();
+///
+/// let gic = tree.find_node_by_path("/intc").unwrap();
+/// let uart = tree.find_node_by_path("/pl011@9000000").unwrap();
+///
+/// let referrers = graph.referrers(gic);
+/// assert_eq!(referrers.len(), 1);
+/// assert_eq!(referrers[0].from, uart);
+/// assert_eq!(referrers[0].kind, ReferenceKind::Phandle(0));
+///
+/// let paths: Vec<String> = graph
+///     .referrers(uart)
+///     .iter()
+///     .map(|r| format!("{}:{}", tree.path_of(r.from), r.property))
+///     .collect();
+/// assert_eq!(paths, vec!["/aliases:serial0", "/chosen:stdout-path"]);
+/// ```
+pub struct ReferenceGraph {
+    references: Vec<Reference>,
+    // The indexes of the references from and to each node
+    from: BTreeMap<NodeId, Vec<usize>>,
+    to: BTreeMap<NodeId, Vec<usize>>,
+}
+
+impl ReferenceGraph {
+    /// Build the reference graph of a tree.
+    pub fn new(tree: &DeviceTree) -> Self {
+        let mut references = vec![];
+        let paths = ReferenceGraph::path_references(tree);
+        let mut phandles = PhandleReference::find_all(tree).into_iter().peekable();
+        // Keep the references in the order of the nodes
+        for id in tree.subtree(tree.root()) {
+            while let Some(reference) = phandles.next_if(|r| r.node == id) {
+                if let Some(to) = tree.find_node_by_phandle(reference.phandle) {
+                    references.push(Reference {
+                        from: id,
+                        property: reference.property,
+                        to,
+                        kind: ReferenceKind::Phandle(reference.cell),
+                    });
+                }
+            }
+            references.extend(paths.iter().filter(|r| r.from == id).cloned());
+        }
+
+        let mut from: BTreeMap<NodeId, Vec<usize>> = BTreeMap::new();
+        let mut to: BTreeMap<NodeId, Vec<usize>> = BTreeMap::new();
+        for (i, reference) in references.iter().enumerate() {
+            from.entry(reference.from).or_default().push(i);
+            to.entry(reference.to).or_default().push(i);
+        }
+        ReferenceGraph {
+            references,
+            from,
+            to,
+        }
+    }
+
+    // Find the references by path strings.
+    fn path_references(tree: &DeviceTree) -> Vec<Reference> {
+        let mut references = vec![];
+        let mut add = |from: NodeId, property: &str, to: Option<NodeId>| {
+            if let Some(to) = to {
+                references.push(Reference {
+                    from,
+                    property: String::from(property),
+                    to,
+                    kind: ReferenceKind::Path,
+                });
+            }
+        };
+
+        // Every property of "/aliases" and "/__symbols__" is a full path
+        for name in ["aliases", "__symbols__"] {
+            let Some(id) = tree.find_child_by_name(tree.root(), name) else {
+                continue;
+            };
+            for prop in &tree.node(id).properties {
+                if let Ok(path) = tree.node(id).read_string(&prop.name) {
+                    if path.starts_with('/') {
+                        add(id, &prop.name, tree.find_node_by_path(path));
+                    }
+                }
+            }
+        }
+
+        // The console paths of "/chosen" may be aliases and have options
+        if let Some(id) = tree.find_child_by_name(tree.root(), "chosen") {
+            for name in ["stdout-path", "linux,stdout-path", "stdin-path"] {
+                if let Ok(path) = tree.node(id).read_string(name) {
+                    let to = tree.find_node_by_path_opts(path).map(|(to, _)| to);
+                    add(id, name, to);
+                }
+            }
+        }
+        references
+    }
+
+    /// Get all the references, in the depth-first order of the nodes having
+    /// them.
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    /// Get the references to a node.
+    pub fn referrers(&self, id: NodeId) -> Vec<&Reference> {
+        self.get(&self.to, id)
+    }
+
+    /// Get the references from the properties of a node.
+    pub fn references_from(&self, id: NodeId) -> Vec<&Reference> {
+        self.get(&self.from, id)
+    }
+
+    /// Generate the JSON text of the references to and from a node.
+    ///
+    /// The output is an object with a `referrers` array and a `references`
+    /// array. Each item has the `path` of the node at the other end and the
+    /// `property` like `Reference::location`.
+    pub fn to_json(&self, tree: &DeviceTree, id: NodeId) -> String {
+        let items = |references: Vec<&Reference>, to: bool| -> String {
+            let items: Vec<String> = references
+                .into_iter()
+                .map(|r| {
+                    let path = tree.path_of(if to { r.to } else { r.from });
+                    format!(
+                        "{{\"path\":{},\"property\":{}}}",
+                        Utils::json_string(&path),
+                        Utils::json_string(&r.location())
+                    )
+                })
+                .collect();
+            format!("[{}]", items.join(","))
+        };
+        format!(
+            "{{\"referrers\":{},\"references\":{}}}",
+            items(self.referrers(id), false),
+            items(self.references_from(id), true)
+        )
+    }
+
+    fn get(&self, index: &BTreeMap<NodeId, Vec<usize>>, id: NodeId) -> Vec<&Reference> {
+        index
+            .get(&id)
+            .map(|indexes| indexes.iter().map(|i| &self.references[*i]).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_graph() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let tree = DeviceTree::from_dts_bytes(&dts);
+        let graph = tree.reference_graph();
+        let intc = tree.find_node_by_path("/intc").unwrap();
+        let msic = tree.find_node_by_path("/intc/msic").unwrap();
+        let pci = tree.find_node_by_path("/pci").unwrap();
+
+        let referrers: Vec<(String, String)> = graph
+            .referrers(intc)
+            .iter()
+            .map(|r| (tree.path_of(r.from), r.property.clone()))
+            .collect();
+        assert_eq!(
+            referrers,
+            vec![(String::from("/"), String::from("interrupt-parent"))]
+        );
+
+        let references = graph.references_from(pci);
+        assert_eq!(
+            references,
+            vec![&Reference {
+                from: pci,
+                property: String::from("msi-parent"),
+                to: msic,
+                kind: ReferenceKind::Phandle(0)
+            }]
+        );
+        assert!(graph.referrers(pci).is_empty());
+        assert_eq!(
+            graph.to_json(&tree, pci),
+            r#"{"referrers":[],"references":[{"path":"/intc/msic","property":"msi-parent[0]"}]}"#
+        );
+
+        // Every reference is indexed both ways
+        for reference in graph.references() {
+            assert!(graph.referrers(reference.to).contains(&reference));
+            assert!(graph.references_from(reference.from).contains(&reference));
+        }
+    }
+
+    #[test]
+    fn test_reference_graph_paths() {
+        let dts = std::fs::read("test/dts_8.dts").unwrap();
+        let tree = DeviceTree::from_dts_bytes(&dts);
+        let graph = tree.reference_graph();
+        let aliases = tree.find_node_by_path("/aliases").unwrap();
+
+        let references: Vec<(String, String)> = graph
+            .references_from(aliases)
+            .iter()
+            .map(|r| (r.property.clone(), tree.path_of(r.to)))
+            .collect();
+        // "ethernet0" isn't a full path
+        assert_eq!(
+            references,
+            vec![
+                (String::from("serial0"), String::from("/pl011@9000000")),
+                (String::from("gpio0"), String::from("/soc/gpio@9030000")),
+            ]
+        );
+
+        let uart = tree.find_node_by_path("/pl011@9000000").unwrap();
+        assert_eq!(graph.referrers(uart).len(), 2);
+    }
+}
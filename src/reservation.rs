@@ -4,10 +4,13 @@
 use crate::dts_generator::DtsGenerator;
 
 /// A memory reservation block for reserving physical memory.
-#[derive(Copy, Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Reservation {
     pub address: u64,
     pub length: u64,
+    /// The label of the `/memreserve/` entry in DTS, which is not kept in
+    /// DTB
+    pub label: Option<String>,
 }
 
 /// Create a new memory reservation block with physical address and length
@@ -23,7 +26,58 @@ pub struct Reservation {
 /// ```
 impl Reservation {
     pub fn new(address: u64, length: u64) -> Self {
-        Reservation { address, length }
+        Reservation {
+            address,
+            length,
+            label: None,
+        }
+    }
+
+    /// Create a new memory reservation block with a label.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use devicetree_tool::Reservation;
+    ///
+    /// let resv = Reservation::new_with_label(0, 0x1000, "fw");
+    ///
+    /// assert_eq!(format!("{}", resv), "fw: /memreserve/ 0x0000000000000000 0x0000000000001000;");
+    /// ```
+    pub fn new_with_label(address: u64, length: u64, label: &str) -> Self {
+        Reservation {
+            address,
+            length,
+            label: Some(String::from(label)),
+        }
+    }
+
+    // Get the end of the reserved range, which is exclusive.
+    pub(crate) fn end(&self) -> u64 {
+        self.address.saturating_add(self.length)
+    }
+
+    // Merge the overlapping and adjacent reservations, in the order of the
+    // addresses. A merged reservation keeps the label of either one, and two
+    // labelled reservations are never merged so that no label is lost.
+    pub(crate) fn coalesce(mut reservations: Vec<Reservation>) -> Vec<Reservation> {
+        reservations.sort_by_key(|r| (r.address, r.length));
+        let mut merged: Vec<Reservation> = vec![];
+        for reservation in reservations {
+            match merged.last_mut() {
+                Some(last)
+                    if reservation.address <= last.end()
+                        && (last.label.is_none() || reservation.label.is_none()) =>
+                {
+                    last.length = last.end().max(reservation.end()) - last.address;
+                    if last.label.is_none() {
+                        last.label = reservation.label;
+                    }
+                }
+                _ => merged.push(reservation),
+            }
+        }
+        merged
     }
 }
 
@@ -48,4 +102,38 @@ mod tests {
             "/memreserve/ 0x0000000000000000 0x0000000000100000;"
         );
     }
+
+    #[test]
+    fn test_reservation_coalesce() {
+        let reservations = vec![
+            Reservation::new(0x3000, 0x1000),
+            Reservation::new_with_label(0x0, 0x1000, "a"),
+            Reservation::new(0x1000, 0x800),
+            Reservation::new(0x800, 0x100),
+            Reservation::new_with_label(0x3800, 0x1000, "b"),
+            Reservation::new(0x5000, 0x0),
+        ];
+        assert_eq!(
+            Reservation::coalesce(reservations),
+            vec![
+                Reservation::new_with_label(0x0, 0x1800, "a"),
+                Reservation::new_with_label(0x3000, 0x1800, "b"),
+                Reservation::new(0x5000, 0x0),
+            ]
+        );
+
+        // The labelled reservations are kept apart
+        let reservations = vec![
+            Reservation::new_with_label(0x0, 0x1000, "a"),
+            Reservation::new_with_label(0x800, 0x1000, "b"),
+            Reservation::new(0x1000, 0x1000),
+        ];
+        assert_eq!(
+            Reservation::coalesce(reservations),
+            vec![
+                Reservation::new_with_label(0x0, 0x1000, "a"),
+                Reservation::new_with_label(0x800, 0x1800, "b"),
+            ]
+        );
+    }
 }
@@ -0,0 +1,427 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::node::{Node, NodeId};
+use crate::property::Property;
+use crate::utils::Utils;
+
+// The value compared by a predicate.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    // A glob matched against each string of a string list
+    String(String),
+    // Cells matched against the whole value
+    Cells(Vec<u32>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Predicate {
+    Exists(String),
+    Missing(String),
+    Equals(String, Value),
+    NotEquals(String, Value),
+}
+
+/// A selector of nodes and properties of a `DeviceTree`.
+///
+/// A selector has three optional parts, `PATH[PREDICATE]...::PROPERTY`:
+///   - `PATH` is a glob of the full path of the nodes, like
+///     `/soc/**/serial@*`. In a path component, `*` matches any characters
+///     and `?` matches one character. A `**` component matches any number of
+///     nodes. Without a path, all the nodes are selected, like `/**`.
+///   - Each `[PREDICATE]` further filters the nodes:
+///       - `[name]`: a property matching the glob `name` exists
+///       - `[!name]`: no property matches the glob `name`
+///       - `[name=value]`: a string of a matching property matches the glob
+///         `value`, so `[compatible=arm,pl011]` selects the nodes compatible
+///         with "arm,pl011". The value can be quoted with `"`.
+///       - `[name=<0x1 2>]`: a matching property holds exactly the cells
+///       - `[name!=value]`: the opposite of `[name=value]`
+///   - `::PROPERTY` is a glob of the property names to select from the nodes,
+///     like `::*-gpios`.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, Node, Property};
+///
+/// let mut root = Node::new("");
+/// let mut soc = Node::new("soc");
+/// let mut uart = Node::new("serial@9000000");
+/// uart.add_property(Property::new_strs("compatible", vec!["arm,pl011", "arm,primecell"]));
+/// uart.add_property(Property::new_str("status", "okay"));
+/// soc.add_sub_node(uart);
+/// let mut gpio = Node::new("gpio@9030000");
+/// gpio.add_property(Property::new_str("status", "disabled"));
+/// gpio.add_property(Property::new_u32s("reset-gpios", vec![1, 3, 0]));
+/// soc.add_sub_node(gpio);
+/// root.add_sub_node(soc);
+/// let tree = DeviceTree::new(vec![], root);
+///
+/// let uarts = tree.select_nodes("[compatible=arm,pl011]").unwrap();
+/// assert_eq!(tree.path_of(uarts[0]), "/soc/serial@9000000");
+///
+/// let okay = tree.select_nodes("/soc/**[status=okay]").unwrap();
+/// assert_eq!(okay, uarts);
+///
+/// let gpios = tree.select_properties("/**::*-gpios").unwrap();
+/// assert_eq!(gpios[0].1.name, "reset-gpios");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector {
+    path: Vec<String>,
+    predicates: Vec<Predicate>,
+    property: Option<String>,
+}
+
+impl Selector {
+    /// Parse a selector.
+    pub fn parse(selector: &str) -> Result<Selector, DeviceTreeError> {
+        let invalid = || DeviceTreeError::InvalidSelector(String::from(selector));
+
+        let (nodes, property) = match selector.find("::") {
+            Some(pos) => (&selector[..pos], Some(&selector[(pos + 2)..])),
+            None => (selector, None),
+        };
+        if property.is_some_and(|property| property.is_empty()) {
+            return Err(invalid());
+        }
+
+        let (path, mut predicates_text) = match nodes.find('[') {
+            Some(pos) => (&nodes[..pos], &nodes[pos..]),
+            None => (nodes, ""),
+        };
+        let path = match path {
+            "" => vec![String::from("**")],
+            path if path.starts_with('/') && !path.contains([']', '"']) => path
+                .split('/')
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect(),
+            _ => return Err(invalid()),
+        };
+
+        let mut predicates = vec![];
+        while !predicates_text.is_empty() {
+            let text = predicates_text.strip_prefix('[').ok_or_else(invalid)?;
+            let end = Selector::predicate_end(text).ok_or_else(invalid)?;
+            predicates.push(Selector::parse_predicate(&text[..end]).ok_or_else(invalid)?);
+            predicates_text = &text[(end + 1)..];
+        }
+
+        Ok(Selector {
+            path,
+            predicates,
+            property: property.map(String::from),
+        })
+    }
+
+    /// Check whether the selector has a `::PROPERTY` part.
+    pub fn selects_properties(&self) -> bool {
+        self.property.is_some()
+    }
+
+    // Find the "]" closing a predicate, skipping the quoted text.
+    fn predicate_end(text: &str) -> Option<usize> {
+        let mut quoted = false;
+        for (i, c) in text.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                ']' if !quoted => return Some(i),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn parse_predicate(text: &str) -> Option<Predicate> {
+        let text = text.trim();
+        if let Some((name, value)) = text.split_once("!=") {
+            let value = Selector::parse_value(value)?;
+            return Some(Predicate::NotEquals(Selector::parse_name(name)?, value));
+        }
+        if let Some((name, value)) = text.split_once('=') {
+            let value = Selector::parse_value(value)?;
+            return Some(Predicate::Equals(Selector::parse_name(name)?, value));
+        }
+        if let Some(name) = text.strip_prefix('!') {
+            return Some(Predicate::Missing(Selector::parse_name(name)?));
+        }
+        Some(Predicate::Exists(Selector::parse_name(text)?))
+    }
+
+    fn parse_name(name: &str) -> Option<String> {
+        let name = name.trim();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '"') {
+            return None;
+        }
+        Some(String::from(name))
+    }
+
+    fn parse_value(value: &str) -> Option<Value> {
+        let value = value.trim();
+        if let Some(cells) = value.strip_prefix('<') {
+            let cells = cells.strip_suffix('>')?;
+            let cells = cells
+                .split_whitespace()
+                .map(|cell| match cell.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => cell.parse::<u32>().ok(),
+                })
+                .collect::<Option<Vec<u32>>>()?;
+            return Some(Value::Cells(cells));
+        }
+        let value = match value.strip_prefix('"') {
+            Some(quoted) => quoted.strip_suffix('"')?,
+            None => value,
+        };
+        Some(Value::String(String::from(value)))
+    }
+
+    // Match the components of a path against the components of a path glob.
+    fn match_path(pattern: &[String], path: &[&str]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((first, rest)) if first == "**" => {
+                (0..=path.len()).any(|skip| Selector::match_path(rest, &path[skip..]))
+            }
+            Some((first, rest)) => match path.split_first() {
+                Some((name, path)) => {
+                    Utils::glob_match(first, name) && Selector::match_path(rest, path)
+                }
+                None => false,
+            },
+        }
+    }
+
+    fn match_value(prop: &Property, value: &Value) -> bool {
+        match value {
+            Value::Cells(cells) => {
+                let bytes: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+                prop.value == bytes
+            }
+            Value::String(pattern) => match prop.value.split_last() {
+                Some((0, strings)) => strings
+                    .split(|c| *c == 0)
+                    .any(|s| Utils::glob_match(pattern, &String::from_utf8_lossy(s))),
+                _ => false,
+            },
+        }
+    }
+
+    fn match_predicate(node: &Node, predicate: &Predicate) -> bool {
+        let props = |name: &str| {
+            node.properties
+                .iter()
+                .filter(move |prop| Utils::glob_match(name, &prop.name))
+                .collect::<Vec<&Property>>()
+        };
+        match predicate {
+            Predicate::Exists(name) => !props(name).is_empty(),
+            Predicate::Missing(name) => props(name).is_empty(),
+            Predicate::Equals(name, value) => props(name)
+                .iter()
+                .any(|prop| Selector::match_value(prop, value)),
+            Predicate::NotEquals(name, value) => !props(name)
+                .iter()
+                .any(|prop| Selector::match_value(prop, value)),
+        }
+    }
+
+    // Get the properties of a node selected by the property glob.
+    fn selected_properties<'a>(&self, node: &'a Node) -> impl Iterator<Item = &'a Property> {
+        let property = self.property.clone();
+        node.properties.iter().filter(move |prop| match &property {
+            Some(pattern) => Utils::glob_match(pattern, &prop.name),
+            None => true,
+        })
+    }
+
+    /// Get the nodes matching the selector, in depth-first order.
+    ///
+    /// If the selector selects properties, only the nodes having any of the
+    /// properties are returned.
+    pub fn select_nodes(&self, tree: &DeviceTree) -> Vec<NodeId> {
+        tree.iter_depth_first()
+            .filter(|(path, _, node)| {
+                let path: Vec<&str> = path.split('/').filter(|n| !n.is_empty()).collect();
+                Selector::match_path(&self.path, &path)
+                    && self
+                        .predicates
+                        .iter()
+                        .all(|predicate| Selector::match_predicate(node, predicate))
+                    && (self.property.is_none() || self.selected_properties(node).next().is_some())
+            })
+            .map(|(_, _, node)| node.id())
+            .collect()
+    }
+
+    /// Get the properties matching the selector, together with the nodes
+    /// having them.
+    ///
+    /// If the selector doesn't select properties, all the properties of the
+    /// matching nodes are returned.
+    pub fn select_properties<'a>(&self, tree: &'a DeviceTree) -> Vec<(NodeId, &'a Property)> {
+        self.select_nodes(tree)
+            .into_iter()
+            .flat_map(|id| {
+                self.selected_properties(tree.node(id))
+                    .map(move |prop| (id, prop))
+            })
+            .collect()
+    }
+
+    /// Generate the JSON text of the matches of the selector.
+    ///
+    /// The output is an object with a single `matches` array. If the selector
+    /// selects properties, each match has the `path` of the node, the `name`
+    /// of the property and the `value` like `PropertyValue::to_json`.
+    /// Otherwise each match has the `path` of a node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use devicetree_tool::{DeviceTree, Node, Property, Selector};
+    ///
+    /// let mut root = Node::new("");
+    /// let mut uart = Node::new("serial@9000000");
+    /// uart.add_property(Property::new_str("status", "okay"));
+    /// root.add_sub_node(uart);
+    /// let tree = DeviceTree::new(vec![], root);
+    ///
+    /// let selector = Selector::parse("[status=okay]").unwrap();
+    /// assert_eq!(selector.to_json(&tree), r#"{"matches":[{"path":"/serial@9000000"}]}"#);
+    ///
+    /// let selector = Selector::parse("/*::status").unwrap();
+    /// assert_eq!(
+    ///     selector.to_json(&tree),
+    ///     r#"{"matches":[{"path":"/serial@9000000","name":"status","value":{"type":"strings","value":["okay"]}}]}"#
+    /// );
+    /// ```
+    pub fn to_json(&self, tree: &DeviceTree) -> String {
+        let matches: Vec<String> = if self.selects_properties() {
+            self.select_properties(tree)
+                .into_iter()
+                .map(|(id, prop)| {
+                    format!(
+                        "{{\"path\":{},\"name\":{},\"value\":{}}}",
+                        Utils::json_string(&tree.path_of(id)),
+                        Utils::json_string(&prop.name),
+                        prop.typed_value().to_json()
+                    )
+                })
+                .collect()
+        } else {
+            self.select_nodes(tree)
+                .into_iter()
+                .map(|id| format!("{{\"path\":{}}}", Utils::json_string(&tree.path_of(id))))
+                .collect()
+        };
+        format!("{{\"matches\":[{}]}}", matches.join(","))
+    }
+}
+
+impl std::str::FromStr for Selector {
+    type Err = DeviceTreeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Selector::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector_parse() {
+        let selector =
+            Selector::parse("/soc/**/serial@*[status=\"ok]ay\"][!dma-coherent]::reg").unwrap();
+        assert_eq!(selector.path, vec!["soc", "**", "serial@*"]);
+        assert_eq!(
+            selector.predicates,
+            vec![
+                Predicate::Equals(String::from("status"), Value::String(String::from("ok]ay"))),
+                Predicate::Missing(String::from("dma-coherent")),
+            ]
+        );
+        assert_eq!(selector.property, Some(String::from("reg")));
+
+        let selector = Selector::parse("[reg=<0x0 16>]").unwrap();
+        assert_eq!(selector.path, vec!["**"]);
+        assert_eq!(
+            selector.predicates,
+            vec![Predicate::Equals(
+                String::from("reg"),
+                Value::Cells(vec![0, 16])
+            )]
+        );
+
+        for invalid in [
+            "soc",
+            "/soc[status",
+            "/soc[]",
+            "/soc::",
+            "/soc[reg=<0xg>]",
+            "/a]",
+        ] {
+            assert_eq!(
+                Selector::parse(invalid),
+                Err(DeviceTreeError::InvalidSelector(String::from(invalid)))
+            );
+        }
+    }
+
+    #[test]
+    fn test_selector_select() {
+        let dts = std::fs::read("test/dts_ori.dts").unwrap();
+        let tree = DeviceTree::from_dts_bytes(&dts);
+        let paths = |selector: &str| -> Vec<String> {
+            tree.select_nodes(selector)
+                .unwrap()
+                .into_iter()
+                .map(|id| tree.path_of(id))
+                .collect()
+        };
+
+        assert_eq!(paths("/"), vec!["/"]);
+        assert_eq!(paths("/cpus/cpu@?").len(), 4);
+        assert_eq!(paths("/cpus/*[reg=<0x0 0x2>]"), vec!["/cpus/cpu@2"]);
+        assert_eq!(paths("/**/msic"), vec!["/intc/msic"]);
+        assert_eq!(
+            paths("[compatible=arm,primecell]"),
+            vec!["/rtc@9010000", "/pl061@9020000", "/pl011@9000000"]
+        );
+        assert_eq!(
+            paths("[compatible=arm,gic-v3*][msi-controller]"),
+            vec!["/intc/msic"]
+        );
+        assert_eq!(paths("/*[#gpio-cells][clocks]"), vec!["/pl061@9020000"]);
+        assert_eq!(paths("/cpus/*[!reg]").len(), 0);
+        assert_eq!(
+            paths("/*[compatible!=arm,*][compatible]"),
+            vec!["/apb-pclk", "/gpio-keys", "/pci"]
+        );
+
+        let props: Vec<(String, String)> = tree
+            .select_properties("/**::*gpio*")
+            .unwrap()
+            .into_iter()
+            .map(|(id, prop)| (tree.path_of(id), prop.name.clone()))
+            .collect();
+        assert_eq!(
+            props,
+            vec![
+                (
+                    String::from("/pl061@9020000"),
+                    String::from("gpio-controller")
+                ),
+                (String::from("/pl061@9020000"), String::from("#gpio-cells")),
+                (String::from("/gpio-keys/poweroff"), String::from("gpios")),
+            ]
+        );
+        assert_eq!(paths("::msi-parent"), vec!["/pci"]);
+    }
+}
@@ -0,0 +1,677 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::cpu::{Cpu, CpuLocation, CpuTopology};
+use crate::devicetree::DeviceTree;
+use crate::error::DeviceTreeError;
+use crate::irq::{InterruptSpecifier, InterruptTrigger};
+use crate::node::{Node, NodeId};
+use crate::property::Property;
+
+// The size of the redistributor of a CPU in GICv3, with the RD_base and the
+// SGI_base frames
+const GICV3_REDIST_SIZE: u64 = 0x20000;
+// The CPUs in a cluster, limited by the target lists of the SGIs of GICv3
+const CLUSTER_SIZE: u32 = 16;
+// The PPIs of the secure, non-secure, virtual and hypervisor physical
+// timers
+const TIMER_PPIS: [u32; 4] = [13, 14, 11, 10];
+// The clock of the PrimeCell devices
+const APB_CLOCK_FREQUENCY: u32 = 24_000_000;
+// The size of the ECAM space of a PCI bus
+const ECAM_BUS_SIZE: u64 = 0x100000;
+const PL011_SIZE: u64 = 0x1000;
+const PL031_SIZE: u64 = 0x1000;
+const VIRTIO_MMIO_SIZE: u64 = 0x200;
+
+/// A PCIe host bridge with ECAM of a `VirtMachine`, like
+/// "pci-host-ecam-generic" of QEMU.
+///
+/// The memory windows are mapped 1:1 between the CPU and the PCI bus, and
+/// the I/O window is at PCI address 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PciHost {
+    /// The address and the size of the ECAM space, with 1MiB for every bus
+    pub ecam: (u64, u64),
+    /// The CPU address and the size of the I/O window
+    pub io: Option<(u64, u64)>,
+    /// The address and the size of the 32-bit memory window
+    pub mmio: (u64, u64),
+    /// The address and the size of the 64-bit memory window
+    pub mmio64: Option<(u64, u64)>,
+    /// The first of the 4 SPIs that INTA to INTD are swizzled to
+    pub irq: u32,
+}
+
+/// A device of a `VirtMachine`. The interrupts are the numbers of the SPIs
+/// of the GIC, starting from 0.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VirtDevice {
+    /// A PL011 UART
+    Pl011 { address: u64, irq: u32 },
+    /// A PL031 real time clock
+    Pl031 { address: u64, irq: u32 },
+    /// A virtio-mmio transport
+    VirtioMmio { address: u64, irq: u32 },
+    /// A PCIe host bridge
+    Pcie(PciHost),
+}
+
+/// The description of an arm64 virtual machine like the "virt" machine of
+/// QEMU, to generate its device tree with `DeviceTree::from_virt_machine`.
+///
+/// The tree has the vCPUs started by PSCI, the memory, a GICv3 with an
+/// optional ITS, the architected timer, the devices and "/chosen". The first
+/// PL011 is the "stdout-path". PSCI is described as "arm,psci-1.0" and
+/// "arm,psci-0.2" only, the PSCI 0.1 binding "arm,psci" needs the function
+/// IDs of the firmware, which are not known to the machine.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, VirtDevice, VirtMachine};
+///
+/// let mut machine = VirtMachine::new(2, 0x4000_0000);
+/// machine.add_device(VirtDevice::Pl011 { address: 0x900_0000, irq: 1 });
+/// machine.bootargs = Some(String::from("console=ttyAMA0"));
+///
+/// let tree = DeviceTree::from_virt_machine(&machine).unwrap();
+/// let uart = tree.find_node_by_path("/pl011@9000000").unwrap();
+/// assert_eq!(tree.reg(uart), Ok(vec![(0x900_0000, 0x1000)]));
+/// assert_eq!(tree.cpu_topology().unwrap().cpus.len(), 2);
+///
+/// let chosen = tree.node(tree.find_node_by_path("/chosen").unwrap());
+/// assert_eq!(chosen.read_string("stdout-path"), Ok("/pl011@9000000"));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtMachine {
+    pub vcpus: u32,
+    /// The "compatible" of the vCPUs
+    pub cpu_compatible: String,
+    /// The "method" of PSCI, "hvc" or "smc"
+    pub psci_method: String,
+    /// The addresses and the sizes of the memory regions
+    pub memory: Vec<(u64, u64)>,
+    /// The address and the size of the GIC distributor
+    pub gic_distributor: (u64, u64),
+    /// The address and the size of the region of the GIC redistributors,
+    /// with 128KiB for every vCPU
+    pub gic_redistributors: (u64, u64),
+    /// The address and the size of the ITS, for the MSIs of PCIe
+    pub gic_its: Option<(u64, u64)>,
+    pub devices: Vec<VirtDevice>,
+    /// The kernel command line, "bootargs" of "/chosen"
+    pub bootargs: Option<String>,
+    /// The address and the size of the initrd
+    pub initrd: Option<(u64, u64)>,
+}
+
+impl VirtMachine {
+    /// Create a machine without devices, with the memory map of the "virt"
+    /// machine of QEMU: the memory at 1GiB and the GICv3 with an ITS at
+    /// 128MiB.
+    pub fn new(vcpus: u32, memory_size: u64) -> Self {
+        VirtMachine {
+            vcpus,
+            cpu_compatible: String::from("arm,arm-v8"),
+            psci_method: String::from("hvc"),
+            memory: vec![(0x4000_0000, memory_size)],
+            gic_distributor: (0x800_0000, 0x10000),
+            gic_redistributors: (0x80a_0000, 0xf6_0000),
+            gic_its: Some((0x808_0000, 0x20000)),
+            devices: vec![],
+            bootargs: None,
+            initrd: None,
+        }
+    }
+
+    /// Add a device.
+    pub fn add_device(&mut self, device: VirtDevice) {
+        self.devices.push(device);
+    }
+
+    fn invalid(message: String) -> DeviceTreeError {
+        DeviceTreeError::InvalidMachine(message)
+    }
+
+    // Check that the vCPUs fit the MPIDRs and the redistributors, and that
+    // the initrd and the PCIe windows fit their address spaces.
+    fn validate(&self) -> Result<(), DeviceTreeError> {
+        if self.vcpus == 0 || self.vcpus > CLUSTER_SIZE * 256 {
+            return Err(VirtMachine::invalid(format!("{} vcpus", self.vcpus)));
+        }
+        if self.memory.is_empty() {
+            return Err(VirtMachine::invalid(String::from("no memory")));
+        }
+        if self.gic_redistributors.1 < self.vcpus as u64 * GICV3_REDIST_SIZE {
+            return Err(VirtMachine::invalid(format!(
+                "redistributors of {} vcpus don't fit {:#x}",
+                self.vcpus, self.gic_redistributors.1
+            )));
+        }
+        let end = |(address, size): (u64, u64)| address.checked_add(size);
+        if let Some(initrd) = self.initrd {
+            if end(initrd).is_none() {
+                return Err(VirtMachine::invalid(format!(
+                    "initrd size {:#x} at {:#x}",
+                    initrd.1, initrd.0
+                )));
+            }
+        }
+        for device in &self.devices {
+            let VirtDevice::Pcie(host) = device else {
+                continue;
+            };
+            let (ecam, size) = host.ecam;
+            let buses = size / ECAM_BUS_SIZE;
+            if size % ECAM_BUS_SIZE != 0 || !(1..=256).contains(&buses) || end(host.ecam).is_none()
+            {
+                return Err(VirtMachine::invalid(format!(
+                    "ECAM size {size:#x} at {ecam:#x}"
+                )));
+            }
+            // The I/O window is at PCI address 0 of the 32-bit I/O space
+            if let Some(io) = host.io {
+                if io.1 > 1 << 32 || end(io).is_none() {
+                    return Err(VirtMachine::invalid(format!("I/O window at {:#x}", io.0)));
+                }
+            }
+            if end(host.mmio).is_none_or(|end| end > 1 << 32) {
+                return Err(VirtMachine::invalid(format!(
+                    "32-bit memory window at {:#x}",
+                    host.mmio.0
+                )));
+            }
+            if let Some(mmio64) = host.mmio64 {
+                if end(mmio64).is_none() {
+                    return Err(VirtMachine::invalid(format!(
+                        "64-bit memory window at {:#x}",
+                        mmio64.0
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Get the MPIDR of a vCPU, with 16 vCPUs in every cluster.
+    fn mpidr(vcpu: u32) -> u64 {
+        (((vcpu / CLUSTER_SIZE) << 8) | (vcpu % CLUSTER_SIZE)) as u64
+    }
+
+    fn add_node(tree: &mut DeviceTree, name: &str, properties: Vec<Property>) -> NodeId {
+        let mut node = Node::new(name);
+        for property in properties {
+            node.add_property(property);
+        }
+        tree.add_sub_node(tree.root(), node)
+    }
+
+    pub(crate) fn build(&self) -> Result<DeviceTree, DeviceTreeError> {
+        self.validate()?;
+        let mut root = Node::new("");
+        root.add_property(Property::new_str("compatible", "linux,dummy-virt"));
+        root.add_property(Property::new_str("model", "linux,dummy-virt"));
+        root.add_property(Property::new_u32("#address-cells", 2));
+        root.add_property(Property::new_u32("#size-cells", 2));
+        let mut tree = DeviceTree::new(vec![], root);
+
+        self.write_chosen(&mut tree);
+        self.write_cpus(&mut tree)?;
+        for (address, size) in &self.memory {
+            let memory = VirtMachine::add_node(
+                &mut tree,
+                &format!("memory@{address:x}"),
+                vec![Property::new_str("device_type", "memory")],
+            );
+            tree.set_reg(memory, &[(*address, *size)])?;
+        }
+        let (gic, its) = self.write_gic(&mut tree)?;
+        self.write_timer(&mut tree, gic)?;
+
+        let mut clock = None;
+        let mut domain = 0;
+        for device in &self.devices {
+            match device {
+                VirtDevice::Pl011 { address, irq } => {
+                    let clock = VirtMachine::write_clock(&mut tree, &mut clock);
+                    let uart = VirtMachine::add_node(
+                        &mut tree,
+                        &format!("pl011@{address:x}"),
+                        vec![
+                            Property::new_strs("compatible", vec!["arm,pl011", "arm,primecell"]),
+                            Property::new_u32s("clocks", vec![clock, clock]),
+                            Property::new_strs("clock-names", vec!["uartclk", "apb_pclk"]),
+                        ],
+                    );
+                    VirtMachine::write_device(&mut tree, uart, gic, (*address, PL011_SIZE), *irq)?;
+                }
+                VirtDevice::Pl031 { address, irq } => {
+                    let clock = VirtMachine::write_clock(&mut tree, &mut clock);
+                    let rtc = VirtMachine::add_node(
+                        &mut tree,
+                        &format!("pl031@{address:x}"),
+                        vec![
+                            Property::new_strs("compatible", vec!["arm,pl031", "arm,primecell"]),
+                            Property::new_u32("clocks", clock),
+                            Property::new_str("clock-names", "apb_pclk"),
+                        ],
+                    );
+                    VirtMachine::write_device(&mut tree, rtc, gic, (*address, PL031_SIZE), *irq)?;
+                }
+                VirtDevice::VirtioMmio { address, irq } => {
+                    let virtio = VirtMachine::add_node(
+                        &mut tree,
+                        &format!("virtio_mmio@{address:x}"),
+                        vec![
+                            Property::new_str("compatible", "virtio,mmio"),
+                            Property::new_empty("dma-coherent"),
+                        ],
+                    );
+                    tree.set_reg(virtio, &[(*address, VIRTIO_MMIO_SIZE)])?;
+                    let irq = InterruptSpecifier::GicSpi {
+                        number: *irq,
+                        trigger: InterruptTrigger::EdgeRising,
+                    };
+                    tree.set_interrupts(virtio, gic, &[irq])?;
+                }
+                VirtDevice::Pcie(host) => {
+                    VirtMachine::write_pcie(&mut tree, host, domain, gic, its)?;
+                    domain += 1;
+                }
+            }
+        }
+        Ok(tree)
+    }
+
+    fn write_chosen(&self, tree: &mut DeviceTree) {
+        let mut properties = vec![];
+        if let Some(bootargs) = &self.bootargs {
+            properties.push(Property::new_str("bootargs", bootargs));
+        }
+        let uart = self.devices.iter().find_map(|device| match device {
+            VirtDevice::Pl011 { address, .. } => Some(format!("/pl011@{address:x}")),
+            _ => None,
+        });
+        if let Some(uart) = uart {
+            properties.push(Property::new_str("stdout-path", &uart));
+        }
+        if let Some((address, size)) = self.initrd {
+            properties.push(Property::new_u64("linux,initrd-start", address));
+            properties.push(Property::new_u64("linux,initrd-end", address + size));
+        }
+        VirtMachine::add_node(tree, "chosen", properties);
+    }
+
+    fn write_cpus(&self, tree: &mut DeviceTree) -> Result<(), DeviceTreeError> {
+        VirtMachine::add_node(
+            tree,
+            "psci",
+            vec![
+                Property::new_strs("compatible", vec!["arm,psci-1.0", "arm,psci-0.2"]),
+                Property::new_str("method", &self.psci_method),
+            ],
+        );
+        let mut topology = CpuTopology::new(1);
+        for vcpu in 0..self.vcpus {
+            let mut cpu = Cpu::new(VirtMachine::mpidr(vcpu));
+            cpu.compatible = Some(self.cpu_compatible.clone());
+            cpu.enable_method = Some(String::from("psci"));
+            cpu.location = Some(CpuLocation::new(vcpu / CLUSTER_SIZE, vcpu % CLUSTER_SIZE));
+            topology.add_cpu(cpu);
+        }
+        tree.set_cpu_topology(&topology)?;
+        Ok(())
+    }
+
+    // Write the GICv3 and its ITS, and make it the interrupt parent of the
+    // tree.
+    fn write_gic(
+        &self,
+        tree: &mut DeviceTree,
+    ) -> Result<(NodeId, Option<NodeId>), DeviceTreeError> {
+        let gic = VirtMachine::add_node(
+            tree,
+            &format!("intc@{:x}", self.gic_distributor.0),
+            vec![
+                Property::new_str("compatible", "arm,gic-v3"),
+                Property::new_u32("#interrupt-cells", 3),
+                Property::new_empty("interrupt-controller"),
+                Property::new_u32("#redistributor-regions", 1),
+                Property::new_u32("#address-cells", 2),
+                Property::new_u32("#size-cells", 2),
+                Property::new_empty("ranges"),
+            ],
+        );
+        tree.set_reg(gic, &[self.gic_distributor, self.gic_redistributors])?;
+        let phandle = tree.ensure_phandle(gic);
+        let root = tree.root();
+        tree.node_mut(root)
+            .add_property(Property::new_u32("interrupt-parent", phandle));
+
+        let Some((address, size)) = self.gic_its else {
+            return Ok((gic, None));
+        };
+        let mut node = Node::new(&format!("its@{address:x}"));
+        node.add_property(Property::new_str("compatible", "arm,gic-v3-its"));
+        node.add_property(Property::new_empty("msi-controller"));
+        node.add_property(Property::new_u32("#msi-cells", 1));
+        let its = tree.add_sub_node(gic, node);
+        tree.set_reg(its, &[(address, size)])?;
+        Ok((gic, Some(its)))
+    }
+
+    fn write_timer(&self, tree: &mut DeviceTree, gic: NodeId) -> Result<(), DeviceTreeError> {
+        let timer = VirtMachine::add_node(
+            tree,
+            "timer",
+            vec![
+                Property::new_strs("compatible", vec!["arm,armv8-timer", "arm,armv7-timer"]),
+                Property::new_empty("always-on"),
+            ],
+        );
+        let irqs: Vec<InterruptSpecifier> = TIMER_PPIS
+            .iter()
+            .map(|number| InterruptSpecifier::GicPpi {
+                number: *number,
+                trigger: InterruptTrigger::LevelHigh,
+                cpus: 0,
+            })
+            .collect();
+        tree.set_interrupts(timer, gic, &irqs)
+    }
+
+    // Get the phandle of the APB clock, which is added for the first
+    // PrimeCell device.
+    fn write_clock(tree: &mut DeviceTree, clock: &mut Option<u32>) -> u32 {
+        if let Some(phandle) = clock {
+            return *phandle;
+        }
+        let id = VirtMachine::add_node(
+            tree,
+            "apb-pclk",
+            vec![
+                Property::new_str("compatible", "fixed-clock"),
+                Property::new_u32("#clock-cells", 0),
+                Property::new_u32("clock-frequency", APB_CLOCK_FREQUENCY),
+                Property::new_str("clock-output-names", "clk24mhz"),
+            ],
+        );
+        *clock.insert(tree.ensure_phandle(id))
+    }
+
+    fn write_device(
+        tree: &mut DeviceTree,
+        id: NodeId,
+        gic: NodeId,
+        reg: (u64, u64),
+        irq: u32,
+    ) -> Result<(), DeviceTreeError> {
+        tree.set_reg(id, &[reg])?;
+        let irq = InterruptSpecifier::GicSpi {
+            number: irq,
+            trigger: InterruptTrigger::LevelHigh,
+        };
+        tree.set_interrupts(id, gic, &[irq])
+    }
+
+    fn write_pcie(
+        tree: &mut DeviceTree,
+        host: &PciHost,
+        domain: u32,
+        gic: NodeId,
+        its: Option<NodeId>,
+    ) -> Result<(), DeviceTreeError> {
+        let (ecam, size) = host.ecam;
+        let buses = (size / ECAM_BUS_SIZE) as u32;
+        let pcie = VirtMachine::add_node(
+            tree,
+            &format!("pcie@{ecam:x}"),
+            vec![
+                Property::new_str("compatible", "pci-host-ecam-generic"),
+                Property::new_str("device_type", "pci"),
+                Property::new_u32("#address-cells", 3),
+                Property::new_u32("#size-cells", 2),
+                Property::new_u32("linux,pci-domain", domain),
+                Property::new_u32s("bus-range", vec![0, buses - 1]),
+                Property::new_empty("dma-coherent"),
+            ],
+        );
+        tree.set_reg(pcie, &[host.ecam])?;
+
+        // The first cell of a PCI address has the space code: 1 for I/O, 2
+        // for 32-bit memory and 3 for 64-bit memory
+        let split = |n: u64| [(n >> 32) as u32, n as u32];
+        let mut windows = vec![];
+        if let Some((address, size)) = host.io {
+            windows.push((0x100_0000, 0, address, size));
+        }
+        windows.push((0x200_0000, host.mmio.0, host.mmio.0, host.mmio.1));
+        if let Some((address, size)) = host.mmio64 {
+            windows.push((0x300_0000, address, address, size));
+        }
+        let mut ranges = vec![];
+        for (space, pci, cpu, size) in windows {
+            ranges.push(space);
+            ranges.extend(split(pci));
+            ranges.extend(split(cpu));
+            ranges.extend(split(size));
+        }
+
+        // INTx of slot `s` pin `p` goes to SPI `irq + (s + p - 1) % 4`, and
+        // the parent unit address in the map is 0
+        let gic_phandle = tree.ensure_phandle(gic);
+        let parent_address = vec![0; tree.node(gic).read_u32("#address-cells")? as usize];
+        let mut map = vec![];
+        for slot in 0..4 {
+            for pin in 0..4 {
+                let irq = InterruptSpecifier::GicSpi {
+                    number: host.irq + (slot + pin) % 4,
+                    trigger: InterruptTrigger::LevelHigh,
+                };
+                map.extend([slot << 11, 0, 0, pin + 1, gic_phandle]);
+                map.extend(&parent_address);
+                map.extend(tree.encode_interrupt(gic, &irq)?);
+            }
+        }
+
+        let mut properties = vec![
+            Property::new_u32s("ranges", ranges),
+            Property::new_u32("#interrupt-cells", 1),
+            Property::new_u32s("interrupt-map-mask", vec![0x1800, 0, 0, 7]),
+            Property::new_u32s("interrupt-map", map),
+        ];
+        if let Some(its) = its {
+            let its = tree.ensure_phandle(its);
+            properties.push(Property::new_u32s("msi-map", vec![0, its, 0, 0x10000]));
+        }
+        let mut node = tree.node_mut(pcie);
+        for property in properties {
+            node.add_property(property);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{DiffOptions, TreeDiff};
+    use crate::phandle::PhandleArgs;
+
+    fn qemu_machine() -> VirtMachine {
+        let mut machine = VirtMachine::new(4, 0x4000_0000);
+        machine.cpu_compatible = String::from("arm,cortex-a57");
+        machine.add_device(VirtDevice::Pl011 {
+            address: 0x900_0000,
+            irq: 1,
+        });
+        machine.add_device(VirtDevice::Pl031 {
+            address: 0x901_0000,
+            irq: 2,
+        });
+        for i in 0..2 {
+            machine.add_device(VirtDevice::VirtioMmio {
+                address: 0xa00_0000 + i * 0x200,
+                irq: 16 + i as u32,
+            });
+        }
+        machine.add_device(VirtDevice::Pcie(PciHost {
+            ecam: (0x40_1000_0000, 0x1000_0000),
+            io: Some((0x3eff_0000, 0x10000)),
+            mmio: (0x1000_0000, 0x2eff_0000),
+            mmio64: Some((0x80_0000_0000, 0x80_0000_0000)),
+            irq: 3,
+        }));
+        machine.bootargs = Some(String::from("console=ttyAMA0 root=/dev/vda"));
+        machine.initrd = Some((0x4800_0000, 0x100_0000));
+        machine
+    }
+
+    // The fixtures are written by hand after the layout of hw/arm/virt.c of
+    // QEMU, they are not dumps of QEMU, see the comments in the files.
+    fn assert_same(tree: &DeviceTree, path: &str) {
+        let dts = std::fs::read(path).unwrap();
+        let expected = DeviceTree::from_dts_bytes(&dts);
+        let options = DiffOptions {
+            ignore_order: true,
+            ..Default::default()
+        };
+        let diff = TreeDiff::from_trees(&expected, tree, options);
+        assert!(diff.is_empty(), "{}", diff);
+    }
+
+    #[test]
+    fn test_virt_machine_qemu() {
+        let tree = DeviceTree::from_virt_machine(&qemu_machine()).unwrap();
+        assert_same(&tree, "test/virt_qemu.dts");
+
+        // The generated tree survives a round trip through DTB
+        let dtb = DeviceTree::from_dtb_bytes(&tree.generate_dtb());
+        assert_same(&dtb, "test/virt_qemu.dts");
+
+        // INTA of slot 1 is swizzled to INTB, which is SPI 4
+        let gic = tree.find_node_by_path("/intc@8000000").unwrap();
+        let its = tree.find_node_by_path("/intc@8000000/its@8080000").unwrap();
+        let pcie = tree.find_node_by_path("/pcie@4010000000").unwrap();
+        let irq = tree
+            .parse_pci_interrupt(pcie, &[(0, 1, 0)], 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(irq.node, gic);
+        assert_eq!(irq.args, vec![0, 4, 4]);
+        assert_eq!(
+            tree.msi_map(pcie, 0x8, None),
+            Ok(Some(PhandleArgs {
+                node: its,
+                args: vec![0x8]
+            }))
+        );
+    }
+
+    #[test]
+    fn test_virt_machine_minimal() {
+        let mut machine = VirtMachine::new(1, 0x2000_0000);
+        machine.memory.push((0x1_0000_0000, 0x2000_0000));
+        machine.psci_method = String::from("smc");
+        machine.gic_its = None;
+        let tree = DeviceTree::from_virt_machine(&machine).unwrap();
+        assert_same(&tree, "test/virt_minimal.dts");
+    }
+
+    #[test]
+    fn test_virt_machine_invalid() {
+        let mut machine = VirtMachine::new(0, 0x2000_0000);
+        assert_eq!(
+            DeviceTree::from_virt_machine(&machine).err(),
+            Some(DeviceTreeError::InvalidMachine(String::from("0 vcpus")))
+        );
+
+        // The default redistributor region fits 123 vCPUs
+        machine.vcpus = 124;
+        assert_eq!(
+            DeviceTree::from_virt_machine(&machine).err(),
+            Some(DeviceTreeError::InvalidMachine(String::from(
+                "redistributors of 124 vcpus don't fit 0xf60000"
+            )))
+        );
+        machine.vcpus = 123;
+        let tree = DeviceTree::from_virt_machine(&machine).unwrap();
+        let topology = tree.cpu_topology().unwrap();
+        assert_eq!(topology.cpus[122].hwid, 0x70a);
+        assert_eq!(topology.cpus[122].location, Some(CpuLocation::new(7, 10)));
+
+        machine.add_device(VirtDevice::Pcie(PciHost {
+            ecam: (0x3f00_0000, 0x80000),
+            io: None,
+            mmio: (0x1000_0000, 0x2eff_0000),
+            mmio64: None,
+            irq: 3,
+        }));
+        assert_eq!(
+            DeviceTree::from_virt_machine(&machine).err(),
+            Some(DeviceTreeError::InvalidMachine(String::from(
+                "ECAM size 0x80000 at 0x3f000000"
+            )))
+        );
+
+        // The windows must not wrap around the address spaces
+        let host = PciHost {
+            ecam: (0xffff_ffff_f000_0000, 0x1000_0000),
+            io: None,
+            mmio: (0x1000_0000, 0x2eff_0000),
+            mmio64: None,
+            irq: 3,
+        };
+        let windows = [
+            (host.clone(), "ECAM size 0x10000000 at 0xfffffffff0000000"),
+            (
+                PciHost {
+                    ecam: (0x3f00_0000, 0x100_0000),
+                    io: Some((0x3eff_0000, 0x1_0000_0000_0000)),
+                    ..host.clone()
+                },
+                "I/O window at 0x3eff0000",
+            ),
+            (
+                PciHost {
+                    ecam: (0x3f00_0000, 0x100_0000),
+                    mmio: (0xffff_ffff_ffff_0000, 0x2_0000),
+                    ..host.clone()
+                },
+                "32-bit memory window at 0xffffffffffff0000",
+            ),
+            (
+                PciHost {
+                    ecam: (0x3f00_0000, 0x100_0000),
+                    mmio64: Some((0x80_0000_0000, u64::MAX)),
+                    ..host.clone()
+                },
+                "64-bit memory window at 0x8000000000",
+            ),
+        ];
+        for (host, message) in windows {
+            machine.devices.pop();
+            machine.add_device(VirtDevice::Pcie(host));
+            assert_eq!(
+                DeviceTree::from_virt_machine(&machine).err(),
+                Some(DeviceTreeError::InvalidMachine(String::from(message)))
+            );
+        }
+        machine.devices.pop();
+
+        machine.initrd = Some((0x4800_0000, u64::MAX));
+        assert_eq!(
+            DeviceTree::from_virt_machine(&machine).err(),
+            Some(DeviceTreeError::InvalidMachine(String::from(
+                "initrd size 0xffffffffffffffff at 0x48000000"
+            )))
+        );
+
+        machine.memory.clear();
+        assert_eq!(
+            DeviceTree::from_virt_machine(&machine).err(),
+            Some(DeviceTreeError::InvalidMachine(String::from("no memory")))
+        );
+    }
+}
@@ -0,0 +1,448 @@
+// Copyright (c) 2023, Michael Zhao
+// SPDX-License-Identifier: MIT
+
+use crate::devicetree::DeviceTree;
+use crate::node::{Node, NodeId, NodeRef};
+use crate::property::Property;
+use std::collections::VecDeque;
+
+/// What to do after a `Visitor` or `VisitorMut` enters a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisitAction {
+    /// Visit the sub nodes of the node.
+    Continue,
+    /// Don't visit the sub nodes of the node, `leave()` is still called for the node.
+    SkipSubtree,
+    /// Stop the walk, no more `enter()` or `leave()` is called.
+    Stop,
+}
+
+/// A visitor of the nodes of a `DeviceTree`, used with `DeviceTree::walk()`.
+///
+/// The nodes are visited in depth-first order. `enter()` is called for a
+/// node before its sub nodes, and `leave()` after them. The depth of the
+/// root node is 0.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, Node, NodeRef, VisitAction, Visitor};
+///
+/// // Collect the paths of the nodes, but not the nodes under "/cpus"
+/// struct Paths(Vec<String>);
+///
+/// impl Visitor for Paths {
+///     fn enter(&mut self, path: &str, _depth: usize, node: NodeRef) -> VisitAction {
+///         self.0.push(String::from(path));
+///         if node.name == "cpus" {
+///             VisitAction::SkipSubtree
+///         } else {
+///             VisitAction::Continue
+///         }
+///     }
+/// }
+///
+/// let mut root = Node::new("");
+/// let mut cpus = Node::new("cpus");
+/// cpus.add_sub_node(Node::new("cpu@0"));
+/// root.add_sub_node(cpus);
+/// root.add_sub_node(Node::new("memory"));
+/// let tree = DeviceTree::new(vec![], root);
+///
+/// let mut paths = Paths(vec![]);
+/// tree.walk(&mut paths);
+///
+/// assert_eq!(paths.0, vec!["/", "/cpus", "/memory"]);
+/// ```
+pub trait Visitor {
+    /// Called when the walk enters a node.
+    fn enter(&mut self, path: &str, depth: usize, node: NodeRef) -> VisitAction {
+        let _ = (path, depth, node);
+        VisitAction::Continue
+    }
+
+    /// Called when the walk leaves a node.
+    fn leave(&mut self, path: &str, depth: usize, node: NodeRef) {
+        let _ = (path, depth, node);
+    }
+}
+
+/// A visitor that can modify the nodes of a `DeviceTree`, used with
+/// `DeviceTree::walk_mut()`.
+///
+/// The nodes are visited in the same order as `Visitor`. The properties, name
+/// and label of a node can be changed, the sub nodes of the node are decided
+/// by the tree and can't be changed through `sub_nodes`. The path of the sub
+/// nodes follows the name of the node after `enter()` returns.
+///
+/// # Example
+///
+/// ```
+/// use devicetree_tool::{DeviceTree, Node, Property, VisitAction, VisitorMut};
+///
+/// // Disable all the nodes with a "status" property
+/// struct Disable;
+///
+/// impl VisitorMut for Disable {
+///     fn enter(&mut self, _path: &str, _depth: usize, node: &mut Node) -> VisitAction {
+///         if node.find_property("status").is_some() {
+///             node.set_property(Property::new_str("status", "disabled"));
+///         }
+///         VisitAction::Continue
+///     }
+/// }
+///
+/// let mut root = Node::new("");
+/// let mut uart = Node::new("uart@0");
+/// uart.add_property(Property::new_str("status", "okay"));
+/// root.add_sub_node(uart);
+/// let mut tree = DeviceTree::new(vec![], root);
+///
+/// tree.walk_mut(&mut Disable);
+///
+/// let uart = tree.node(tree.find_node_by_path("/uart@0").unwrap());
+/// assert_eq!(uart.find_property("status").unwrap().value, b"disabled\0".to_vec());
+/// ```
+pub trait VisitorMut {
+    /// Called when the walk enters a node.
+    fn enter(&mut self, path: &str, depth: usize, node: &mut Node) -> VisitAction {
+        let _ = (path, depth, node);
+        VisitAction::Continue
+    }
+
+    /// Called when the walk leaves a node.
+    fn leave(&mut self, path: &str, depth: usize, node: &mut Node) {
+        let _ = (path, depth, node);
+    }
+}
+
+// Get the path of a sub node from the path of its parent.
+pub(crate) fn sub_path(path: &str, name: &str) -> String {
+    if path == "/" {
+        format!("/{name}")
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+/// An iterator over the nodes of a subtree in depth-first order.
+///
+/// Created with `DeviceTree::iter_depth_first()`, it yields the path, the
+/// depth and the node.
+pub struct DepthFirstIter<'a> {
+    tree: &'a DeviceTree,
+    stack: Vec<(String, usize, NodeId)>,
+}
+
+impl<'a> DepthFirstIter<'a> {
+    pub(crate) fn new(tree: &'a DeviceTree, id: NodeId) -> Self {
+        DepthFirstIter {
+            tree,
+            stack: vec![(tree.path_of(id), 0, id)],
+        }
+    }
+}
+
+impl<'a> Iterator for DepthFirstIter<'a> {
+    type Item = (String, usize, NodeRef<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, depth, id) = self.stack.pop()?;
+        for child in self.tree.children(id).iter().rev() {
+            let child_path = sub_path(&path, &self.tree.node(*child).name);
+            self.stack.push((child_path, depth + 1, *child));
+        }
+        Some((path, depth, self.tree.node_ref(id)))
+    }
+}
+
+/// An iterator over the nodes of a subtree in breadth-first order.
+///
+/// Created with `DeviceTree::iter_breadth_first()`, it yields the path, the
+/// depth and the node.
+pub struct BreadthFirstIter<'a> {
+    tree: &'a DeviceTree,
+    queue: VecDeque<(String, usize, NodeId)>,
+}
+
+impl<'a> BreadthFirstIter<'a> {
+    pub(crate) fn new(tree: &'a DeviceTree, id: NodeId) -> Self {
+        BreadthFirstIter {
+            tree,
+            queue: VecDeque::from([(tree.path_of(id), 0, id)]),
+        }
+    }
+}
+
+impl<'a> Iterator for BreadthFirstIter<'a> {
+    type Item = (String, usize, NodeRef<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, depth, id) = self.queue.pop_front()?;
+        for child in self.tree.children(id) {
+            let child_path = sub_path(&path, &self.tree.node(*child).name);
+            self.queue.push_back((child_path, depth + 1, *child));
+        }
+        Some((path, depth, self.tree.node_ref(id)))
+    }
+}
+
+/// An iterator over the properties of all the nodes of a tree.
+///
+/// Created with `DeviceTree::iter_properties()`, it yields the path of the
+/// node and the property. The nodes are visited in depth-first order, and
+/// the properties of a node in order.
+pub struct PropertyIter<'a> {
+    nodes: DepthFirstIter<'a>,
+    current: Option<(String, std::slice::Iter<'a, Property>)>,
+}
+
+impl<'a> PropertyIter<'a> {
+    pub(crate) fn new(nodes: DepthFirstIter<'a>) -> Self {
+        PropertyIter {
+            nodes,
+            current: None,
+        }
+    }
+}
+
+impl<'a> Iterator for PropertyIter<'a> {
+    type Item = (String, &'a Property);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((path, props)) = &mut self.current {
+                if let Some(prop) = props.next() {
+                    return Some((path.clone(), prop));
+                }
+            }
+            let (path, _, node) = self.nodes.next()?;
+            let tree = node.tree();
+            self.current = Some((path, tree.node(node.id()).properties.iter()));
+        }
+    }
+}
+
+// Walk a subtree with a visitor, return false if the walk is stopped.
+pub(crate) fn walk<V: Visitor + ?Sized>(
+    tree: &DeviceTree,
+    id: NodeId,
+    path: &str,
+    depth: usize,
+    visitor: &mut V,
+) -> bool {
+    match visitor.enter(path, depth, tree.node_ref(id)) {
+        VisitAction::Stop => return false,
+        VisitAction::SkipSubtree => {}
+        VisitAction::Continue => {
+            for child in tree.children(id) {
+                let child_path = sub_path(path, &tree.node(*child).name);
+                if !walk(tree, *child, &child_path, depth + 1, visitor) {
+                    return false;
+                }
+            }
+        }
+    }
+    visitor.leave(path, depth, tree.node_ref(id));
+    true
+}
+
+// Walk a subtree with a mutable visitor, return false if the walk is stopped.
+pub(crate) fn walk_mut<V: VisitorMut + ?Sized>(
+    tree: &mut DeviceTree,
+    id: NodeId,
+    path: &str,
+    depth: usize,
+    visitor: &mut V,
+) -> bool {
+    let action = visitor.enter(path, depth, &mut tree.node_mut(id));
+    match action {
+        VisitAction::Stop => return false,
+        VisitAction::SkipSubtree => {}
+        VisitAction::Continue => {
+            // The name of the node may be changed by the visitor
+            let path = match tree.parent(id) {
+                Some(_) => tree.path_of(id),
+                None => String::from(path),
+            };
+            for child in tree.children(id).to_vec() {
+                let child_path = sub_path(&path, &tree.node(child).name);
+                if !walk_mut(tree, child, &child_path, depth + 1, visitor) {
+                    return false;
+                }
+            }
+        }
+    }
+    visitor.leave(path, depth, &mut tree.node_mut(id));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Recorder {
+        events: Vec<String>,
+        skip: &'static str,
+        stop: &'static str,
+    }
+
+    impl Visitor for Recorder {
+        fn enter(&mut self, path: &str, depth: usize, node: NodeRef) -> VisitAction {
+            self.events.push(format!("enter {path} {depth}"));
+            if node.name == self.stop {
+                VisitAction::Stop
+            } else if node.name == self.skip {
+                VisitAction::SkipSubtree
+            } else {
+                VisitAction::Continue
+            }
+        }
+
+        fn leave(&mut self, path: &str, _depth: usize, _node: NodeRef) {
+            self.events.push(format!("leave {path}"));
+        }
+    }
+
+    // A tree of "/", "/cpus", "/cpus/cpu@0", "/cpus/cpu@1", "/soc", "/soc/uart@0"
+    fn small_tree() -> DeviceTree {
+        let mut root = Node::new("");
+        root.add_property(Property::new_u32("#address-cells", 2));
+        let mut cpus = Node::new("cpus");
+        cpus.add_sub_node(Node::new("cpu@0"));
+        cpus.add_sub_node(Node::new("cpu@1"));
+        root.add_sub_node(cpus);
+        let mut soc = Node::new("soc");
+        let mut uart = Node::new("uart@0");
+        uart.add_property(Property::new_str("status", "okay"));
+        uart.add_property(Property::new_u32("reg", 0));
+        soc.add_sub_node(uart);
+        root.add_sub_node(soc);
+        DeviceTree::new(vec![], root)
+    }
+
+    #[test]
+    fn test_visit_iterators() {
+        let tree = small_tree();
+
+        let dfs: Vec<(String, usize)> = tree
+            .iter_depth_first()
+            .map(|(path, depth, _)| (path, depth))
+            .collect();
+        assert_eq!(
+            dfs,
+            vec![
+                (String::from("/"), 0),
+                (String::from("/cpus"), 1),
+                (String::from("/cpus/cpu@0"), 2),
+                (String::from("/cpus/cpu@1"), 2),
+                (String::from("/soc"), 1),
+                (String::from("/soc/uart@0"), 2),
+            ]
+        );
+
+        let bfs: Vec<String> = tree.iter_breadth_first().map(|(path, _, _)| path).collect();
+        assert_eq!(
+            bfs,
+            vec![
+                "/",
+                "/cpus",
+                "/soc",
+                "/cpus/cpu@0",
+                "/cpus/cpu@1",
+                "/soc/uart@0"
+            ]
+        );
+
+        // Iterate a subtree, the paths are still full paths
+        let soc = tree.find_node_by_path("/soc").unwrap();
+        let sub: Vec<(String, usize)> = tree
+            .iter_breadth_first_from(soc)
+            .map(|(path, depth, _)| (path, depth))
+            .collect();
+        assert_eq!(
+            sub,
+            vec![(String::from("/soc"), 0), (String::from("/soc/uart@0"), 1)]
+        );
+
+        let props: Vec<(String, String)> = tree
+            .iter_properties()
+            .map(|(path, prop)| (path, prop.name.clone()))
+            .collect();
+        assert_eq!(
+            props,
+            vec![
+                (String::from("/"), String::from("#address-cells")),
+                (String::from("/soc/uart@0"), String::from("status")),
+                (String::from("/soc/uart@0"), String::from("reg")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_visit_walk() {
+        let tree = small_tree();
+
+        let mut recorder = Recorder {
+            events: vec![],
+            skip: "cpus",
+            stop: "none",
+        };
+        tree.walk(&mut recorder);
+        assert_eq!(
+            recorder.events,
+            vec![
+                "enter / 0",
+                "enter /cpus 1",
+                "leave /cpus",
+                "enter /soc 1",
+                "enter /soc/uart@0 2",
+                "leave /soc/uart@0",
+                "leave /soc",
+                "leave /",
+            ]
+        );
+
+        let mut recorder = Recorder {
+            events: vec![],
+            skip: "none",
+            stop: "cpu@1",
+        };
+        tree.walk(&mut recorder);
+        assert_eq!(
+            recorder.events,
+            vec![
+                "enter / 0",
+                "enter /cpus 1",
+                "enter /cpus/cpu@0 2",
+                "leave /cpus/cpu@0",
+                "enter /cpus/cpu@1 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_visit_walk_mut() {
+        struct Renamer;
+
+        impl VisitorMut for Renamer {
+            fn enter(&mut self, path: &str, _depth: usize, node: &mut Node) -> VisitAction {
+                if path == "/soc" {
+                    node.name = String::from("soc@0");
+                }
+                node.label = None;
+                VisitAction::Continue
+            }
+        }
+
+        let dts = std::fs::read("test/dts_7.dts").unwrap();
+        let mut tree = DeviceTree::from_dts_bytes(&dts);
+        tree.get_or_create_node("/soc/uart@0").unwrap();
+        tree.walk_mut(&mut Renamer);
+
+        assert!(tree.find_node_by_path("/soc@0/uart@0").is_some());
+        assert!(tree
+            .iter_depth_first()
+            .all(|(_, _, node)| node.label.is_none()));
+    }
+}